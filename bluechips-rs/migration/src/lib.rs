@@ -2,6 +2,19 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_table;
 mod m20240221_044438_auth_session;
+mod m20261019_000001_external_identity;
+mod m20261019_000002_api_token;
+mod m20261019_000003_password_reset;
+mod m20261019_000004_login_throttle;
+mod m20261019_000005_totp;
+mod m20261019_000006_audit_log;
+mod m20261019_000007_webhooks;
+mod m20261019_000008_notification_preferences;
+mod m20261019_000009_digests;
+mod m20261019_000010_notifications;
+mod m20261019_000011_imports;
+mod m20261019_000012_categories;
+mod m20261019_000013_admins;
 
 pub struct Migrator;
 
@@ -11,6 +24,19 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20220101_000001_create_table::Migration),
             Box::new(m20240221_044438_auth_session::Migration),
+            Box::new(m20261019_000001_external_identity::Migration),
+            Box::new(m20261019_000002_api_token::Migration),
+            Box::new(m20261019_000003_password_reset::Migration),
+            Box::new(m20261019_000004_login_throttle::Migration),
+            Box::new(m20261019_000005_totp::Migration),
+            Box::new(m20261019_000006_audit_log::Migration),
+            Box::new(m20261019_000007_webhooks::Migration),
+            Box::new(m20261019_000008_notification_preferences::Migration),
+            Box::new(m20261019_000009_digests::Migration),
+            Box::new(m20261019_000010_notifications::Migration),
+            Box::new(m20261019_000011_imports::Migration),
+            Box::new(m20261019_000012_categories::Migration),
+            Box::new(m20261019_000013_admins::Migration),
         ]
     }
 }
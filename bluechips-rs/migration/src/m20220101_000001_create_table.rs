@@ -9,11 +9,39 @@ pub struct Migration;
 impl MigrationTrait for Migration {
     async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
         let schema = Schema::new(manager.get_database_backend());
+        // The users and expenditures tables are spelled out rather than
+        // taken from their entities, which have gained columns that later
+        // migrations add.
         manager
-            .create_table(schema.create_table_from_entity(user::Entity))
+            .create_table(
+                Table::create()
+                    .table(user::Entity)
+                    .col(ColumnDef::new(user::Column::Id).integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(user::Column::Username).string().not_null())
+                    .col(ColumnDef::new(user::Column::Name).string().null())
+                    .col(ColumnDef::new(user::Column::Resident).boolean().not_null())
+                    .col(ColumnDef::new(user::Column::Email).string().null())
+                    .col(ColumnDef::new(user::Column::Password).string().null())
+                    .to_owned(),
+            )
             .await?;
         manager
-            .create_table(schema.create_table_from_entity(expenditure::Entity))
+            .create_table(
+                Table::create()
+                    .table(expenditure::Entity)
+                    .col(ColumnDef::new(expenditure::Column::Id).integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(expenditure::Column::SpenderId).integer().not_null())
+                    .col(ColumnDef::new(expenditure::Column::Amount).integer().not_null())
+                    .col(ColumnDef::new(expenditure::Column::Description).text().null())
+                    .col(ColumnDef::new(expenditure::Column::Date).date().null())
+                    .col(ColumnDef::new(expenditure::Column::EnteredTime).date_time().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(expenditure::Entity, expenditure::Column::SpenderId)
+                            .to(user::Entity, user::Column::Id),
+                    )
+                    .to_owned(),
+            )
             .await?;
         manager
             .create_table(schema.create_table_from_entity(split::Entity))
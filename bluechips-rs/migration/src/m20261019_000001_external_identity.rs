@@ -0,0 +1,33 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::Schema;
+use bluechips_rs::entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(manager.get_database_backend());
+        manager
+            .create_table(schema.create_table_from_entity(external_identity::Entity))
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-external_identities-provider-subject")
+                    .table(external_identity::Entity)
+                    .col(external_identity::Column::Provider)
+                    .col(external_identity::Column::Subject)
+                    .unique()
+                    .to_owned()
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(external_identity::Entity).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::Schema;
+use bluechips_rs::entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(manager.get_database_backend());
+        manager
+            .create_table(schema.create_table_from_entity(webhook::Entity))
+            .await?;
+        manager
+            .create_table(schema.create_table_from_entity(webhook_delivery::Entity))
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-webhook_deliveries-next_attempt")
+                    .table(webhook_delivery::Entity)
+                    .col(webhook_delivery::Column::NextAttempt)
+                    .to_owned()
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(webhook_delivery::Entity).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(webhook::Entity).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,35 @@
+use sea_orm_migration::prelude::*;
+use bluechips_rs::entities::*;
+use notification_preference::Column;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Spelled out rather than taken from the entity, which has gained
+        // the columns that the digests migration adds.
+        manager
+            .create_table(
+                Table::create()
+                    .table(notification_preference::Entity)
+                    .col(ColumnDef::new(Column::UserId).integer().not_null().primary_key())
+                    .col(ColumnDef::new(Column::EmailChanges).boolean().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(notification_preference::Entity, Column::UserId)
+                            .to(user::Entity, user::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(notification_preference::Entity).to_owned())
+            .await
+    }
+}
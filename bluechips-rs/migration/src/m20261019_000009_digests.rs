@@ -0,0 +1,43 @@
+use sea_orm_migration::prelude::*;
+use bluechips_rs::entities::*;
+use notification_preference::Column;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite can only add one column per statement.
+        for mut column in [
+            ColumnDef::new(Column::Digest).string_len(16).not_null().default("never").to_owned(),
+            ColumnDef::new(Column::LastDigest).timestamp_with_time_zone().null().to_owned(),
+            ColumnDef::new(Column::Remind).boolean().not_null().default(false).to_owned(),
+            ColumnDef::new(Column::LastReminder).timestamp_with_time_zone().null().to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(notification_preference::Entity)
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [Column::LastReminder, Column::Remind, Column::LastDigest, Column::Digest] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(notification_preference::Entity)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::Schema;
+use bluechips_rs::entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(manager.get_database_backend());
+        manager
+            .create_table(schema.create_table_from_entity(notification::Entity))
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-notifications-user_id-read")
+                    .table(notification::Entity)
+                    .col(notification::Column::UserId)
+                    .col(notification::Column::Read)
+                    .to_owned()
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(notification::Entity).to_owned())
+            .await
+    }
+}
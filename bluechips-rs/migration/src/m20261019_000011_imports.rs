@@ -0,0 +1,28 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::Schema;
+use bluechips_rs::entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(manager.get_database_backend());
+        manager
+            .create_table(schema.create_table_from_entity(import_mapping::Entity))
+            .await?;
+        manager
+            .create_table(schema.create_table_from_entity(imported_row::Entity))
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(imported_row::Entity).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(import_mapping::Entity).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,55 @@
+use sea_orm_migration::prelude::*;
+use sea_orm::Schema;
+use bluechips_rs::entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(manager.get_database_backend());
+        manager
+            .create_table(schema.create_table_from_entity(category::Entity))
+            .await?;
+        manager
+            .create_table(schema.create_table_from_entity(expenditure_tag::Entity))
+            .await?;
+        // SQLite can't add a foreign key to an existing table, so deleting a
+        // category clears it from expenditures in `Mutation::delete_category`.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(expenditure::Entity)
+                    .add_column(ColumnDef::new(expenditure::Column::CategoryId).integer().null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-expenditure_tags-tag")
+                    .table(expenditure_tag::Entity)
+                    .col(expenditure_tag::Column::Tag)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(expenditure::Entity)
+                    .drop_column(expenditure::Column::CategoryId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(expenditure_tag::Entity).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(category::Entity).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,31 @@
+use sea_orm_migration::prelude::*;
+use bluechips_rs::entities::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Nobody is an admin until someone runs `make-admin`.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(user::Entity)
+                    .add_column(ColumnDef::new(user::Column::IsAdmin).boolean().not_null().default(false))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(user::Entity)
+                    .drop_column(user::Column::IsAdmin)
+                    .to_owned(),
+            )
+            .await
+    }
+}
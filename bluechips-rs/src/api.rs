@@ -0,0 +1,757 @@
+//! Versioned JSON API, mounted at `/api/v1`.
+//!
+//! Clients authenticate with a personal API token in an
+//! `Authorization: Bearer` header (or a session cookie). Amounts are
+//! decimal strings like `"12.50"` and dates are ISO 8601. Errors come back
+//! as `{"error": "...", "message": "..."}` with a matching status code.
+//!
+//! The OpenAPI description is generated from the routes below and served at
+//! `/api/v1/openapi.json`, with a viewer at `/api/v1/docs/`.
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, status::Created, Redirect, Responder};
+use rocket::serde::json::Json;
+use rocket::serde::Serialize;
+use rocket::either::Either;
+use rocket::{Catcher, Route, State};
+use sea_orm::{DatabaseConnection, DbErr, TransactionError};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi, ToSchema};
+
+use crate::{auth, mail, notify};
+use crate::export::Filter;
+use crate::entities::{audit_log::Action, expenditure, split, transfer, user, prelude::Currency};
+use crate::service::{Query, Mutation, HistoryCursor, CategoryPath, CategoryTotal, category_paths, ExpenditureDisplay, TransferDisplay, SettleError, Totals, PeriodTotal, Periods, Span, DateField, ExpenditureForm, TransferForm};
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiError {
+    #[serde(skip)]
+    status: Status,
+    /// A short machine-readable code, such as `not_found`.
+    error: String,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: Status, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            error: status.reason_lossy().to_lowercase().replace(' ', "_"),
+            message: message.into(),
+        }
+    }
+
+    fn not_found(what: &str) -> Self {
+        Self::new(Status::NotFound, format!("{} not found", what))
+    }
+
+    fn invalid(message: impl Into<String>) -> Self {
+        Self::new(Status::UnprocessableEntity, message)
+    }
+}
+
+impl From<DbErr> for ApiError {
+    fn from(e: DbErr) -> Self {
+        error!("API database error: {:?}", e);
+        Self::new(Status::InternalServerError, "database error")
+    }
+}
+
+impl From<TransactionError<DbErr>> for ApiError {
+    fn from(e: TransactionError<DbErr>) -> Self {
+        match e {
+            TransactionError::Connection(e) => e.into(),
+            TransactionError::Transaction(e) => e.into(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        (self.status, Json(self)).respond_to(req)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ApiError>;
+
+#[derive(Serialize, ToSchema)]
+pub struct Expenditure {
+    pub id: i32,
+    pub spender_id: i32,
+    pub amount: Currency,
+    pub description: Option<String>,
+    pub date: Option<NaiveDate>,
+    pub entered_time: Option<NaiveDateTime>,
+    /// Each user's share of `amount`, keyed by user ID.
+    pub splits: HashMap<i32, Currency>,
+    pub category_id: Option<i32>,
+    pub tags: Vec<String>,
+}
+
+impl From<(expenditure::Model, Vec<split::Model>)> for Expenditure {
+    fn from((expenditure, splits): (expenditure::Model, Vec<split::Model>)) -> Self {
+        Self {
+            id: expenditure.id,
+            spender_id: expenditure.spender_id,
+            amount: expenditure.amount,
+            description: expenditure.description,
+            date: expenditure.date,
+            entered_time: expenditure.entered_time,
+            splits: splits.into_iter().map(|s| (s.user_id, s.share)).collect(),
+            category_id: expenditure.category_id,
+            tags: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Transfer {
+    pub id: i32,
+    pub debtor_id: i32,
+    pub creditor_id: i32,
+    pub amount: Currency,
+    pub description: Option<String>,
+    pub date: Option<NaiveDate>,
+    pub entered_time: Option<NaiveDateTime>,
+}
+
+impl From<transfer::Model> for Transfer {
+    fn from(transfer: transfer::Model) -> Self {
+        Self {
+            id: transfer.id,
+            debtor_id: transfer.debtor_id,
+            creditor_id: transfer.creditor_id,
+            amount: transfer.amount,
+            description: transfer.description,
+            date: transfer.date,
+            entered_time: transfer.entered_time,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    pub name: Option<String>,
+    pub resident: bool,
+}
+
+impl From<user::Model> for User {
+    fn from(user: user::Model) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            name: user.name,
+            resident: user.resident,
+        }
+    }
+}
+
+/// Positive amounts are owed by the user, negative amounts are owed to them.
+#[derive(Serialize, ToSchema)]
+pub struct Debt {
+    pub user_id: i32,
+    pub amount: Currency,
+}
+
+/// One payment that would help settle all debts.
+#[derive(Serialize, ToSchema)]
+pub struct Settlement {
+    pub from_user_id: i32,
+    pub to_user_id: i32,
+    pub amount: Currency,
+}
+
+/// The web form checks the same things through `FromForm` validation.
+async fn validate_expenditure(db: &DatabaseConnection, form: &ExpenditureForm) -> Result<()> {
+    if form.splits.values().sum::<Currency>().is_zero() {
+        return Err(ApiError::invalid("splits cannot sum to zero"));
+    }
+    let users: Vec<i32> = Query::find_users(db).await?.into_iter().map(|u| u.id).collect();
+    if !users.contains(&form.spender_id) {
+        return Err(ApiError::invalid("spender not found"));
+    }
+    if let Some(id) = form.splits.keys().find(|id| !users.contains(id)) {
+        return Err(ApiError::invalid(format!("user {} in splits not found", id)));
+    }
+    Ok(())
+}
+
+async fn validate_transfer(db: &DatabaseConnection, form: &TransferForm) -> Result<()> {
+    for (id, what) in [(form.debtor_id, "debtor"), (form.creditor_id, "creditor")] {
+        if Query::get_user_by_id(db, id).await?.is_none() {
+            return Err(ApiError::invalid(format!("{} not found", what)));
+        }
+    }
+    Ok(())
+}
+
+async fn get_expenditure(db: &DatabaseConnection, id: i32) -> Result<Expenditure> {
+    let mut expenditure = Query::get_expenditure_with_splits(db, id)
+        .await?
+        .map(Expenditure::from)
+        .ok_or(ApiError::not_found("expenditure"))?;
+    expenditure.tags = Query::get_expenditure_tags(db, id).await?;
+    Ok(expenditure)
+}
+
+/// `mine`, `involved` and `share_amount` are relative to the caller.
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "expenditures",
+    params(
+        crate::export::Filter,
+        ("after" = Option<String>, Query, description = "Only expenditures older than this one, given as `date_id` like `2026-10-19_123`"),
+        ("limit" = Option<u64>, Query, description = "At most this many expenditures"),
+    ),
+    responses((status = 200, description = "Matching expenditures, newest first", body = [ExpenditureDisplay])),
+    security(("bearer" = [])),
+)]
+#[get("/expenditures?<after>&<limit>&<filter..>")]
+async fn expenditures_list(
+    after: Option<HistoryCursor>,
+    limit: Option<u64>,
+    filter: Option<Filter>,
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+) -> Result<Json<Vec<ExpenditureDisplay>>> {
+    let filter = filter.unwrap_or_default().history(user.id);
+    Ok(Json(Query::find_expenditures(db, user.id, &filter, after, limit).await?))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "expenditures",
+    responses(
+        (status = 200, body = Expenditure),
+        (status = 404, body = ApiError),
+    ),
+    security(("bearer" = [])),
+)]
+#[get("/expenditures/<id>")]
+async fn expenditures_get(id: i32, db: &State<DatabaseConnection>, _user: auth::User) -> Result<Json<Expenditure>> {
+    Ok(Json(get_expenditure(db, id).await?))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "expenditures",
+    request_body = ExpenditureForm,
+    responses(
+        (status = 201, body = Expenditure),
+        (status = 422, body = ApiError),
+    ),
+    security(("bearer" = [])),
+)]
+#[post("/expenditures", data = "<form>")]
+async fn expenditures_create(
+    db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
+    user: auth::User,
+    form: Json<ExpenditureForm>,
+) -> Result<Created<Json<Expenditure>>> {
+    let db = db as &DatabaseConnection;
+    validate_expenditure(db, &form).await?;
+    let expenditure = Mutation::save_expenditure(db, Some(user.id), None, form.into_inner()).await?;
+    notify_expenditure(db, mailer, mail_config, Action::Create, None, expenditure.id).await?;
+    Ok(Created::new(uri!("/api/v1", expenditures_get(expenditure.id)).to_string())
+        .body(Json(get_expenditure(db, expenditure.id).await?)))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "expenditures",
+    request_body = ExpenditureForm,
+    responses(
+        (status = 200, body = Expenditure),
+        (status = 404, body = ApiError),
+        (status = 422, body = ApiError),
+    ),
+    security(("bearer" = [])),
+)]
+#[put("/expenditures/<id>", data = "<form>")]
+async fn expenditures_update(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
+    user: auth::User,
+    form: Json<ExpenditureForm>,
+) -> Result<Json<Expenditure>> {
+    let db = db as &DatabaseConnection;
+    get_expenditure(db, id).await?;
+    validate_expenditure(db, &form).await?;
+    let old = notify::ExpenditureSummary::load(db, id).await?;
+    Mutation::save_expenditure(db, Some(user.id), Some(id), form.into_inner()).await?;
+    notify_expenditure(db, mailer, mail_config, Action::Update, old, id).await?;
+    Ok(Json(get_expenditure(db, id).await?))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "expenditures",
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, body = ApiError),
+    ),
+    security(("bearer" = [])),
+)]
+#[delete("/expenditures/<id>")]
+async fn expenditures_delete(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
+    user: auth::User,
+) -> Result<Status> {
+    let db = db as &DatabaseConnection;
+    let old = notify::ExpenditureSummary::load(db, id).await?;
+    match Mutation::delete_expenditure(db, Some(user.id), id).await?.rows_affected {
+        0 => Err(ApiError::not_found("expenditure")),
+        _ => {
+            notify_expenditure(db, mailer, mail_config, Action::Delete, old, id).await?;
+            Ok(Status::NoContent)
+        }
+    }
+}
+
+/// Mail the people involved in a change, once it has been saved.
+async fn notify_expenditure(
+    db: &DatabaseConnection,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &mail::Config,
+    action: Action,
+    old: Option<notify::ExpenditureSummary>,
+    id: i32,
+) -> Result<()> {
+    let new = match action {
+        Action::Delete => None,
+        _ => notify::ExpenditureSummary::load(db, id).await?,
+    };
+    let Some(message) = new.as_ref().or(old.as_ref()).map(|e| e.message(action)) else {
+        return Ok(());
+    };
+    notify::expenditure(db, mailer.map(|m| m.inner()), mail_config, action, &message, old.as_ref(), new.as_ref()).await;
+    Ok(())
+}
+
+/// `involved` is relative to the caller.
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "transfers",
+    params(
+        crate::export::Filter,
+        ("after" = Option<String>, Query, description = "Only transfers older than this one, given as `date_id` like `2026-10-19_123`"),
+        ("limit" = Option<u64>, Query, description = "At most this many transfers"),
+    ),
+    responses((status = 200, description = "Matching transfers, newest first", body = [TransferDisplay])),
+    security(("bearer" = [])),
+)]
+#[get("/transfers?<after>&<limit>&<filter..>")]
+async fn transfers_list(
+    after: Option<HistoryCursor>,
+    limit: Option<u64>,
+    filter: Option<Filter>,
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+) -> Result<Json<Vec<TransferDisplay>>> {
+    let filter = filter.unwrap_or_default().history(user.id);
+    Ok(Json(Query::find_transfers(db, user.id, &filter, after, limit).await?))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "transfers",
+    responses(
+        (status = 200, body = Transfer),
+        (status = 404, body = ApiError),
+    ),
+    security(("bearer" = [])),
+)]
+#[get("/transfers/<id>")]
+async fn transfers_get(id: i32, db: &State<DatabaseConnection>, _user: auth::User) -> Result<Json<Transfer>> {
+    Query::get_transfer(db, id)
+        .await?
+        .map(|t| Json(t.into()))
+        .ok_or(ApiError::not_found("transfer"))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "transfers",
+    request_body = TransferForm,
+    responses(
+        (status = 201, body = Transfer),
+        (status = 422, body = ApiError),
+    ),
+    security(("bearer" = [])),
+)]
+#[post("/transfers", data = "<form>")]
+async fn transfers_create(
+    db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
+    user: auth::User,
+    form: Json<TransferForm>,
+) -> Result<Created<Json<Transfer>>> {
+    let db = db as &DatabaseConnection;
+    validate_transfer(db, &form).await?;
+    let transfer = Mutation::save_transfer(db, Some(user.id), None, form.into_inner()).await?;
+    notify_transfer(db, mailer, mail_config, Action::Create, None, transfer.id).await?;
+    Ok(Created::new(uri!("/api/v1", transfers_get(transfer.id)).to_string())
+        .body(Json(transfer.into())))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "transfers",
+    request_body = TransferForm,
+    responses(
+        (status = 200, body = Transfer),
+        (status = 404, body = ApiError),
+        (status = 422, body = ApiError),
+    ),
+    security(("bearer" = [])),
+)]
+#[put("/transfers/<id>", data = "<form>")]
+async fn transfers_update(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
+    user: auth::User,
+    form: Json<TransferForm>,
+) -> Result<Json<Transfer>> {
+    let db = db as &DatabaseConnection;
+    Query::get_transfer(db, id).await?.ok_or(ApiError::not_found("transfer"))?;
+    validate_transfer(db, &form).await?;
+    let old = notify::TransferSummary::load(db, id).await?;
+    let transfer = Mutation::save_transfer(db, Some(user.id), Some(id), form.into_inner()).await?;
+    notify_transfer(db, mailer, mail_config, Action::Update, old, id).await?;
+    Ok(Json(transfer.into()))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "transfers",
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, body = ApiError),
+    ),
+    security(("bearer" = [])),
+)]
+#[delete("/transfers/<id>")]
+async fn transfers_delete(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
+    user: auth::User,
+) -> Result<Status> {
+    let db = db as &DatabaseConnection;
+    let old = notify::TransferSummary::load(db, id).await?;
+    match Mutation::delete_transfer(db, Some(user.id), id).await?.rows_affected {
+        0 => Err(ApiError::not_found("transfer")),
+        _ => {
+            notify_transfer(db, mailer, mail_config, Action::Delete, old, id).await?;
+            Ok(Status::NoContent)
+        }
+    }
+}
+
+async fn notify_transfer(
+    db: &DatabaseConnection,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &mail::Config,
+    action: Action,
+    old: Option<notify::TransferSummary>,
+    id: i32,
+) -> Result<()> {
+    let new = match action {
+        Action::Delete => None,
+        _ => notify::TransferSummary::load(db, id).await?,
+    };
+    let Some(message) = new.as_ref().or(old.as_ref()).map(|t| t.message(action)) else {
+        return Ok(());
+    };
+    notify::transfer(db, mailer.map(|m| m.inner()), mail_config, action, &message, old.as_ref(), new.as_ref()).await;
+    Ok(())
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "users",
+    responses((status = 200, body = [User])),
+    security(("bearer" = [])),
+)]
+#[get("/users")]
+async fn users_list(db: &State<DatabaseConnection>, _user: auth::User) -> Result<Json<Vec<User>>> {
+    Ok(Json(Query::find_users(db).await?.into_iter().map(User::from).collect()))
+}
+
+/// Each category comes right before the ones under it.
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "categories",
+    responses((status = 200, body = [CategoryPath])),
+    security(("bearer" = [])),
+)]
+#[get("/categories")]
+async fn categories_list(db: &State<DatabaseConnection>, _user: auth::User) -> Result<Json<Vec<CategoryPath>>> {
+    Ok(Json(category_paths(&Query::find_categories(db).await?)))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "balances",
+    responses((status = 200, body = [Debt])),
+    security(("bearer" = [])),
+)]
+#[get("/debts")]
+async fn debts(db: &State<DatabaseConnection>, _user: auth::User) -> Result<Json<Vec<Debt>>> {
+    let mut debts: Vec<Debt> = Query::get_debts(db.inner())
+        .await?
+        .into_iter()
+        .map(|(user_id, amount)| Debt { user_id, amount })
+        .collect();
+    debts.sort_by_key(|d| d.user_id);
+    Ok(Json(debts))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "balances",
+    responses(
+        (status = 200, description = "Payments that would settle all debts", body = [Settlement]),
+        (status = 409, body = ApiError),
+    ),
+    security(("bearer" = [])),
+)]
+#[get("/settle")]
+async fn settle(db: &State<DatabaseConnection>, _user: auth::User) -> Result<Json<Vec<Settlement>>> {
+    let settle = Query::settle(Query::get_debts(db.inner()).await?).map_err(|e| ApiError::new(
+        Status::Conflict,
+        match e {
+            SettleError::CollectiveDebt(_) => "debts don't balance: the group owes money overall",
+            SettleError::CollectiveCredit(_) => "debts don't balance: the group is owed money overall",
+        },
+    ))?;
+    Ok(Json(settle
+        .into_iter()
+        .map(|(from_user_id, to_user_id, amount)| Settlement { from_user_id, to_user_id, amount })
+        .collect()))
+}
+
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "balances",
+    responses((status = 200, body = Totals)),
+    security(("bearer" = [])),
+)]
+#[get("/totals")]
+async fn totals(db: &State<DatabaseConnection>, periods: &State<Periods>, user: auth::User) -> Result<Json<Totals>> {
+    Ok(Json(Query::get_totals(db, user.id, periods).await?))
+}
+
+/// Either end may be left out to leave the range open.
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "balances",
+    params(
+        ("from" = Option<String>, Query, description = "On or after this date, as YYYY-MM-DD"),
+        ("to" = Option<String>, Query, description = "On or before this date"),
+    ),
+    responses((status = 200, body = PeriodTotal)),
+    security(("bearer" = [])),
+)]
+#[get("/totals/range?<from>&<to>")]
+async fn totals_range(db: &State<DatabaseConnection>, user: auth::User, from: Option<DateField>, to: Option<DateField>) -> Result<Json<PeriodTotal>> {
+    let span = Span::new(from.map(|d| d.0), to.map(|d| d.0));
+    Ok(Json(Query::get_totals_for_span(db, user.id, span.to_string(), span).await?))
+}
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "bearer",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "BlueChips",
+        description = "Shared expenses for a household. Create a token under User settings and send it as a bearer token.",
+    ),
+    paths(
+        expenditures_list,
+        expenditures_get,
+        expenditures_create,
+        expenditures_update,
+        expenditures_delete,
+        transfers_list,
+        transfers_get,
+        transfers_create,
+        transfers_update,
+        transfers_delete,
+        users_list,
+        categories_list,
+        debts,
+        settle,
+        totals,
+        totals_range,
+    ),
+    components(schemas(
+        Currency,
+        ExpenditureForm,
+        TransferForm,
+        ExpenditureDisplay,
+        TransferDisplay,
+        Totals,
+        PeriodTotal,
+        Span,
+        CategoryPath,
+        CategoryTotal,
+        Expenditure,
+        Transfer,
+        User,
+        Debt,
+        Settlement,
+        ApiError,
+    )),
+    modifiers(&BearerAuth),
+)]
+pub struct ApiDoc;
+
+#[get("/openapi.json")]
+fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Swagger UI, served from files compiled into the binary so it works
+/// without internet access.
+#[get("/docs/<path..>")]
+fn docs(path: PathBuf) -> std::result::Result<(ContentType, Vec<u8>), Either<Redirect, ApiError>> {
+    if path.as_os_str().is_empty() {
+        // The viewer loads its assets with relative URLs.
+        return Err(Either::Left(Redirect::to(uri!("/api/v1", docs("index.html")))));
+    }
+    let config = Arc::new(utoipa_swagger_ui::Config::from(uri!("/api/v1", openapi()).to_string()));
+    match utoipa_swagger_ui::serve(&path.to_string_lossy(), config) {
+        Ok(Some(file)) => Ok((
+            ContentType::parse_flexible(&file.content_type).unwrap_or(ContentType::Binary),
+            file.bytes.into_owned(),
+        )),
+        Ok(None) => Err(Either::Right(ApiError::not_found("file"))),
+        Err(e) => Err(Either::Right(ApiError::new(Status::InternalServerError, e.to_string()))),
+    }
+}
+
+#[catch(default)]
+fn default_catcher(status: Status, req: &Request) -> ApiError {
+    match status.code {
+        401 => ApiError::new(status, "a valid API token or session is required"),
+        403 => ApiError::new(status, req
+            .local_cache(|| auth::Rejection(None))
+            .0
+            .clone()
+            .unwrap_or_else(|| "you aren't allowed to do that".to_string())),
+        _ => ApiError::new(status, status.reason_lossy()),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        expenditures_list,
+        expenditures_get,
+        expenditures_create,
+        expenditures_update,
+        expenditures_delete,
+        transfers_list,
+        transfers_get,
+        transfers_create,
+        transfers_update,
+        transfers_delete,
+        users_list,
+        categories_list,
+        debts,
+        settle,
+        totals,
+        totals_range,
+        openapi,
+        docs,
+    ]
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    catchers![default_catcher]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// Every API route must be described in the spec, and vice versa.
+    #[test]
+    fn spec_matches_routes() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let documented: BTreeSet<String> = spec["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| item
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(move |method| format!("{} {}", method.to_uppercase(), path)))
+            .collect();
+        let routed: BTreeSet<String> = routes()
+            .into_iter()
+            .filter(|r| !matches!(r.name.as_deref(), Some("openapi" | "docs")))
+            .map(|r| {
+                let path = r.uri.path().replace('<', "{").replace('>', "}");
+                format!("{} /api/v1{}", r.method.map_or("ANY".into(), |m| m.to_string()), path)
+            })
+            .collect();
+        assert_eq!(documented, routed);
+    }
+
+    /// Every schema the spec refers to must be registered as a component.
+    #[test]
+    fn spec_refs_resolve() {
+        fn refs(value: &serde_json::Value, out: &mut Vec<String>) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    if let Some(serde_json::Value::String(r)) = map.get("$ref") {
+                        out.push(r.clone());
+                    }
+                    map.values().for_each(|v| refs(v, out));
+                }
+                serde_json::Value::Array(items) => items.iter().for_each(|v| refs(v, out)),
+                _ => {}
+            }
+        }
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let mut found = vec![];
+        refs(&spec, &mut found);
+        assert!(!found.is_empty());
+        for r in found {
+            let name = r.strip_prefix("#/components/schemas/").unwrap();
+            assert!(spec["components"]["schemas"].get(name).is_some(), "missing schema {}", name);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Personal API tokens, for scripts and integrations that can't hold a
+//! session cookie.
+//!
+//! Only a SHA-256 of each token is stored. Tokens are long and random, so a
+//! slow password hash would buy nothing and would make lookup impossible.
+use rand::{distributions::Alphanumeric, Rng};
+use rocket::http::Method;
+use sea_orm::DatabaseConnection;
+use sha2::{Digest, Sha256};
+
+use crate::service::{Mutation, Query};
+use super::{Error, Result, User};
+
+/// Makes tokens easy to recognize if they end up somewhere they shouldn't.
+const PREFIX: &str = "bc_";
+
+/// `last_used` is only for showing roughly when a token was last used, so
+/// it isn't written on every request.
+const TOUCH_INTERVAL: chrono::Duration = chrono::Duration::minutes(1);
+
+pub fn generate() -> String {
+    let secret: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+    format!("{}{}", PREFIX, secret)
+}
+
+pub fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Resolve a bearer token to its user, enforcing expiry and scope.
+pub async fn authenticate(db: &DatabaseConnection, token: &str, method: Method) -> Result<User> {
+    let token = Query::find_api_token_by_hash(db, &hash(token)).await?
+        .ok_or(Error::UnauthorizedError)?;
+    if token.expires.map_or(false, |expires| expires < chrono::Utc::now()) {
+        return Err(Error::UnauthorizedError);
+    }
+    if token.read_only && !matches!(method, Method::Get | Method::Head) {
+        return Err(Error::ReadOnlyTokenError);
+    }
+    if needs_touch(token.last_used, chrono::Utc::now()) {
+        Mutation::touch_api_token(db, token.id).await?;
+    }
+    Query::get_user_by_id(db, token.user_id).await?.ok_or(Error::UserNotFoundError)
+}
+
+fn needs_touch(last_used: Option<chrono::DateTime<chrono::Utc>>, now: chrono::DateTime<chrono::Utc>) -> bool {
+    last_used.map_or(true, |last_used| now - last_used > TOUCH_INTERVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{test_db, ApiTokenForm};
+    use sea_orm::{ActiveModelTrait, EntityTrait};
+    use sea_orm::ActiveValue::{Set, Unchanged};
+
+    #[test]
+    fn tokens_are_prefixed_and_random() {
+        let token = generate();
+        assert!(token.starts_with(PREFIX));
+        assert_eq!(token.len(), PREFIX.len() + 40);
+        assert!(token[PREFIX.len()..].chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(token, generate());
+    }
+
+    #[test]
+    fn hashes_are_sha256_hex() {
+        assert_eq!(hash("bc_abc"), hash("bc_abc"));
+        assert_ne!(hash("bc_abc"), hash("bc_abd"));
+        assert_eq!(hash(""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+
+    #[test]
+    fn last_used_is_touched_at_most_once_a_minute() {
+        let now = chrono::Utc::now();
+        assert!(needs_touch(None, now));
+        assert!(!needs_touch(Some(now - chrono::Duration::seconds(30)), now));
+        assert!(needs_touch(Some(now - chrono::Duration::seconds(90)), now));
+    }
+
+    #[rocket::async_test]
+    async fn bearer_tokens_authenticate_within_their_scope() {
+        let db = test_db().await;
+        let user = crate::entities::user::ActiveModel {
+            username: Set("alice".to_string()),
+            resident: Set(true),
+            ..Default::default()
+        }
+            .insert(&db)
+            .await
+            .unwrap();
+        let create = |read_only, expires_in_days| {
+            let token = generate();
+            let form = ApiTokenForm { name: "script".to_string(), read_only, expires_in_days };
+            let db = &db;
+            async move {
+                let model = Mutation::create_api_token(db, user.id, hash(&token), form).await.unwrap();
+                (token, model)
+            }
+        };
+
+        let (token, model) = create(false, None).await;
+        assert_eq!(authenticate(&db, &token, Method::Post).await.unwrap().id, user.id);
+        let last_used = crate::entities::api_token::Entity::find_by_id(model.id).one(&db).await.unwrap().unwrap().last_used;
+        assert!(last_used.is_some());
+        authenticate(&db, &token, Method::Get).await.unwrap();
+        let again = crate::entities::api_token::Entity::find_by_id(model.id).one(&db).await.unwrap().unwrap().last_used;
+        assert_eq!(again, last_used);
+
+        assert!(matches!(authenticate(&db, "bc_unknown", Method::Get).await, Err(Error::UnauthorizedError)));
+
+        let (read_only, _) = create(true, None).await;
+        assert!(authenticate(&db, &read_only, Method::Get).await.is_ok());
+        assert!(matches!(authenticate(&db, &read_only, Method::Post).await, Err(Error::ReadOnlyTokenError)));
+
+        let (expired, expired_model) = create(false, Some(1)).await;
+        crate::entities::api_token::ActiveModel {
+            id: Unchanged(expired_model.id),
+            expires: Set(Some(chrono::Utc::now() - chrono::Duration::hours(1))),
+            ..Default::default()
+        }
+            .update(&db)
+            .await
+            .unwrap();
+        assert!(matches!(authenticate(&db, &expired, Method::Get).await, Err(Error::UnauthorizedError)));
+    }
+}
@@ -1,16 +1,22 @@
-use rocket::http::{Cookie, CookieJar, Status, HeaderMap};
+use rocket::http::{Cookie, CookieJar, Status, HeaderMap, SameSite};
 use rocket::State;
 use rocket::request::{FromRequest, Outcome, Request};
 use rocket::serde::{Serialize, Deserialize};
 use rocket::outcome::try_outcome;
 use sea_orm::DatabaseConnection;
 use serde_json::{json, from_str};
-use password_auth::{verify_password, VerifyError};
+use password_auth::{generate_hash, VerifyError};
+use std::net::IpAddr;
 use std::time::SystemTime;
 
 mod session;
 pub use session::SessionManager;
 mod db_session;
+pub mod oidc;
+pub mod api_token;
+pub mod password;
+mod throttle;
+pub mod totp;
 
 use rand::random;
 pub fn rand_string(size: usize) -> String {
@@ -54,8 +60,11 @@ pub enum Error {
     #[error("That email address already exists. Try logging in.")]
     EmailAlreadyExists,
     /// This error occurs when the user does exists, but their password was incorrect.
-    #[error("Incorrect email or password")]
+    #[error("Incorrect username or password.")]
     UnauthorizedError,
+    /// Too many failed logins; the value is how many seconds to wait.
+    #[error("Too many failed logins. Try again in {0} seconds.")]
+    ThrottledError(i64),
     #[error("Incorrect password: {0}")]
     VerifyError(#[from] VerifyError),
 
@@ -76,6 +85,25 @@ pub enum Error {
     /// A wrapper around [`serde_json::Error`].
     #[error("SerdeError: {0}")]
     SerdeError(#[from] serde_json::Error),
+
+    /// A new password didn't pass [`password::check_strength`].
+    #[error("{0}")]
+    WeakPasswordError(&'static str),
+
+    /// A read-only API token was used for a request that changes something.
+    #[error("This API token is read-only.")]
+    ReadOnlyTokenError,
+
+    /// The second-factor code was wrong or had already been used.
+    #[error("Incorrect code.")]
+    InvalidCodeError,
+
+    #[error("TOTP error: {0}")]
+    TotpError(String),
+
+    /// Anything that goes wrong while talking to the OpenID Connect provider.
+    #[error("OIDC login failed: {0}")]
+    OidcError(String),
 }
 
 impl From<&Error> for Status {
@@ -85,6 +113,9 @@ impl From<&Error> for Status {
             Error::UnauthenticatedError => Status::Unauthorized,
             Error::UserNotFoundError => Status::Unauthorized,
             Error::VerifyError(VerifyError::PasswordInvalid) => Status::Unauthorized,
+            Error::ReadOnlyTokenError => Status::Forbidden,
+            Error::ThrottledError(_) => Status::TooManyRequests,
+            Error::InvalidCodeError => Status::Unauthorized,
             _ => Status::InternalServerError,
         }
     }
@@ -132,11 +163,39 @@ fn get_session(cookies: &CookieJar) -> Option<Session> {
     from_str(session.value()).ok()
 }
 
+/// A user who has passed the password check but not the second factor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingLogin {
+    id: i32,
+    time_stamp: i64,
+}
+
+const PENDING_LOGIN_COOKIE: &str = "rocket_auth_pending";
+/// How long someone has to enter their second factor, in seconds.
+const PENDING_LOGIN_TTL: i64 = 5 * 60;
+
+pub enum LoginOutcome {
+    LoggedIn,
+    SecondFactorRequired,
+}
+
 #[derive(Deserialize)]
 #[serde(default)]
 pub struct Config {
     authentik_use_headers: bool,
     authentik_residents_group: String,
+    /// Failed logins allowed before backoff starts.
+    login_free_attempts: i32,
+    /// Failed logins for one account before it is locked.
+    login_lockout_after: i32,
+    /// Failed logins from one address before it is locked. Higher than the
+    /// per-account limit since households often share an address.
+    login_ip_lockout_after: i32,
+    login_lockout_minutes: i64,
+    /// Reverse proxies whose `X-Real-IP` header is believed. From anywhere
+    /// else the header is ignored, since clients can send whatever they
+    /// like in it to get around the per-address lockout.
+    trusted_proxies: Vec<IpAddr>,
 }
 
 impl Default for Config {
@@ -144,6 +203,11 @@ impl Default for Config {
         Self {
             authentik_use_headers: false,
             authentik_residents_group: "Residents".to_string(),
+            login_free_attempts: 3,
+            login_lockout_after: 10,
+            login_ip_lockout_after: 50,
+            login_lockout_minutes: 15,
+            trusted_proxies: Vec::new(),
         }
     }
 }
@@ -155,6 +219,7 @@ pub struct Auth<'a> {
     pub config: &'a Config,
     pub cookies: &'a CookieJar<'a>,
     pub headers: &'a HeaderMap<'a>,
+    pub client_ip: Option<IpAddr>,
     pub session: Option<Session>,
 }
 
@@ -165,20 +230,122 @@ pub struct Login {
 }
 
 impl<'a> Auth<'a> {
-    pub async fn login(&self, form: &Login, db: &DatabaseConnection) -> Result<()> {
-        let form_pwd = &form.password.as_bytes();
+    /// Log in with a username and password.
+    ///
+    /// Every failure is reported as [`Error::UnauthorizedError`], whatever
+    /// the reason, and counts towards the login throttle.
+    pub async fn login(&self, form: &Login, db: &DatabaseConnection) -> Result<LoginOutcome> {
+        let account = format!("user:{}", form.username.to_lowercase());
+        let address = self.client_ip.map(|ip| format!("ip:{}", ip));
+        throttle::check(db, self.config, &account).await?;
+        if let Some(address) = &address {
+            throttle::check(db, self.config, address).await?;
+        }
+        let user = match self.check_password(form, db).await {
+            Ok(user) => user,
+            Err(e) => {
+                warn!("Failed login for {:?} from {}: {}", form.username, address.as_deref().unwrap_or("unknown address"), e);
+                throttle::record_failure(db, self.config, &account, self.config.login_lockout_after).await?;
+                if let Some(address) = &address {
+                    throttle::record_failure(db, self.config, address, self.config.login_ip_lockout_after).await?;
+                }
+                return Err(Error::UnauthorizedError);
+            }
+        };
+        throttle::record_success(db, &account).await?;
+        if Query::get_user_totp(db, user.id).await?.is_some() {
+            let pending = PendingLogin {
+                id: user.id,
+                time_stamp: now(),
+            };
+            self.cookies.add_private(Cookie::build((PENDING_LOGIN_COOKIE, json!(pending).to_string())).same_site(SameSite::Lax));
+            return Ok(LoginOutcome::SecondFactorRequired);
+        }
+        self.start_session(&user).await?;
+        Ok(LoginOutcome::LoggedIn)
+    }
+
+    /// Whether there's a password login waiting on its second factor.
+    pub fn second_factor_pending(&self) -> bool {
+        self.pending_login().is_some()
+    }
+
+    fn pending_login(&self) -> Option<PendingLogin> {
+        let pending: PendingLogin = from_str(self.cookies.get_private(PENDING_LOGIN_COOKIE)?.value()).ok()?;
+        Some(pending).filter(|p| now() - p.time_stamp < PENDING_LOGIN_TTL)
+    }
+
+    /// Finish a login that [`Auth::login`] left waiting on a second factor.
+    pub async fn login_second_factor(&self, code: &str, db: &DatabaseConnection) -> Result<()> {
+        let pending = self.pending_login().ok_or(Error::UnauthenticatedError)?;
+        let target = format!("totp:{}", pending.id);
+        throttle::check(db, self.config, &target).await?;
+        if !totp::verify(db, pending.id, code).await? {
+            warn!("Failed second factor for user {}", pending.id);
+            throttle::record_failure(db, self.config, &target, self.config.login_lockout_after).await?;
+            return Err(Error::InvalidCodeError);
+        }
+        throttle::record_success(db, &target).await?;
+        self.cookies.remove_private(PENDING_LOGIN_COOKIE);
+        let user = Query::get_user_by_id(db, pending.id).await?.ok_or(Error::UserNotFoundError)?;
+        self.start_session(&user).await
+    }
+
+    /// Count a request to mail a reset link for `username`, refusing it if
+    /// that account or this address has asked too often. Every request
+    /// counts, since any of them can send mail, whether or not it does.
+    pub async fn throttle_reset(&self, db: &DatabaseConnection, username: &str) -> Result<()> {
+        let account = format!("reset:user:{}", username.to_lowercase());
+        let address = self.client_ip.map(|ip| format!("reset:ip:{}", ip));
+        throttle::check(db, self.config, &account).await?;
+        if let Some(address) = &address {
+            throttle::check(db, self.config, address).await?;
+        }
+        throttle::record_failure(db, self.config, &account, self.config.login_lockout_after).await?;
+        if let Some(address) = &address {
+            throttle::record_failure(db, self.config, address, self.config.login_ip_lockout_after).await?;
+        }
+        Ok(())
+    }
+
+    async fn check_password(&self, form: &Login, db: &DatabaseConnection) -> Result<User> {
         let user = Query::find_user_by_username(db, &form.username).await?.ok_or(Error::UserNotFoundError)?;
-        let user_pwd = &user.password.ok_or(Error::UnauthorizedError)?;
-        verify_password(form_pwd, user_pwd)?;
+        let user_pwd = user.password.as_ref().ok_or(Error::UnauthorizedError)?;
+        if password::verify(&form.password, user_pwd)? {
+            Mutation::set_password(db, user.id, generate_hash(&form.password)).await?;
+        }
+        Ok(user)
+    }
+
+    /// Log in through the OpenID Connect provider.
+    ///
+    /// Someone who is already logged in connects the provider's account to
+    /// theirs instead, so they can use either from then on.
+    pub async fn login_oidc(&self, provider: &oidc::Provider, config: &oidc::Config, code: &str, state: &str, db: &DatabaseConnection) -> Result<()> {
+        let (subject, user) = provider.exchange(config, self.cookies, code, state).await?;
+        if let Some(current) = self.get_user(db).await {
+            Mutation::connect_identity(db, &provider.issuer, &subject, current.id).await?;
+            return Ok(());
+        }
+        let user = Mutation::ensure_user(db, &provider.issuer, &subject, user).await
+            .map_err(|e| match e {
+                sea_orm::TransactionError::Connection(e) => e,
+                sea_orm::TransactionError::Transaction(e) => e,
+            })?;
+        self.start_session(&user).await
+    }
+
+    async fn start_session(&self, user: &User) -> Result<()> {
         let key = self.set_auth_key(user.id).await?;
         let session = Session {
             id: user.id,
-            username: user.username,
+            username: user.username.clone(),
             auth_key: key,
             time_stamp: now(),
         };
         let to_str = format!("{}", json!(session));
-        self.cookies.add_private(Cookie::new("rocket_auth", to_str));
+        // Lax rather than Strict so the cookie survives the redirect back from an OIDC provider.
+        self.cookies.add_private(Cookie::build(("rocket_auth", to_str)).same_site(SameSite::Lax));
         Ok(())
     }
 
@@ -196,17 +363,28 @@ impl<'a> Auth<'a> {
         }
     }
 
+    /// The token from an `Authorization: Bearer` header, if there is one.
+    pub fn bearer_token(&self) -> Option<&str> {
+        self.headers.get_one("Authorization")?.strip_prefix("Bearer ")
+    }
+
     pub async fn get_user(&self, db: &DatabaseConnection) -> Option<User> {
         if self.config.authentik_use_headers {
             let username = self.headers.get_one("X-authentik-username");
             let email = self.headers.get_one("X-authentik-email");
             let name = self.headers.get_one("X-authentik-name");
             let groups = self.headers.get_one("X-authentik-groups");
-            match (username, email, name, groups) {
-                (Some(username), Some(email), Some(name), Some(groups)) => {
+            let uid = self.headers.get_one("X-authentik-uid");
+            if username.is_some() && uid.is_none() {
+                // Usernames can change, so they can't stand in for the uid.
+                error!("X-authentik-uid is missing; update the authentik outpost to use header login");
+                return None;
+            }
+            match (uid, username, email, name, groups) {
+                (Some(uid), Some(username), Some(email), Some(name), Some(groups)) => {
                     let resident = groups.split("|").any(|g| g == self.config.authentik_residents_group);
                     use sea_orm::ActiveValue::Set;
-                    return Mutation::ensure_user(db, crate::entities::user::ActiveModel {
+                    return Mutation::ensure_user(db, PROXY_PROVIDER, uid, crate::entities::user::ActiveModel {
                         username: Set(username.to_string()),
                         name: Set(Some(name.to_string())),
                         email: Set(Some(email.to_string())),
@@ -230,6 +408,29 @@ use crate::service::*;
 #[derive(PartialEq, Eq, Clone, Hash)]
 pub struct Resident(User);
 
+impl std::ops::Deref for Resident {
+    type Target = User;
+    fn deref(&self) -> &User {
+        &self.0
+    }
+}
+
+/// Why a guard turned the request away, if it had more to say than the
+/// status code. Error catchers read it from the request's local cache.
+pub struct Rejection(pub Option<String>);
+
+/// A user allowed on the `/admin` pages. Being a resident isn't enough;
+/// `is_admin` is only set with the `make-admin` command.
+#[derive(PartialEq, Eq, Clone, Hash)]
+pub struct Admin(User);
+
+impl std::ops::Deref for Admin {
+    type Target = User;
+    fn deref(&self) -> &User {
+        &self.0
+    }
+}
+
 pub struct Users<'a> {
     sess: &'a dyn SessionManager,
 }
@@ -259,6 +460,16 @@ impl<'r> FromRequest<'r> for Users<'r> {
     }
 }
 
+/// The address of whoever sent `req`: the connection's, or the one a
+/// trusted proxy passed on.
+fn client_ip(req: &Request<'_>, config: &Config) -> Option<IpAddr> {
+    let remote = req.remote().and_then(|endpoint| endpoint.ip());
+    match remote {
+        Some(proxy) if config.trusted_proxies.contains(&proxy) => req.real_ip().or(remote),
+        _ => remote,
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for Auth<'r> {
     type Error = Error;
@@ -287,6 +498,7 @@ impl<'r> FromRequest<'r> for Auth<'r> {
             session,
             cookies: req.cookies(),
             headers: req.headers(),
+            client_ip: client_ip(req, config),
         })
     }
 }
@@ -294,21 +506,40 @@ impl<'r> FromRequest<'r> for Auth<'r> {
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for User {
     type Error = Error;
+    /// Worked out once per request and cached, however many guards ask for
+    /// it, so sessions and tokens are only checked once.
     async fn from_request(request: &'r Request<'_>) -> Outcome<User, Error> {
-        let auth: Auth = try_outcome!(request.guard().await);
-        let db: &State<DatabaseConnection> = match request.guard().await {
-            Outcome::Success(db) => db,
-            _ => return Outcome::Error((Status::InternalServerError, Error::UnmanagedStateError)),
-        };
-        let db: &DatabaseConnection = db as &DatabaseConnection;
-        if let Some(user) = auth.get_user(db).await {
-            Outcome::Success(user)
-        } else {
-            Outcome::Error((Status::Unauthorized, Error::UnauthorizedError))
+        let user: &std::result::Result<User, Status> = request.local_cache_async(authenticate(request)).await;
+        match user {
+            Ok(user) => Outcome::Success(user.clone()),
+            Err(Status::InternalServerError) => Outcome::Error((Status::InternalServerError, Error::UnmanagedStateError)),
+            Err(status) => Outcome::Error((*status, Error::UnauthorizedError)),
         }
     }
 }
 
+/// Who sent `request`, by bearer token or session. Why a token was refused
+/// is left in the local cache as a `Rejection`.
+async fn authenticate(request: &Request<'_>) -> std::result::Result<User, Status> {
+    let auth: Auth = match request.guard().await {
+        Outcome::Success(auth) => auth,
+        Outcome::Error((status, _)) => return Err(status),
+        Outcome::Forward(status) => return Err(status),
+    };
+    let db: &State<DatabaseConnection> = match request.guard().await {
+        Outcome::Success(db) => db,
+        _ => return Err(Status::InternalServerError),
+    };
+    let db: &DatabaseConnection = db as &DatabaseConnection;
+    if let Some(token) = auth.bearer_token() {
+        return api_token::authenticate(db, token, request.method()).await.map_err(|e| {
+            request.local_cache(|| Rejection(Some(e.to_string())));
+            (&e).into()
+        });
+    }
+    auth.get_user(db).await.ok_or(Status::Unauthorized)
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for Resident {
     type Error = Error;
@@ -321,3 +552,16 @@ impl<'r> FromRequest<'r> for Resident {
         }
     }
 }
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = Error;
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Admin, Error> {
+        let user: User = try_outcome!(request.guard().await);
+        if user.is_admin {
+            Outcome::Success(Admin(user))
+        } else {
+            Outcome::Error((Status::Forbidden, Error::UnauthorizedError))
+        }
+    }
+}
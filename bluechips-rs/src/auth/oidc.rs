@@ -0,0 +1,230 @@
+//! Native OpenID Connect login, as an alternative to running a forward-auth
+//! proxy in front of the app.
+//!
+//! This implements the authorization code flow with PKCE. The provider is
+//! found through discovery when the app starts, and ID tokens are validated
+//! against the provider's JWKS. Pointing `oidc_issuer_url` at a local mock
+//! issuer is enough to exercise the whole flow.
+use std::collections::HashMap;
+
+use openidconnect::core::{CoreAuthenticationFlow, CoreClient, CoreGenderClaim, CoreProviderMetadata};
+use openidconnect::reqwest::async_http_client;
+use openidconnect::{
+    AdditionalClaims, AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce,
+    OAuth2TokenResponse, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+    UserInfoClaims,
+};
+use rocket::http::{Cookie, CookieJar, SameSite};
+use rocket::serde::{Serialize, Deserialize};
+use sea_orm::ActiveValue::Set;
+use serde_json::{json, from_str, Map, Value};
+
+use crate::entities::user;
+use super::{Error, Result};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// OIDC login is disabled unless this is set.
+    pub oidc_issuer_url: Option<String>,
+    pub oidc_client_id: String,
+    pub oidc_client_secret: Option<String>,
+    pub oidc_redirect_url: String,
+    /// Shown on the login button.
+    pub oidc_display_name: String,
+    pub oidc_username_claim: String,
+    pub oidc_name_claim: String,
+    pub oidc_email_claim: String,
+    pub oidc_groups_claim: String,
+    pub oidc_residents_group: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            oidc_issuer_url: None,
+            oidc_client_id: "bluechips".to_string(),
+            oidc_client_secret: None,
+            oidc_redirect_url: "http://localhost:8000/login/oidc/callback".to_string(),
+            oidc_display_name: "single sign-on".to_string(),
+            oidc_username_claim: "preferred_username".to_string(),
+            oidc_name_claim: "name".to_string(),
+            oidc_email_claim: "email".to_string(),
+            oidc_groups_claim: "groups".to_string(),
+            oidc_residents_group: "Residents".to_string(),
+        }
+    }
+}
+
+/// Catch-all for claims we don't know about ahead of time, such as groups.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ExtraClaims {
+    #[serde(flatten)]
+    claims: HashMap<String, Value>,
+}
+impl AdditionalClaims for ExtraClaims {}
+
+/// State that has to survive the round trip through the provider.
+#[derive(Serialize, Deserialize)]
+struct PendingLogin {
+    csrf: String,
+    nonce: String,
+    pkce_verifier: String,
+}
+
+const STATE_COOKIE: &str = "oidc_state";
+
+pub struct Provider {
+    client: CoreClient,
+    pub issuer: String,
+    pub display_name: String,
+}
+
+impl Provider {
+    /// Returns `None` if OIDC isn't configured.
+    pub async fn discover(config: &Config) -> Result<Option<Self>> {
+        let Some(issuer) = &config.oidc_issuer_url else {
+            return Ok(None);
+        };
+        let issuer_url = IssuerUrl::new(issuer.clone())
+            .map_err(|e| Error::OidcError(format!("invalid issuer URL: {}", e)))?;
+        let metadata = CoreProviderMetadata::discover_async(issuer_url, async_http_client)
+            .await
+            .map_err(|e| Error::OidcError(format!("discovery failed: {}", e)))?;
+        let redirect_url = RedirectUrl::new(config.oidc_redirect_url.clone())
+            .map_err(|e| Error::OidcError(format!("invalid redirect URL: {}", e)))?;
+        let client = CoreClient::from_provider_metadata(
+            metadata,
+            ClientId::new(config.oidc_client_id.clone()),
+            config.oidc_client_secret.clone().map(ClientSecret::new),
+        )
+            .set_redirect_uri(redirect_url);
+        Ok(Some(Self {
+            client,
+            issuer: issuer.clone(),
+            display_name: config.oidc_display_name.clone(),
+        }))
+    }
+
+    /// Start a login, returning the URL to send the user to.
+    pub fn authorize_url(&self, cookies: &CookieJar<'_>) -> String {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, csrf, nonce) = self.client
+            .authorize_url(CoreAuthenticationFlow::AuthorizationCode, CsrfToken::new_random, Nonce::new_random)
+            .add_scope(Scope::new("profile".to_string()))
+            .add_scope(Scope::new("email".to_string()))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+        let pending = PendingLogin {
+            csrf: csrf.secret().clone(),
+            nonce: nonce.secret().clone(),
+            pkce_verifier: pkce_verifier.secret().clone(),
+        };
+        // The provider redirects back to us cross-site, so this can't be SameSite=Strict.
+        cookies.add_private(
+            Cookie::build((STATE_COOKIE, json!(pending).to_string()))
+                .same_site(SameSite::Lax)
+                .http_only(true)
+        );
+        url.to_string()
+    }
+
+    /// Finish a login, returning the provider's subject and the user it maps to.
+    pub async fn exchange(&self, config: &Config, cookies: &CookieJar<'_>, code: &str, state: &str) -> Result<(String, user::ActiveModel)> {
+        let pending: PendingLogin = cookies.get_private(STATE_COOKIE)
+            .and_then(|c| from_str(c.value()).ok())
+            .ok_or(Error::OidcError("login was not started here".to_string()))?;
+        cookies.remove_private(STATE_COOKIE);
+        if pending.csrf != state {
+            return Err(Error::OidcError("state mismatch".to_string()));
+        }
+        let token = self.client
+            .exchange_code(AuthorizationCode::new(code.to_string()))
+            .set_pkce_verifier(PkceCodeVerifier::new(pending.pkce_verifier))
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| Error::OidcError(format!("token exchange failed: {}", e)))?;
+        let id_token = token.id_token()
+            .ok_or(Error::OidcError("provider did not return an ID token".to_string()))?;
+        let id_claims = id_token
+            .claims(&self.client.id_token_verifier(), &Nonce::new(pending.nonce))
+            .map_err(|e| Error::OidcError(format!("invalid ID token: {}", e)))?;
+        let subject = id_claims.subject().to_string();
+        let mut claims = match serde_json::to_value(id_claims)? {
+            Value::Object(claims) => claims,
+            _ => Map::new(),
+        };
+        // Groups are often only available from the userinfo endpoint.
+        if let Ok(request) = self.client.user_info(token.access_token().clone(), Some(id_claims.subject().clone())) {
+            let userinfo: UserInfoClaims<ExtraClaims, CoreGenderClaim> = request
+                .request_async(async_http_client)
+                .await
+                .map_err(|e| Error::OidcError(format!("userinfo request failed: {}", e)))?;
+            if let Value::Object(userinfo) = serde_json::to_value(userinfo)? {
+                claims.extend(userinfo);
+            }
+        }
+        let user = user_from_claims(config, &claims)
+            .ok_or(Error::OidcError(format!("missing {} claim", config.oidc_username_claim)))?;
+        Ok((subject, user))
+    }
+}
+
+fn user_from_claims(config: &Config, claims: &Map<String, Value>) -> Option<user::ActiveModel> {
+    let username = claims.get(&config.oidc_username_claim)?.as_str()?;
+    let get = |claim: &str| claims.get(claim).and_then(Value::as_str).map(str::to_string);
+    let resident = match claims.get(&config.oidc_groups_claim) {
+        Some(Value::Array(groups)) => groups.iter().any(|g| g.as_str() == Some(&config.oidc_residents_group)),
+        Some(Value::String(groups)) => groups.split(&[',', ' ', '|']).any(|g| g == config.oidc_residents_group),
+        _ => false,
+    };
+    Some(user::ActiveModel {
+        username: Set(username.to_string()),
+        name: Set(get(&config.oidc_name_claim)),
+        email: Set(get(&config.oidc_email_claim)),
+        resident: Set(resident),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn maps_claims() {
+        let user = user_from_claims(&Config::default(), &claims(json!({
+            "sub": "1234",
+            "preferred_username": "alice",
+            "name": "Alice",
+            "email": "alice@example.com",
+            "groups": ["Residents", "Admins"],
+        }))).unwrap();
+        assert_eq!(user.username, Set("alice".to_string()));
+        assert_eq!(user.name, Set(Some("Alice".to_string())));
+        assert_eq!(user.email, Set(Some("alice@example.com".to_string())));
+        assert_eq!(user.resident, Set(true));
+    }
+
+    #[test]
+    fn non_resident_without_group() {
+        let user = user_from_claims(&Config::default(), &claims(json!({
+            "preferred_username": "bob",
+            "groups": "Guests",
+        }))).unwrap();
+        assert_eq!(user.resident, Set(false));
+        assert_eq!(user.name, Set(None));
+    }
+
+    #[test]
+    fn requires_username() {
+        assert!(user_from_claims(&Config::default(), &claims(json!({"sub": "1234"}))).is_none());
+    }
+}
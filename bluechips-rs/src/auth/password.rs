@@ -0,0 +1,140 @@
+//! Password checking, hashing upgrades and reset tokens.
+use argon2::{PasswordHash, Params, ARGON2ID_IDENT};
+use password_auth::{verify_password, VerifyError};
+use rand::{distributions::Alphanumeric, Rng};
+use sea_orm::DatabaseConnection;
+
+use crate::service::Mutation;
+use super::{api_token, Error, Result, User};
+
+pub const MIN_LENGTH: usize = 10;
+
+/// A few passwords that pass the length check but shouldn't.
+const COMMON: &[&str] = &[
+    "1234567890",
+    "0123456789",
+    "qwertyuiop",
+    "password12",
+    "password123",
+    "password1234",
+    "iloveyou12",
+    "letmein123",
+    "bluechips1",
+    "bluechips123",
+];
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct SetPassword {
+    pub password: String,
+    #[field(validate = eq(self.password.as_str()).or_else(msg!("passwords do not match")))]
+    pub confirm_password: String,
+}
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct ChangePassword {
+    pub current_password: Option<String>,
+    pub password: String,
+    #[field(validate = eq(self.password.as_str()).or_else(msg!("passwords do not match")))]
+    pub confirm_password: String,
+}
+
+pub fn check_strength(password: &str, user: &User) -> Result<()> {
+    if password.chars().count() < MIN_LENGTH {
+        return Err(Error::WeakPasswordError("Passwords must be at least 10 characters long."));
+    }
+    let lower = password.to_lowercase();
+    if lower.contains(&user.username.to_lowercase()) {
+        return Err(Error::WeakPasswordError("Passwords can't contain your username."));
+    }
+    if COMMON.contains(&lower.as_str()) {
+        return Err(Error::WeakPasswordError("That password is too common."));
+    }
+    let mut chars: Vec<char> = lower.chars().collect();
+    chars.sort_unstable();
+    chars.dedup();
+    if chars.len() < 5 {
+        return Err(Error::WeakPasswordError("Passwords need more than a few different characters."));
+    }
+    Ok(())
+}
+
+/// Check `password` against `stored`.
+///
+/// Returns whether the stored hash should be replaced with a fresh one,
+/// which is the case for hashes made with older argon2 parameters and for
+/// the plain-text passwords the Python app kept.
+pub fn verify(password: &str, stored: &str) -> Result<bool> {
+    if !stored.starts_with('$') {
+        return if constant_time_eq(password.as_bytes(), stored.as_bytes()) {
+            Ok(true)
+        } else {
+            Err(VerifyError::PasswordInvalid.into())
+        };
+    }
+    verify_password(password, stored)?;
+    Ok(is_obsolete(stored))
+}
+
+fn is_obsolete(stored: &str) -> bool {
+    match PasswordHash::new(stored) {
+        Ok(hash) =>
+            hash.algorithm != ARGON2ID_IDENT
+            || Params::try_from(&hash).map_or(true, |params| params != Params::default()),
+        Err(_) => true,
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Create a single-use reset token for `user_id`, returning the secret.
+pub async fn issue_reset(db: &DatabaseConnection, user_id: i32, valid_for: chrono::Duration) -> Result<String> {
+    let secret: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+    Mutation::create_password_reset(db, user_id, api_token::hash(&secret), valid_for).await?;
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use password_auth::generate_hash;
+
+    fn user() -> User {
+        User {
+            id: 1,
+            username: "alice".to_string(),
+            name: None,
+            resident: true,
+            email: None,
+            password: None,
+            is_admin: false,
+        }
+    }
+
+    #[test]
+    fn strength() {
+        assert!(check_strength("short", &user()).is_err());
+        assert!(check_strength("alice-is-great", &user()).is_err());
+        assert!(check_strength("password123", &user()).is_err());
+        assert!(check_strength("aaaaaaaaaaaa", &user()).is_err());
+        assert!(check_strength("correct horse battery", &user()).is_ok());
+    }
+
+    #[test]
+    fn current_hash_is_not_rehashed() {
+        let hash = generate_hash("correct horse battery");
+        assert!(!verify("correct horse battery", &hash).unwrap());
+        assert!(verify("wrong", &hash).is_err());
+    }
+
+    #[test]
+    fn plain_text_is_rehashed() {
+        assert!(verify("hunter2", "hunter2").unwrap());
+        assert!(verify("hunter3", "hunter2").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Slow down password guessing.
+//!
+//! Failed logins are counted per account and per client address. After a
+//! few free attempts, each further attempt has to wait twice as long as the
+//! last, and enough failures lock the target out entirely for a while.
+//! Accounts are keyed by the submitted username whether or not it exists,
+//! so the throttle doesn't reveal which usernames are real. Requests for
+//! password reset links are counted the same way, under their own targets.
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::DatabaseConnection;
+
+use crate::entities::login_throttle;
+use crate::service::{Mutation, Query};
+use super::{Config, Error, Result};
+
+/// The longest we'll make someone wait between attempts, short of a lockout.
+const MAX_BACKOFF_SECS: i64 = 5 * 60;
+
+pub(super) async fn check(db: &DatabaseConnection, config: &Config, target: &str) -> Result<()> {
+    if let Some(throttle) = Query::get_login_throttle(db, target).await? {
+        if let Some(wait) = retry_after(&throttle, config, Utc::now()) {
+            return Err(Error::ThrottledError(wait.num_seconds().max(1)));
+        }
+    }
+    Ok(())
+}
+
+pub(super) async fn record_failure(db: &DatabaseConnection, config: &Config, target: &str, lock_after: i32) -> Result<()> {
+    let lock_for = Duration::minutes(config.login_lockout_minutes);
+    let throttle = Mutation::record_login_failure(db, target, lock_after, lock_for).await?;
+    if throttle.locked_until.is_some() {
+        warn!("Locked out {} after {} failed logins", target, throttle.failures);
+    }
+    Ok(())
+}
+
+pub(super) async fn record_success(db: &DatabaseConnection, target: &str) -> Result<()> {
+    Mutation::clear_login_throttle(db, target).await?;
+    Ok(())
+}
+
+fn retry_after(throttle: &login_throttle::Model, config: &Config, now: DateTime<Utc>) -> Option<Duration> {
+    if let Some(locked_until) = throttle.locked_until {
+        return (locked_until > now).then(|| locked_until - now);
+    }
+    let excess = throttle.failures - config.login_free_attempts;
+    if excess <= 0 {
+        return None;
+    }
+    let backoff = Duration::seconds((1i64 << excess.min(16)).min(MAX_BACKOFF_SECS));
+    let ready = throttle.last_failure + backoff;
+    (ready > now).then(|| ready - now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(failures: i32, locked_until: Option<DateTime<Utc>>) -> login_throttle::Model {
+        login_throttle::Model {
+            target: "user:alice".to_string(),
+            failures,
+            last_failure: DateTime::from_timestamp(1_000_000, 0).unwrap(),
+            locked_until,
+        }
+    }
+
+    #[test]
+    fn free_attempts() {
+        let t = throttle(3, None);
+        assert_eq!(retry_after(&t, &Config::default(), t.last_failure), None);
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let config = Config::default();
+        let t = throttle(4, None);
+        assert_eq!(retry_after(&t, &config, t.last_failure), Some(Duration::seconds(2)));
+        assert_eq!(retry_after(&t, &config, t.last_failure + Duration::seconds(2)), None);
+        let t = throttle(6, None);
+        assert_eq!(retry_after(&t, &config, t.last_failure), Some(Duration::seconds(8)));
+        let t = throttle(30, None);
+        assert_eq!(retry_after(&t, &config, t.last_failure), Some(Duration::seconds(MAX_BACKOFF_SECS)));
+    }
+
+    #[test]
+    fn lockout() {
+        let config = Config::default();
+        let now = DateTime::from_timestamp(1_000_000, 0).unwrap();
+        let t = throttle(10, Some(now + Duration::minutes(15)));
+        assert_eq!(retry_after(&t, &config, now), Some(Duration::minutes(15)));
+        assert_eq!(retry_after(&t, &config, now + Duration::minutes(15)), None);
+    }
+}
@@ -0,0 +1,113 @@
+//! RFC 6238 time-based one-time passwords, as an optional second factor
+//! for password logins.
+//!
+//! Logins through a proxy or OpenID Connect skip this; the identity
+//! provider is expected to handle its own second factor.
+use rand::{distributions::Alphanumeric, Rng};
+use sea_orm::DatabaseConnection;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::service::{Mutation, Query};
+use super::{api_token, Error, Result};
+
+const ISSUER: &str = "BlueChips";
+const STEP: u64 = 30;
+/// How many steps either side of now to accept, for clock drift.
+const SKEW: u64 = 1;
+const RECOVERY_CODES: usize = 10;
+
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn totp(secret: &str, account: &str) -> Result<TOTP> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| Error::TotpError(e.to_string()))?;
+    TOTP::new(Algorithm::SHA1, 6, SKEW as u8, STEP, secret, Some(ISSUER.to_string()), account.to_string())
+        .map_err(|e| Error::TotpError(e.to_string()))
+}
+
+/// The `otpauth://` URI to hand to an authenticator app.
+pub fn otpauth_url(secret: &str, account: &str) -> Result<String> {
+    Ok(totp(secret, account)?.get_url())
+}
+
+/// The same URI as a base64-encoded PNG QR code.
+pub fn qr_code(secret: &str, account: &str) -> Result<String> {
+    totp(secret, account)?.get_qr_base64().map_err(Error::TotpError)
+}
+
+/// Returns the time step `code` is valid for, if any.
+pub fn check(secret: &str, code: &str, now: u64) -> Result<Option<i64>> {
+    let totp = totp(secret, "")?;
+    let current = now / STEP;
+    Ok((current.saturating_sub(SKEW)..=current + SKEW)
+        .find(|step| totp.generate(step * STEP) == code)
+        .map(|step| step as i64))
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let code: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+    api_token::hash(&normalize(code))
+}
+
+fn normalize(code: &str) -> String {
+    code.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_lowercase()).collect()
+}
+
+/// Check a code from the second login step, which may be either a current
+/// TOTP code or an unused recovery code. Either is used up on success.
+pub async fn verify(db: &DatabaseConnection, user_id: i32, code: &str) -> Result<bool> {
+    let Some(enrolment) = Query::get_user_totp(db, user_id).await? else {
+        return Ok(false);
+    };
+    let digits: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if let Some(step) = check(&enrolment.secret, &digits, super::now() as u64)? {
+        if step <= enrolment.last_step {
+            return Ok(false);
+        }
+        Mutation::set_totp_last_step(db, user_id, step).await?;
+        return Ok(true);
+    }
+    Ok(Mutation::use_recovery_code(db, user_id, hash_recovery_code(code)).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA-1 test vector from RFC 6238, appendix B.
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn rfc6238() {
+        assert_eq!(check(SECRET, "287082", 59).unwrap(), Some(1));
+        assert_eq!(check(SECRET, "081804", 1111111109).unwrap(), Some(37037036));
+    }
+
+    #[test]
+    fn skew() {
+        assert_eq!(check(SECRET, "287082", 59 + STEP).unwrap(), Some(1));
+        assert_eq!(check(SECRET, "287082", 59 + 2 * STEP).unwrap(), None);
+    }
+
+    #[test]
+    fn recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert_eq!(hash_recovery_code(&codes[0]), hash_recovery_code(&codes[0].to_uppercase().replace('-', " ")));
+    }
+}
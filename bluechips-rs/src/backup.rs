@@ -0,0 +1,618 @@
+//! Backups as a portable archive, and restoring them into any database
+//! bluechips supports.
+//!
+//! An archive is JSON Lines: a header with the format version, then one
+//! line per row, tagged with the table it came from. Rows keep their IDs.
+//! It includes password hashes, two-factor secrets and webhook signing
+//! secrets, so keep backups somewhere private.
+//!
+//! Setting `backup_dir` also takes a backup every `backup_interval_hours`
+//! in the background, keeping the newest `backup_keep`.
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rocket::serde::{Deserialize, Serialize};
+use sea_orm::prelude::DateTimeUtc;
+use sea_orm::{
+    ActiveModelTrait, ConnectionTrait, DatabaseBackend, DatabaseConnection, DbErr, EntityTrait, IntoActiveModel, PaginatorTrait,
+    TransactionTrait,
+};
+
+use crate::entities::{prelude::*, *};
+
+/// Bumped whenever the tables in an archive change shape. Older archives
+/// can still be restored; version 1 had no categories or tags, and
+/// version 2 only had users, the ledger and sessions.
+pub const VERSION: u32 = 3;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Where scheduled backups go. They're off unless it's set.
+    pub backup_dir: Option<PathBuf>,
+    pub backup_interval_hours: i64,
+    /// How many scheduled backups to keep.
+    pub backup_keep: usize,
+    /// Whether scheduled backups include login sessions.
+    pub backup_sessions: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backup_dir: None,
+            backup_interval_hours: 24,
+            backup_keep: 7,
+            backup_sessions: false,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Db(#[from] DbErr),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Line {0}: {1}")]
+    Json(usize, serde_json::Error),
+    #[error("The archive doesn't start with a header.")]
+    NoHeader,
+    #[error("The archive is version {0}, but only versions up to {current} can be restored.", current = VERSION)]
+    Version(u32),
+    #[error("The archive is inconsistent: {}", .0.join("; "))]
+    Invalid(Vec<String>),
+    #[error("The database already has {0} in it; restore into a freshly migrated one.")]
+    NotEmpty(&'static str),
+}
+
+/// One line of an archive.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+    Header { version: u32, created: DateTimeUtc },
+    User(user::Model),
+    Category(category::Model),
+    Expenditure(expenditure::Model),
+    Tag(expenditure_tag::Model),
+    Split(split::Model),
+    Subitem(subitem::Model),
+    Transfer(transfer::Model),
+    Session(auth_session::Model),
+    Identity(external_identity::Model),
+    ApiToken(api_token::Model),
+    Totp(user_totp::Model),
+    RecoveryCode(recovery_code::Model),
+    NotificationPreference(notification_preference::Model),
+    ImportMapping(import_mapping::Model),
+    ImportedRow(imported_row::Model),
+    Webhook(webhook::Model),
+    Audit(audit_log::Model),
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Archive {
+    pub users: Vec<user::Model>,
+    pub categories: Vec<category::Model>,
+    pub expenditures: Vec<expenditure::Model>,
+    pub tags: Vec<expenditure_tag::Model>,
+    pub splits: Vec<split::Model>,
+    pub subitems: Vec<subitem::Model>,
+    pub transfers: Vec<transfer::Model>,
+    pub sessions: Vec<auth_session::Model>,
+    pub identities: Vec<external_identity::Model>,
+    pub api_tokens: Vec<api_token::Model>,
+    pub totps: Vec<user_totp::Model>,
+    pub recovery_codes: Vec<recovery_code::Model>,
+    pub notification_preferences: Vec<notification_preference::Model>,
+    pub import_mappings: Vec<import_mapping::Model>,
+    pub imported_rows: Vec<imported_row::Model>,
+    pub webhooks: Vec<webhook::Model>,
+    pub audit_log: Vec<audit_log::Model>,
+}
+
+/// Rows per INSERT, to stay under SQLite's limit on bound parameters.
+const CHUNK: usize = 500;
+
+/// Insert `rows` as they are, IDs included.
+pub(crate) async fn insert_all<A, C>(db: &C, rows: Vec<<A::Entity as EntityTrait>::Model>) -> Result<usize, DbErr>
+where
+    A: ActiveModelTrait,
+    <A::Entity as EntityTrait>::Model: IntoActiveModel<A>,
+    C: ConnectionTrait,
+{
+    let count = rows.len();
+    let mut rows = rows.into_iter().peekable();
+    while rows.peek().is_some() {
+        A::Entity::insert_many(rows.by_ref().take(CHUNK).map(IntoActiveModel::into_active_model))
+            .exec_without_returning(db)
+            .await?;
+    }
+    Ok(count)
+}
+
+/// Postgres doesn't move a sequence on past IDs that were given
+/// explicitly, as `insert_all` does; SQLite and MySQL do.
+pub(crate) async fn reset_sequences<C: ConnectionTrait>(db: &C, tables: &[&str]) -> Result<(), DbErr> {
+    if db.get_database_backend() != DatabaseBackend::Postgres {
+        return Ok(());
+    }
+    for table in tables {
+        db.execute_unprepared(&format!(
+            "SELECT setval(pg_get_serial_sequence('{table}', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM {table}"
+        ))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Order categories so each comes after the one it's under. Any caught in
+/// a loop go last, where the database will refuse them.
+fn parents_first(mut categories: Vec<category::Model>) -> Vec<category::Model> {
+    let mut placed = HashSet::new();
+    let mut ordered = Vec::with_capacity(categories.len());
+    loop {
+        let (ready, rest): (Vec<_>, Vec<_>) = categories
+            .into_iter()
+            .partition(|c| c.parent_id.map_or(true, |p| placed.contains(&p)));
+        categories = rest;
+        if ready.is_empty() {
+            break;
+        }
+        placed.extend(ready.iter().map(|c| c.id));
+        ordered.extend(ready);
+    }
+    ordered.extend(categories);
+    ordered
+}
+
+impl Archive {
+    /// Everything in `db`, with login sessions if `sessions` is set.
+    pub async fn load(db: &DatabaseConnection, sessions: bool) -> Result<Archive, DbErr> {
+        Ok(Archive {
+            users: User::find().all(db).await?,
+            categories: Category::find().all(db).await?,
+            expenditures: Expenditure::find().all(db).await?,
+            tags: ExpenditureTag::find().all(db).await?,
+            splits: Split::find().all(db).await?,
+            subitems: Subitem::find().all(db).await?,
+            transfers: Transfer::find().all(db).await?,
+            sessions: match sessions {
+                true => AuthSession::find().all(db).await?,
+                false => Vec::new(),
+            },
+            identities: ExternalIdentity::find().all(db).await?,
+            api_tokens: ApiToken::find().all(db).await?,
+            totps: UserTotp::find().all(db).await?,
+            recovery_codes: RecoveryCode::find().all(db).await?,
+            notification_preferences: NotificationPreference::find().all(db).await?,
+            import_mappings: ImportMapping::find().all(db).await?,
+            imported_rows: ImportedRow::find().all(db).await?,
+            webhooks: Webhook::find().all(db).await?,
+            audit_log: AuditLog::find().all(db).await?,
+        })
+    }
+
+    pub fn write<W: Write>(&self, mut out: W, created: DateTimeUtc) -> io::Result<()> {
+        let mut line = |record: Record| -> io::Result<()> {
+            serde_json::to_writer(&mut out, &record)?;
+            out.write_all(b"\n")
+        };
+        line(Record::Header { version: VERSION, created })?;
+        self.users.iter().cloned().map(Record::User).try_for_each(&mut line)?;
+        self.categories.iter().cloned().map(Record::Category).try_for_each(&mut line)?;
+        self.expenditures.iter().cloned().map(Record::Expenditure).try_for_each(&mut line)?;
+        self.tags.iter().cloned().map(Record::Tag).try_for_each(&mut line)?;
+        self.splits.iter().cloned().map(Record::Split).try_for_each(&mut line)?;
+        self.subitems.iter().cloned().map(Record::Subitem).try_for_each(&mut line)?;
+        self.transfers.iter().cloned().map(Record::Transfer).try_for_each(&mut line)?;
+        self.sessions.iter().cloned().map(Record::Session).try_for_each(&mut line)?;
+        self.identities.iter().cloned().map(Record::Identity).try_for_each(&mut line)?;
+        self.api_tokens.iter().cloned().map(Record::ApiToken).try_for_each(&mut line)?;
+        self.totps.iter().cloned().map(Record::Totp).try_for_each(&mut line)?;
+        self.recovery_codes.iter().cloned().map(Record::RecoveryCode).try_for_each(&mut line)?;
+        self.notification_preferences.iter().cloned().map(Record::NotificationPreference).try_for_each(&mut line)?;
+        self.import_mappings.iter().cloned().map(Record::ImportMapping).try_for_each(&mut line)?;
+        self.imported_rows.iter().cloned().map(Record::ImportedRow).try_for_each(&mut line)?;
+        self.webhooks.iter().cloned().map(Record::Webhook).try_for_each(&mut line)?;
+        self.audit_log.iter().cloned().map(Record::Audit).try_for_each(&mut line)?;
+        out.flush()
+    }
+
+    pub fn read<R: BufRead>(input: R) -> Result<Archive, Error> {
+        let mut archive = Archive::default();
+        let mut header = false;
+        for (i, line) in input.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Record = serde_json::from_str(&line).map_err(|e| Error::Json(i + 1, e))?;
+            match record {
+                Record::Header { version, .. } if version > VERSION => return Err(Error::Version(version)),
+                Record::Header { .. } => header = true,
+                _ if !header => return Err(Error::NoHeader),
+                Record::User(row) => archive.users.push(row),
+                Record::Category(row) => archive.categories.push(row),
+                Record::Expenditure(row) => archive.expenditures.push(row),
+                Record::Tag(row) => archive.tags.push(row),
+                Record::Split(row) => archive.splits.push(row),
+                Record::Subitem(row) => archive.subitems.push(row),
+                Record::Transfer(row) => archive.transfers.push(row),
+                Record::Session(row) => archive.sessions.push(row),
+                Record::Identity(row) => archive.identities.push(row),
+                Record::ApiToken(row) => archive.api_tokens.push(row),
+                Record::Totp(row) => archive.totps.push(row),
+                Record::RecoveryCode(row) => archive.recovery_codes.push(row),
+                Record::NotificationPreference(row) => archive.notification_preferences.push(row),
+                Record::ImportMapping(row) => archive.import_mappings.push(row),
+                Record::ImportedRow(row) => archive.imported_rows.push(row),
+                Record::Webhook(row) => archive.webhooks.push(row),
+                Record::Audit(row) => archive.audit_log.push(row),
+            }
+        }
+        match header {
+            true => Ok(archive),
+            false => Err(Error::NoHeader),
+        }
+    }
+
+    /// Check that every row refers to rows that exist, no IDs repeat, and
+    /// every expenditure is split exactly.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = Vec::new();
+        let mut unique = |table: &str, ids: Vec<i32>| -> HashSet<i32> {
+            let mut seen = HashSet::new();
+            for id in ids {
+                if !seen.insert(id) {
+                    problems.push(format!("{} {} appears more than once", table, id));
+                }
+            }
+            seen
+        };
+        let users = unique("user", self.users.iter().map(|u| u.id).collect());
+        let categories = unique("category", self.categories.iter().map(|c| c.id).collect());
+        let expenditures = unique("expenditure", self.expenditures.iter().map(|e| e.id).collect());
+        unique("split", self.splits.iter().map(|s| s.id).collect());
+        unique("subitem", self.subitems.iter().map(|s| s.id).collect());
+        unique("transfer", self.transfers.iter().map(|t| t.id).collect());
+        unique("session", self.sessions.iter().map(|s| s.id).collect());
+        unique("external identity", self.identities.iter().map(|i| i.id).collect());
+        unique("api token", self.api_tokens.iter().map(|t| t.id).collect());
+        unique("two-factor secret for user", self.totps.iter().map(|t| t.user_id).collect());
+        unique("recovery code", self.recovery_codes.iter().map(|c| c.id).collect());
+        unique("notification preferences for user", self.notification_preferences.iter().map(|p| p.user_id).collect());
+        unique("import mapping", self.import_mappings.iter().map(|m| m.id).collect());
+        unique("imported row", self.imported_rows.iter().map(|r| r.id).collect());
+        unique("webhook", self.webhooks.iter().map(|w| w.id).collect());
+        unique("audit entry", self.audit_log.iter().map(|a| a.id).collect());
+        let mut check = |exists: bool, problem: &dyn Fn() -> String| {
+            if !exists {
+                problems.push(problem());
+            }
+        };
+        for c in &self.categories {
+            if let Some(parent_id) = c.parent_id {
+                check(categories.contains(&parent_id), &|| format!("category {} is under missing category {}", c.id, parent_id));
+            }
+        }
+        for e in &self.expenditures {
+            check(users.contains(&e.spender_id), &|| format!("expenditure {} was spent by missing user {}", e.id, e.spender_id));
+            if let Some(category_id) = e.category_id {
+                check(categories.contains(&category_id), &|| format!("expenditure {} is in missing category {}", e.id, category_id));
+            }
+        }
+        for t in &self.tags {
+            check(expenditures.contains(&t.expenditure_id), &|| format!("tag {:?} is on missing expenditure {}", t.tag, t.expenditure_id));
+        }
+        for (table, rows) in [
+            ("split", self.splits.iter().map(|s| (s.id, s.expenditure_id, s.user_id)).collect::<Vec<_>>()),
+            ("subitem", self.subitems.iter().map(|s| (s.id, s.expenditure_id, s.user_id)).collect()),
+        ] {
+            for (id, expenditure_id, user_id) in rows {
+                check(expenditures.contains(&expenditure_id), &|| format!("{} {} is for missing expenditure {}", table, id, expenditure_id));
+                check(users.contains(&user_id), &|| format!("{} {} is for missing user {}", table, id, user_id));
+            }
+        }
+        for t in &self.transfers {
+            for user_id in [t.debtor_id, t.creditor_id] {
+                check(users.contains(&user_id), &|| format!("transfer {} involves missing user {}", t.id, user_id));
+            }
+        }
+        for (table, rows) in [
+            ("external identity", self.identities.iter().map(|i| (i.id, i.user_id)).collect::<Vec<_>>()),
+            ("api token", self.api_tokens.iter().map(|t| (t.id, t.user_id)).collect()),
+            ("two-factor secret", self.totps.iter().map(|t| (t.user_id, t.user_id)).collect()),
+            ("recovery code", self.recovery_codes.iter().map(|c| (c.id, c.user_id)).collect()),
+            ("notification preferences", self.notification_preferences.iter().map(|p| (p.user_id, p.user_id)).collect()),
+        ] {
+            for (id, user_id) in rows {
+                check(users.contains(&user_id), &|| format!("{} {} belongs to missing user {}", table, id, user_id));
+            }
+        }
+        for r in &self.imported_rows {
+            if let Some(expenditure_id) = r.expenditure_id {
+                check(expenditures.contains(&expenditure_id), &|| format!("imported row {} is for missing expenditure {}", r.id, expenditure_id));
+            }
+        }
+        let mut split_totals: HashMap<i32, Currency> = HashMap::new();
+        for s in &self.splits {
+            let total = split_totals.entry(s.expenditure_id).or_insert(0.into());
+            *total = total.clone() + s.share.clone();
+        }
+        for e in &self.expenditures {
+            let total = split_totals.remove(&e.id).unwrap_or(0.into());
+            check(total == e.amount, &|| format!("expenditure {} is for {} but its splits add up to {}", e.id, e.amount, total));
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Invalid(problems)),
+        }
+    }
+
+    /// Load the archive into `db`, which must not have any of its tables'
+    /// rows yet. Nothing is saved unless it all is.
+    pub async fn restore(self, db: &DatabaseConnection) -> Result<(), Error> {
+        self.validate()?;
+        for (table, count) in [
+            ("users", User::find().count(db).await?),
+            ("expenditures", Expenditure::find().count(db).await?),
+            ("transfers", Transfer::find().count(db).await?),
+            ("sessions", AuthSession::find().count(db).await?),
+            ("import mappings", ImportMapping::find().count(db).await?),
+            ("webhooks", Webhook::find().count(db).await?),
+            ("audit log entries", AuditLog::find().count(db).await?),
+        ] {
+            if count > 0 {
+                return Err(Error::NotEmpty(table));
+            }
+        }
+        let txn = db.begin().await?;
+        insert_all::<user::ActiveModel, _>(&txn, self.users).await?;
+        insert_all::<category::ActiveModel, _>(&txn, parents_first(self.categories)).await?;
+        insert_all::<expenditure::ActiveModel, _>(&txn, self.expenditures).await?;
+        insert_all::<expenditure_tag::ActiveModel, _>(&txn, self.tags).await?;
+        insert_all::<split::ActiveModel, _>(&txn, self.splits).await?;
+        insert_all::<subitem::ActiveModel, _>(&txn, self.subitems).await?;
+        insert_all::<transfer::ActiveModel, _>(&txn, self.transfers).await?;
+        insert_all::<auth_session::ActiveModel, _>(&txn, self.sessions).await?;
+        insert_all::<external_identity::ActiveModel, _>(&txn, self.identities).await?;
+        insert_all::<api_token::ActiveModel, _>(&txn, self.api_tokens).await?;
+        insert_all::<user_totp::ActiveModel, _>(&txn, self.totps).await?;
+        insert_all::<recovery_code::ActiveModel, _>(&txn, self.recovery_codes).await?;
+        insert_all::<notification_preference::ActiveModel, _>(&txn, self.notification_preferences).await?;
+        insert_all::<import_mapping::ActiveModel, _>(&txn, self.import_mappings).await?;
+        insert_all::<imported_row::ActiveModel, _>(&txn, self.imported_rows).await?;
+        insert_all::<webhook::ActiveModel, _>(&txn, self.webhooks).await?;
+        insert_all::<audit_log::ActiveModel, _>(&txn, self.audit_log).await?;
+        reset_sequences(&txn, &[
+            "users", "categories", "expenditures", "splits", "subitems", "transfers", "auth_sessions",
+            "external_identities", "api_tokens", "recovery_codes", "import_mappings", "imported_rows", "webhooks", "audit_log",
+        ]).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+}
+
+const FILE_PREFIX: &str = "bluechips-";
+const FILE_SUFFIX: &str = ".jsonl";
+const FILE_TIME: &str = "%Y%m%dT%H%M%SZ";
+
+fn file_name(created: DateTimeUtc) -> String {
+    format!("{}{}{}", FILE_PREFIX, created.format(FILE_TIME), FILE_SUFFIX)
+}
+
+/// When the backup in `name` was taken, if it's one of ours.
+fn file_time(name: &str) -> Option<DateTimeUtc> {
+    let time = name.strip_prefix(FILE_PREFIX)?.strip_suffix(FILE_SUFFIX)?;
+    chrono::NaiveDateTime::parse_from_str(time, FILE_TIME).ok().map(|t| t.and_utc())
+}
+
+/// Scheduled backups in `dir`, newest first.
+async fn existing(dir: &Path) -> io::Result<Vec<(DateTimeUtc, PathBuf)>> {
+    let mut backups = Vec::new();
+    let mut entries = rocket::tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(time) = entry.file_name().to_str().and_then(file_time) {
+            backups.push((time, entry.path()));
+        }
+    }
+    backups.sort_by(|a, b| b.cmp(a));
+    Ok(backups)
+}
+
+/// Take a backup if the newest is older than the interval, then delete
+/// all but the newest `backup_keep`.
+async fn run_due(db: &DatabaseConnection, dir: &Path, config: &Config, now: DateTimeUtc) -> Result<(), Error> {
+    let mut backups = existing(dir).await?;
+    let due = backups
+        .first()
+        .map_or(true, |(last, _)| *last + chrono::Duration::hours(config.backup_interval_hours) <= now);
+    if due {
+        let mut data = Vec::new();
+        Archive::load(db, config.backup_sessions).await?.write(&mut data, now)?;
+        let path = dir.join(file_name(now));
+        // Written under another name first, so a half-written file is
+        // never taken for a backup.
+        let partial = path.with_extension("partial");
+        rocket::tokio::fs::write(&partial, data).await?;
+        rocket::tokio::fs::rename(&partial, &path).await?;
+        info!("Backed up to {}", path.display());
+        backups.insert(0, (now, path));
+    }
+    for (_, path) in backups.into_iter().skip(config.backup_keep.max(1)) {
+        rocket::tokio::fs::remove_file(&path).await?;
+    }
+    Ok(())
+}
+
+/// Start taking scheduled backups in the background, if they're set up.
+pub fn spawn(db: DatabaseConnection, config: Config) {
+    let Some(dir) = config.backup_dir.clone() else {
+        return;
+    };
+    rocket::tokio::spawn(async move {
+        loop {
+            if let Err(e) = run_due(&db, &dir, &config, chrono::Utc::now()).await {
+                error!("Scheduled backup failed: {}", e);
+            }
+            // The interval is in hours, so checking hourly is often enough.
+            rocket::tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone};
+
+    fn archive() -> Archive {
+        let created = chrono::Utc.with_ymd_and_hms(2026, 10, 1, 0, 0, 0).unwrap();
+        let user = |id, username: &str| user::Model {
+            id,
+            username: username.to_string(),
+            name: None,
+            resident: true,
+            email: None,
+            password: Some("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA".to_string()),
+            is_admin: id == 1,
+        };
+        Archive {
+            users: vec![user(1, "alice"), user(2, "bob")],
+            categories: vec![
+                category::Model { id: 1, name: "Food".to_string(), parent_id: None },
+                category::Model { id: 2, name: "Groceries".to_string(), parent_id: Some(1) },
+            ],
+            expenditures: vec![expenditure::Model {
+                id: 10,
+                spender_id: 1,
+                amount: 3001.into(),
+                description: Some("Groceries \"and\" things".to_string()),
+                date: NaiveDate::from_ymd_opt(2026, 10, 1),
+                entered_time: None,
+                category_id: Some(2),
+            }],
+            tags: vec![expenditure_tag::Model { expenditure_id: 10, tag: "weekly".to_string() }],
+            splits: vec![
+                split::Model { id: 1, expenditure_id: 10, user_id: 1, share: 1501.into() },
+                split::Model { id: 2, expenditure_id: 10, user_id: 2, share: 1500.into() },
+            ],
+            subitems: vec![],
+            transfers: vec![transfer::Model {
+                id: 5,
+                debtor_id: 2,
+                creditor_id: 1,
+                amount: 1500.into(),
+                description: None,
+                date: NaiveDate::from_ymd_opt(2026, 10, 2),
+                entered_time: None,
+            }],
+            sessions: vec![],
+            identities: vec![external_identity::Model {
+                id: 1,
+                provider: "authentik".to_string(),
+                subject: "abc123".to_string(),
+                user_id: 2,
+                created,
+            }],
+            api_tokens: vec![api_token::Model {
+                id: 1,
+                user_id: 1,
+                name: "phone".to_string(),
+                token_hash: "0".repeat(64),
+                read_only: true,
+                created,
+                expires: None,
+                last_used: Some(created),
+            }],
+            totps: vec![user_totp::Model { user_id: 1, secret: "JBSWY3DPEHPK3PXP".to_string(), last_step: 0, created }],
+            recovery_codes: vec![recovery_code::Model { id: 1, user_id: 1, code_hash: "hash".to_string(), used: None }],
+            notification_preferences: vec![notification_preference::Model {
+                user_id: 2,
+                email_changes: true,
+                digest: notification_preference::DigestFrequency::Weekly,
+                last_digest: None,
+                remind: false,
+                last_reminder: None,
+            }],
+            import_mappings: vec![],
+            imported_rows: vec![imported_row::Model { id: 1, fingerprint: "f".to_string(), expenditure_id: Some(10), time: created }],
+            webhooks: vec![],
+            audit_log: vec![audit_log::Model {
+                id: 1,
+                time: created,
+                actor_id: Some(1),
+                action: audit_log::Action::Create,
+                record_type: audit_log::RecordType::Expenditure,
+                record_id: 10,
+                before: None,
+                after: Some("{}".to_string()),
+            }],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut data = Vec::new();
+        archive().write(&mut data, chrono::Utc.with_ymd_and_hms(2026, 10, 19, 0, 0, 0).unwrap()).unwrap();
+        let text = String::from_utf8(data.clone()).unwrap();
+        assert!(text.starts_with(r#"{"type":"header","version":3,"#));
+        assert_eq!(text.lines().count(), 17);
+        assert!(text.contains(r#""action":"create","record_type":"expenditure""#));
+        assert_eq!(Archive::read(data.as_slice()).unwrap(), archive());
+        assert!(archive().validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let err = Archive::read(r#"{"type":"header","version":99,"created":"2026-10-19T00:00:00Z"}"#.as_bytes()).unwrap_err();
+        assert!(matches!(err, Error::Version(99)));
+        let err = Archive::read(r#"{"type":"session","id":1,"expires":"2026-10-19T00:00:00Z","secret":"x"}"#.as_bytes()).unwrap_err();
+        assert!(matches!(err, Error::NoHeader));
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let archive = Archive::read(concat!(
+            r#"{"type":"header","version":1,"created":"2026-10-19T00:00:00Z"}"#, "\n",
+            r#"{"type":"expenditure","id":10,"spender_id":1,"amount":"30.01","description":null,"date":null,"entered_time":null}"#,
+        ).as_bytes()).unwrap();
+        assert_eq!(archive.expenditures[0].category_id, None);
+    }
+
+    #[test]
+    fn categories_go_after_their_parents() {
+        let category = |id, parent_id| category::Model { id, name: id.to_string(), parent_id };
+        let ordered = parents_first(vec![category(3, Some(2)), category(2, Some(1)), category(1, None), category(4, Some(4))]);
+        let ids: Vec<i32> = ordered.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn finds_inconsistencies() {
+        let mut broken = archive();
+        broken.splits[1].share = 1499.into();
+        broken.transfers[0].creditor_id = 3;
+        broken.users.push(broken.users[0].clone());
+        broken.api_tokens[0].user_id = 3;
+        let Err(Error::Invalid(problems)) = broken.validate() else {
+            panic!("expected problems");
+        };
+        assert_eq!(problems.len(), 4);
+        assert!(problems.contains(&"api token 1 belongs to missing user 3".to_string()));
+        assert!(problems.iter().any(|p| p.starts_with("expenditure 10 is for")));
+        assert!(problems.contains(&"transfer 5 involves missing user 3".to_string()));
+        assert!(problems.contains(&"user 1 appears more than once".to_string()));
+    }
+
+    #[test]
+    fn scheduled_file_names() {
+        let time = chrono::Utc.with_ymd_and_hms(2026, 10, 19, 3, 4, 5).unwrap();
+        assert_eq!(file_name(time), "bluechips-20261019T030405Z.jsonl");
+        assert_eq!(file_time(&file_name(time)), Some(time));
+        assert_eq!(file_time("bluechips-20261019T030405Z.partial"), None);
+    }
+}
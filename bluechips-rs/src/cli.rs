@@ -0,0 +1,97 @@
+//! Administrative commands, run as `bluechips-rs <command> [args...]`.
+//!
+//! These use the same configuration (`Rocket.toml` and `ROCKET_*`
+//! environment variables) as the web app.
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+use sea_orm::{Database, DatabaseConnection};
+
+use crate::{auth, backup, legacy, mail, Config, Mutation, Query};
+
+const USAGE: &str = "\
+usage: bluechips-rs [command]
+
+With no command, runs the web app.
+
+commands:
+  reset-password <username>  print a link for <username> to set a new password
+  make-admin <username>      let <username> use the administration pages
+  remove-admin <username>    stop <username> using the administration pages
+  import-legacy <database>   copy everything from the Python app's database,
+                             given as a URL like sqlite://bluechips.db, into
+                             this one, which must be migrated and empty
+  backup [--sessions] <file> write everything to <file>, including login
+                             sessions if asked; it holds password hashes,
+                             two-factor and webhook secrets, so keep it
+                             private
+  restore <file>             load a backup into this database, which must be
+                             migrated and empty";
+
+pub async fn run(args: &[String]) -> Result<(), String> {
+    let figment = rocket::Config::figment();
+    let config: Config = figment.extract().map_err(|e| e.to_string())?;
+    let mail_config: mail::Config = figment.extract().map_err(|e| e.to_string())?;
+    let db = Database::connect(&config.db_uri).await.map_err(|e| e.to_string())?;
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["reset-password", username] => reset_password(&db, &mail_config, username).await,
+        ["make-admin", username] => set_admin(&db, username, true).await,
+        ["remove-admin", username] => set_admin(&db, username, false).await,
+        ["import-legacy", uri] => import_legacy(&db, uri).await,
+        ["backup", path] => write_backup(&db, path, false).await,
+        ["backup", "--sessions", path] => write_backup(&db, path, true).await,
+        ["restore", path] => restore(&db, path).await,
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+async fn reset_password(db: &DatabaseConnection, mail_config: &mail::Config, username: &str) -> Result<(), String> {
+    let user = Query::find_user_by_username(db, username).await
+        .map_err(|e| e.to_string())?
+        .ok_or(format!("no such user: {}", username))?;
+    // Long enough to get the link to someone who was just invited.
+    let token = auth::password::issue_reset(db, user.id, chrono::Duration::days(7)).await
+        .map_err(|e| e.to_string())?;
+    println!("{}", mail_config.url(uri!(crate::password_reset(token = token.as_str()))));
+    Ok(())
+}
+
+async fn set_admin(db: &DatabaseConnection, username: &str, is_admin: bool) -> Result<(), String> {
+    let user = Query::find_user_by_username(db, username).await
+        .map_err(|e| e.to_string())?
+        .ok_or(format!("no such user: {}", username))?;
+    Mutation::set_admin(db, user.id, is_admin).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+async fn import_legacy(db: &DatabaseConnection, uri: &str) -> Result<(), String> {
+    let legacy = Database::connect(uri).await.map_err(|e| e.to_string())?;
+    let report = legacy::import(&legacy, db).await.map_err(|e| e.to_string())?;
+    println!(
+        "Copied {} users, {} expenditures, {} splits, {} subitems and {} transfers. Balances are unchanged.",
+        report.users, report.expenditures, report.splits, report.subitems, report.transfers,
+    );
+    if !report.needs_reset.is_empty() {
+        println!("These users have no usable password; run reset-password for each:");
+        for username in &report.needs_reset {
+            println!("  {}", username);
+        }
+    }
+    Ok(())
+}
+
+async fn write_backup(db: &DatabaseConnection, path: &str, sessions: bool) -> Result<(), String> {
+    let archive = backup::Archive::load(db, sessions).await.map_err(|e| e.to_string())?;
+    let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    archive.write(BufWriter::new(file), chrono::Utc::now()).map_err(|e| format!("{}: {}", path, e))
+}
+
+async fn restore(db: &DatabaseConnection, path: &str) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let archive = backup::Archive::read(BufReader::new(file)).map_err(|e| e.to_string())?;
+    let counts = (archive.users.len(), archive.expenditures.len(), archive.transfers.len());
+    archive.restore(db).await.map_err(|e| e.to_string())?;
+    println!("Restored {} users, {} expenditures and {} transfers.", counts.0, counts.1, counts.2);
+    Ok(())
+}
@@ -0,0 +1,243 @@
+//! Scheduled mail: a weekly or monthly digest of where each user stands, and
+//! reminders for people whose debt has grown past a threshold.
+//!
+//! Both are opt-in on the user settings page. A background task started at
+//! liftoff checks for anything due every `digest_poll_seconds`; when each
+//! was last sent is kept with the user's preferences, so restarts neither
+//! skip nor repeat them.
+use std::collections::HashMap;
+use std::time::Duration;
+
+use askama::Template;
+use rocket::serde::Deserialize;
+use sea_orm::{prelude::DateTimeUtc, DatabaseConnection, DbErr};
+
+use crate::entities::notification_preference::{self, DigestFrequency};
+use crate::entities::prelude::*;
+use crate::mail;
+use crate::service::{ExpenditureDisplay, Mutation, Periods, Query, Totals};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub digest_poll_seconds: u64,
+    /// Remind people who owe more than this, e.g. `"50.00"`. Reminders are
+    /// off unless it's set.
+    pub reminder_threshold: Option<Currency>,
+    /// Don't remind anyone more often than this.
+    pub reminder_interval_days: i64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            digest_poll_seconds: 60 * 60,
+            reminder_threshold: None,
+            reminder_interval_days: 7,
+        }
+    }
+}
+
+fn digest_due(preference: &notification_preference::Model, now: DateTimeUtc) -> bool {
+    match preference.last_digest {
+        Some(last) => preference.digest.next_after(last).map_or(false, |next| next <= now),
+        None => preference.digest != DigestFrequency::Never,
+    }
+}
+
+fn reminder_due(preference: &notification_preference::Model, balance: &Currency, config: &Config, now: DateTimeUtc) -> bool {
+    let Some(threshold) = &config.reminder_threshold else {
+        return false;
+    };
+    preference.remind
+        && balance > threshold
+        && preference.last_reminder.map_or(true, |last| last + chrono::Duration::days(config.reminder_interval_days) <= now)
+}
+
+/// The user's part of the settle plan, as lines like `Pay Bob $5.00`.
+fn settle_lines(settle: &[(i32, i32, Currency)], user_id: i32, names: &HashMap<i32, String>) -> Vec<String> {
+    let name = |id: &i32| names.get(id).cloned().unwrap_or_else(|| id.to_string());
+    settle
+        .iter()
+        .filter_map(|(from, to, amount)| {
+            if *from == user_id {
+                Some(format!("Pay {} {}", name(to), amount))
+            } else if *to == user_id {
+                Some(format!("{} pays you {}", name(from), amount))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[derive(Template)]
+#[template(path = "emails/digest.txt")]
+struct DigestEmail<'a> {
+    name: &'a str,
+    balance: Currency,
+    settle: Vec<String>,
+    since: DateTimeUtc,
+    expenditures: Vec<ExpenditureDisplay>,
+    totals: Totals,
+    url: String,
+}
+
+#[derive(Template)]
+#[template(path = "emails/reminder.txt")]
+struct ReminderEmail<'a> {
+    name: &'a str,
+    balance: Currency,
+    threshold: &'a Currency,
+    settle: Vec<String>,
+    url: String,
+}
+
+/// Send every digest and reminder that is due. Returns how many were sent.
+pub async fn run_due(db: &DatabaseConnection, mailer: &mail::Mailer, mail_config: &mail::Config, config: &Config, periods: &Periods, now: DateTimeUtc) -> Result<usize, DbErr> {
+    let recipients = Query::find_digest_recipients(db).await?;
+    if recipients.is_empty() {
+        return Ok(0);
+    }
+    let debts = Query::get_debts(db).await?;
+    let settle = Query::settle(debts.clone()).unwrap_or_else(|e| {
+        warn!("No settle plan for digests: {:?}", e);
+        Vec::new()
+    });
+    let names: HashMap<i32, String> = Query::find_users(db)
+        .await?
+        .into_iter()
+        .map(|u| (u.id, u.name.unwrap_or(u.username)))
+        .collect();
+    let mut sent = 0;
+    for (preference, user) in recipients {
+        let Some(email) = &user.email else { continue };
+        let name = names.get(&user.id).map_or(user.username.as_str(), String::as_str);
+        let balance = debts.get(&user.id).cloned().unwrap_or(0.into());
+        if digest_due(&preference, now) {
+            let since = preference.last_digest.unwrap_or(match preference.digest {
+                DigestFrequency::Monthly => now - chrono::Duration::days(30),
+                _ => now - chrono::Duration::weeks(1),
+            });
+            let body = DigestEmail {
+                name,
+                balance: balance.clone(),
+                settle: settle_lines(&settle, user.id, &names),
+                since,
+                expenditures: Query::find_my_expenditures_entered_since(db, user.id, since).await?,
+                totals: Query::get_totals(db, user.id, periods).await?,
+                url: mail_config.url(uri!(crate::status_index())),
+            }
+                .render();
+            match body {
+                Ok(body) => match mailer.send(email, "BlueChips: your balance", body).await {
+                    Ok(()) => {
+                        Mutation::record_digest_sent(db, user.id, now).await?;
+                        sent += 1;
+                    }
+                    Err(e) => error!("Failed to send digest to {}: {}", email, e),
+                },
+                Err(e) => error!("Failed to render digest: {}", e),
+            }
+        }
+        if reminder_due(&preference, &balance, config, now) {
+            let body = ReminderEmail {
+                name,
+                threshold: config.reminder_threshold.as_ref().expect("checked by reminder_due"),
+                balance,
+                settle: settle_lines(&settle, user.id, &names),
+                url: mail_config.url(uri!(crate::transfer_index())),
+            }
+                .render();
+            match body {
+                Ok(body) => match mailer.send(email, "BlueChips: time to settle up", body).await {
+                    Ok(()) => {
+                        Mutation::record_reminder_sent(db, user.id, now).await?;
+                        sent += 1;
+                    }
+                    Err(e) => error!("Failed to send reminder to {}: {}", email, e),
+                },
+                Err(e) => error!("Failed to render reminder: {}", e),
+            }
+        }
+    }
+    Ok(sent)
+}
+
+/// Start the digest loop in the background, if mail is configured.
+pub fn spawn(db: DatabaseConnection, mail_config: mail::Config, config: Config, periods: Periods) {
+    let mailer = match mail::Mailer::new(&mail_config) {
+        Ok(Some(mailer)) => mailer,
+        Ok(None) => return,
+        Err(e) => {
+            error!("Digests disabled: {}", e);
+            return;
+        }
+    };
+    rocket::tokio::spawn(async move {
+        loop {
+            if let Err(e) = run_due(&db, &mailer, &mail_config, &config, &periods, chrono::Utc::now()).await {
+                error!("Sending digests failed: {:?}", e);
+            }
+            rocket::tokio::time::sleep(Duration::from_secs(config.digest_poll_seconds)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn preference() -> notification_preference::Model {
+        notification_preference::Model {
+            user_id: 1,
+            email_changes: false,
+            digest: DigestFrequency::Weekly,
+            last_digest: None,
+            remind: true,
+            last_reminder: None,
+        }
+    }
+
+    #[test]
+    fn digests_follow_frequency() {
+        let now = chrono::Utc.with_ymd_and_hms(2026, 10, 19, 9, 0, 0).unwrap();
+        let mut p = preference();
+        assert!(digest_due(&p, now));
+        p.last_digest = Some(now - chrono::Duration::days(6));
+        assert!(!digest_due(&p, now));
+        p.last_digest = Some(now - chrono::Duration::days(7));
+        assert!(digest_due(&p, now));
+        p.digest = DigestFrequency::Monthly;
+        assert!(!digest_due(&p, now));
+        p.last_digest = Some(chrono::Utc.with_ymd_and_hms(2026, 9, 19, 9, 0, 0).unwrap());
+        assert!(digest_due(&p, now));
+        p.digest = DigestFrequency::Never;
+        assert!(!digest_due(&p, now));
+    }
+
+    #[test]
+    fn reminders_need_threshold_and_interval() {
+        let now = chrono::Utc::now();
+        let mut config = Config::default();
+        let mut p = preference();
+        assert!(!reminder_due(&p, &Currency::from(10000), &config, now));
+        config.reminder_threshold = Some(Currency::from(5000));
+        assert!(reminder_due(&p, &Currency::from(10000), &config, now));
+        assert!(!reminder_due(&p, &Currency::from(5000), &config, now));
+        assert!(!reminder_due(&p, &Currency::from(-10000), &config, now));
+        p.last_reminder = Some(now - chrono::Duration::days(3));
+        assert!(!reminder_due(&p, &Currency::from(10000), &config, now));
+        p.remind = false;
+        p.last_reminder = None;
+        assert!(!reminder_due(&p, &Currency::from(10000), &config, now));
+    }
+
+    #[test]
+    fn settle_lines_are_from_the_users_side() {
+        let names = HashMap::from([(1, "Alice".to_string()), (2, "Bob".to_string()), (3, "Carol".to_string())]);
+        let settle = vec![(1, 2, Currency::from(500)), (3, 1, Currency::from(250)), (3, 2, Currency::from(100))];
+        assert_eq!(settle_lines(&settle, 1, &names), vec!["Pay Bob $5.00", "Carol pays you $2.50"]);
+    }
+}
@@ -27,6 +27,13 @@ impl Currency {
             self.clone()
         }
     }
+    /// The amount without symbol or separators, to the currency's number
+    /// of decimal places, like `-1234.50`.
+    pub fn to_decimal_string(&self) -> String {
+        let mut amount = *self.amount();
+        amount.rescale(self.0.currency().exponent());
+        amount.to_string()
+    }
 }
 
 impl core::ops::Neg for Currency {
@@ -93,6 +100,35 @@ impl fmt::Display for Currency {
     }
 }
 
+/// Serialized as a decimal string like `"1234.56"`, so clients don't have to
+/// deal with floating point.
+impl serde::Serialize for Currency {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.to_decimal_string())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Currency {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Self::try_from(value.as_str()).map_err(|e| serde::de::Error::custom(format!("failed to parse currency: {:?}", e)))
+    }
+}
+
+impl<'s> utoipa::ToSchema<'s> for Currency {
+    fn schema() -> (&'s str, utoipa::openapi::RefOr<utoipa::openapi::schema::Schema>) {
+        (
+            "Currency",
+            utoipa::openapi::ObjectBuilder::new()
+                .schema_type(utoipa::openapi::SchemaType::String)
+                .description(Some("A decimal amount of money in dollars."))
+                .pattern(Some(r"^-?[0-9]+(\.[0-9]{1,2})?$"))
+                .example(Some(serde_json::json!("12.50")))
+                .into(),
+        )
+    }
+}
+
 impl sea_orm::TryGetable for Currency {
     fn try_get_by<I: sea_orm::ColIdx>(res: &QueryResult, idx: I) -> Result<Self, sea_orm::TryGetError> {
         <i32 as sea_orm::TryGetable>::try_get_by(res, idx).map(|v| v.into())
@@ -132,6 +168,13 @@ mod tests {
         assert_eq!("$1,234.56", format!("{}", c));
     }
 
+    #[test]
+    fn serde() {
+        let c = Currency::from(-123450);
+        assert_eq!("\"-1234.50\"", serde_json::to_string(&c).unwrap());
+        assert_eq!(c, serde_json::from_str("\"-1234.5\"").unwrap());
+    }
+
     #[test]
     fn add() {
         let c1 = Currency::from(123);
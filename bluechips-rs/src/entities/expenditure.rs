@@ -1,9 +1,10 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.1
 
 use sea_orm::entity::prelude::*;
+use rocket::serde::{Deserialize, Serialize};
 use super::currency::Currency;
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
 #[sea_orm(table_name = "expenditures")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -14,6 +15,7 @@ pub struct Model {
     pub description: Option<String>,
     pub date: Option<Date>,
     pub entered_time: Option<DateTime>,
+    pub category_id: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -29,6 +31,17 @@ pub enum Relation {
         on_delete = "Cascade"
     )]
     Split,
+    #[sea_orm(
+        belongs_to = "super::category::Entity",
+        from = "Column::CategoryId",
+        to = "super::category::Column::Id"
+    )]
+    Category,
+    #[sea_orm(
+        has_many = "super::expenditure_tag::Entity",
+        on_delete = "Cascade"
+    )]
+    Tag,
 }
 
 // `Related` trait has to be implemented by hand
@@ -45,4 +58,16 @@ impl Related<super::split::Entity> for Entity {
     }
 }
 
+impl Related<super::category::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Category.def()
+    }
+}
+
+impl Related<super::expenditure_tag::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Tag.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
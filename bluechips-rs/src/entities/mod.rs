@@ -9,3 +9,18 @@ pub mod transfer;
 pub mod user;
 pub mod currency;
 pub mod auth_session;
+pub mod external_identity;
+pub mod api_token;
+pub mod password_reset;
+pub mod login_throttle;
+pub mod user_totp;
+pub mod recovery_code;
+pub mod audit_log;
+pub mod webhook;
+pub mod webhook_delivery;
+pub mod notification_preference;
+pub mod notification;
+pub mod import_mapping;
+pub mod imported_row;
+pub mod category;
+pub mod expenditure_tag;
@@ -6,4 +6,19 @@ pub use super::subitem::Entity as Subitem;
 pub use super::transfer::Entity as Transfer;
 pub use super::user::Entity as User;
 pub use super::auth_session::Entity as AuthSession;
+pub use super::external_identity::Entity as ExternalIdentity;
+pub use super::api_token::Entity as ApiToken;
+pub use super::password_reset::Entity as PasswordReset;
+pub use super::login_throttle::Entity as LoginThrottle;
+pub use super::user_totp::Entity as UserTotp;
+pub use super::recovery_code::Entity as RecoveryCode;
+pub use super::audit_log::Entity as AuditLog;
+pub use super::webhook::Entity as Webhook;
+pub use super::webhook_delivery::Entity as WebhookDelivery;
+pub use super::notification_preference::Entity as NotificationPreference;
+pub use super::notification::Entity as Notification;
+pub use super::import_mapping::Entity as ImportMapping;
+pub use super::imported_row::Entity as ImportedRow;
+pub use super::category::Entity as Category;
+pub use super::expenditure_tag::Entity as ExpenditureTag;
 pub use super::currency::Currency;
@@ -1,9 +1,10 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.1
 
 use sea_orm::entity::prelude::*;
+use rocket::serde::{Deserialize, Serialize};
 use super::currency::Currency;
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
 #[sea_orm(table_name = "splits")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -1,8 +1,9 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.1
 
 use sea_orm::entity::prelude::*;
+use rocket::serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, Hash, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, Hash, DeriveEntityModel, Eq, Serialize, Deserialize)]
 #[sea_orm(table_name = "users")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -12,6 +13,10 @@ pub struct Model {
     pub resident: bool,
     pub email: Option<String>,
     pub password: Option<String>,
+    /// Can use the `/admin` pages. Missing from older backups.
+    #[sea_orm(default_value = false)]
+    #[serde(default)]
+    pub is_admin: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
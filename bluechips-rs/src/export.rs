@@ -0,0 +1,331 @@
+//! CSV downloads of expenditures, transfers and per-user statements.
+//!
+//! Rows are written out as they come back from the database, so exporting a
+//! long history doesn't load all of it into memory first. Amounts are plain
+//! decimals like `-12.50`, and statement balances follow `get_debts`: a
+//! positive balance is what the user owes.
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use rocket::futures::{Stream, StreamExt};
+use rocket::http::{ContentType, Header};
+use rocket::request::Request;
+use rocket::response::{self, stream::TextStream, Responder, Response};
+use sea_orm::DatabaseConnection;
+use utoipa::IntoParams;
+
+use crate::entities::{prelude::Currency, transfer};
+use crate::service::{DateField, ExpenditureDisplay, HistoryFilter, Query};
+
+/// The history page's filters, as they appear in its query string. The
+/// same ones narrow down every download.
+#[derive(FromForm, UriDisplayQuery, IntoParams, Clone, Default)]
+#[into_params(parameter_in = Query)]
+pub struct Filter {
+    /// On or after this date, as MM/DD/YYYY or YYYY-MM-DD.
+    #[param(value_type = Option<String>)]
+    pub from: Option<DateField>,
+    /// On or before this date.
+    #[param(value_type = Option<String>)]
+    pub to: Option<DateField>,
+    /// Only records this user is part of.
+    pub user: Option<i32>,
+    /// Only records the current user is part of, unless `user` is given.
+    pub mine: bool,
+    pub spender: Option<i32>,
+    pub debtor: Option<i32>,
+    pub creditor: Option<i32>,
+    /// Smallest amount, like `12.50`. Left as text so an empty box means no
+    /// limit rather than zero.
+    pub min: Option<String>,
+    pub max: Option<String>,
+    /// Text the description contains.
+    pub search: Option<String>,
+    /// Only expenditures in this category or one under it.
+    pub category: Option<i32>,
+    /// Only expenditures with this tag.
+    pub tag: Option<String>,
+}
+
+impl Filter {
+    /// The query to run for `user_id`, who "mine" refers to.
+    pub fn history(&self, user_id: i32) -> HistoryFilter {
+        let amount = |value: &Option<String>| value
+            .as_deref()
+            .filter(|v| !v.trim().is_empty())
+            .and_then(|v| Currency::try_from(v.trim()).ok());
+        HistoryFilter {
+            from: self.from.as_ref().map(|d| d.0),
+            to: self.to.as_ref().map(|d| d.0),
+            user_id: self.user.or(self.mine.then_some(user_id)),
+            spender_id: self.spender,
+            debtor_id: self.debtor,
+            creditor_id: self.creditor,
+            min_amount: amount(&self.min),
+            max_amount: amount(&self.max),
+            search: self.search.clone().filter(|s| !s.trim().is_empty()),
+            category_id: self.category,
+            tag: self.tag.as_deref().map(str::trim).filter(|t| !t.is_empty()).map(String::from),
+        }
+    }
+}
+
+/// A streamed CSV file, offered as a download.
+pub struct Csv<S> {
+    pub filename: String,
+    pub rows: S,
+}
+
+impl<'r, S> Responder<'r, 'r> for Csv<S>
+    where S: Stream<Item = String> + Send + 'r
+{
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'r> {
+        Response::build_from(TextStream(self.rows).respond_to(request)?)
+            .header(ContentType::CSV)
+            .header(Header::new("Content-Disposition", format!("attachment; filename=\"{}\"", self.filename)))
+            .ok()
+    }
+}
+
+/// One CSV line, quoted as needed.
+pub(crate) fn record<I: IntoIterator<Item = S>, S: AsRef<[u8]>>(fields: I) -> String {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(fields).expect("writing to a Vec can't fail");
+    String::from_utf8(writer.into_inner().expect("writing to a Vec can't fail")).expect("fields are UTF-8")
+}
+
+fn date(date: Option<NaiveDate>) -> String {
+    date.map(|d| d.to_string()).unwrap_or_default()
+}
+
+fn name(names: &HashMap<i32, String>, id: i32) -> String {
+    names.get(&id).cloned().unwrap_or_else(|| id.to_string())
+}
+
+/// One row per split, so each expenditure takes as many rows as it has
+/// shares.
+pub fn expenditures(db: &DatabaseConnection, names: HashMap<i32, String>, filter: HistoryFilter) -> impl Stream<Item = String> + Send + '_ {
+    rocket::response::stream::stream! {
+        yield record(["expenditure_id", "date", "description", "spender", "amount", "user", "share"]);
+        let rows = match Query::stream_expenditure_splits(db, &filter).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("Expenditure export failed: {:?}", e);
+                return;
+            }
+        };
+        let mut rows = Box::pin(rows);
+        while let Some(row) = rows.next().await {
+            match row {
+                Ok(row) => {
+                    yield record([
+                        row.expenditure_id.to_string(),
+                        date(row.date),
+                        row.description.unwrap_or_default(),
+                        name(&names, row.spender_id),
+                        row.amount.to_decimal_string(),
+                        name(&names, row.user_id),
+                        row.share.to_decimal_string(),
+                    ]);
+                }
+                Err(e) => {
+                    error!("Expenditure export failed: {:?}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+pub fn transfers(db: &DatabaseConnection, names: HashMap<i32, String>, filter: HistoryFilter) -> impl Stream<Item = String> + Send + '_ {
+    rocket::response::stream::stream! {
+        yield record(["transfer_id", "date", "description", "from", "to", "amount"]);
+        let rows = match Query::stream_transfers(db, &filter).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("Transfer export failed: {:?}", e);
+                return;
+            }
+        };
+        let mut rows = Box::pin(rows);
+        while let Some(row) = rows.next().await {
+            match row {
+                Ok(row) => {
+                    yield record([
+                        row.id.to_string(),
+                        date(row.date),
+                        row.description.unwrap_or_default(),
+                        name(&names, row.debtor_id),
+                        name(&names, row.creditor_id),
+                        row.amount.to_decimal_string(),
+                    ]);
+                }
+                Err(e) => {
+                    error!("Transfer export failed: {:?}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// A line of a statement, before the running balance is added.
+struct Entry {
+    date: Option<NaiveDate>,
+    kind: &'static str,
+    id: i32,
+    description: String,
+    /// How much this adds to what the user owes.
+    change: Currency,
+}
+
+impl Entry {
+    fn from_expenditure(e: ExpenditureDisplay) -> Self {
+        let paid = if e.mine { e.amount.clone() } else { 0.into() };
+        Entry {
+            date: e.date,
+            kind: "expenditure",
+            id: e.id,
+            description: e.description.unwrap_or_default(),
+            change: e.share_amount - paid,
+        }
+    }
+
+    fn from_transfer(t: transfer::Model, user_id: i32) -> Self {
+        let received = if t.creditor_id == user_id { t.amount.clone() } else { 0.into() };
+        let sent = if t.debtor_id == user_id { t.amount } else { 0.into() };
+        Entry {
+            date: t.date,
+            kind: "transfer",
+            id: t.id,
+            description: t.description.unwrap_or_default(),
+            change: received - sent,
+        }
+    }
+}
+
+/// The order the queries return rows in: by date with undated ones last,
+/// expenditures before transfers on the same day.
+fn entry_order(a: &Entry, b: &Entry) -> Ordering {
+    (a.date.is_none(), a.date, a.kind, a.id).cmp(&(b.date.is_none(), b.date, b.kind, b.id))
+}
+
+/// Everything that changed what `user_id` owes, with a running balance.
+/// Entries before `from` are summed into an opening balance line. Only the
+/// filter's dates apply, since leaving entries out would throw the balance
+/// off.
+pub fn statement(db: &DatabaseConnection, user_id: i32, filter: HistoryFilter) -> impl Stream<Item = String> + Send + '_ {
+    rocket::response::stream::stream! {
+        yield record(["date", "type", "id", "description", "change", "balance"]);
+        let streams = match (
+            Query::stream_my_expenditures(db, user_id, filter.to).await,
+            Query::stream_transfers(db, &HistoryFilter { to: filter.to, user_id: Some(user_id), ..Default::default() }).await,
+        ) {
+            (Ok(expenditures), Ok(transfers)) => (expenditures, transfers),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Statement export failed: {:?}", e);
+                return;
+            }
+        };
+        let mut expenditures = Box::pin(streams.0.map(|e| e.map(Entry::from_expenditure)));
+        let mut transfers = Box::pin(streams.1.map(|t| t.map(|t| Entry::from_transfer(t, user_id))));
+        let mut next_expenditure = expenditures.next().await;
+        let mut next_transfer = transfers.next().await;
+        let mut balance = Currency::from(0);
+        let mut opened = filter.from.is_none();
+        loop {
+            let take_expenditure = match (&next_expenditure, &next_transfer) {
+                (Some(Ok(e)), Some(Ok(t))) => entry_order(e, t) != Ordering::Greater,
+                (Some(_), _) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let entry = if take_expenditure {
+                std::mem::replace(&mut next_expenditure, expenditures.next().await)
+            } else {
+                std::mem::replace(&mut next_transfer, transfers.next().await)
+            };
+            let entry = match entry {
+                Some(Ok(entry)) => entry,
+                Some(Err(e)) => {
+                    error!("Statement export failed: {:?}", e);
+                    return;
+                }
+                None => break,
+            };
+            let before_range = match (filter.from, entry.date) {
+                (Some(from), Some(date)) => date < from,
+                _ => false,
+            };
+            if !before_range && !opened {
+                yield record([date(filter.from), "opening".to_string(), String::new(), "Opening balance".to_string(), String::new(), balance.to_decimal_string()]);
+                opened = true;
+            }
+            balance = balance + entry.change.clone();
+            if !before_range {
+                yield record([
+                    date(entry.date),
+                    entry.kind.to_string(),
+                    entry.id.to_string(),
+                    entry.description,
+                    entry.change.to_decimal_string(),
+                    balance.to_decimal_string(),
+                ]);
+            }
+        }
+        if !opened {
+            yield record([date(filter.from), "opening".to_string(), String::new(), "Opening balance".to_string(), String::new(), balance.to_decimal_string()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_quoted() {
+        assert_eq!(record(["1", "Milk, eggs", "say \"hi\""]), "1,\"Milk, eggs\",\"say \"\"hi\"\"\"\n");
+    }
+
+    #[test]
+    fn entries_order_by_date_then_kind() {
+        let entry = |date: Option<(i32, u32, u32)>, kind, id| Entry {
+            date: date.map(|(y, m, d)| NaiveDate::from_ymd_opt(y, m, d).unwrap()),
+            kind,
+            id,
+            description: String::new(),
+            change: 0.into(),
+        };
+        let mut entries = vec![
+            entry(None, "expenditure", 1),
+            entry(Some((2026, 2, 1)), "transfer", 2),
+            entry(Some((2026, 2, 1)), "expenditure", 9),
+            entry(Some((2026, 1, 1)), "transfer", 3),
+        ];
+        entries.sort_by(entry_order);
+        let order: Vec<_> = entries.iter().map(|e| (e.kind, e.id)).collect();
+        assert_eq!(order, vec![("transfer", 3), ("expenditure", 9), ("transfer", 2), ("expenditure", 1)]);
+    }
+
+    #[test]
+    fn empty_boxes_are_no_filter() {
+        let filter = Filter {
+            mine: true,
+            min: Some(" 12.50 ".to_string()),
+            max: Some(String::new()),
+            search: Some("  ".to_string()),
+            tag: Some(" car ".to_string()),
+            ..Default::default()
+        };
+        let history = filter.history(7);
+        assert_eq!(history.user_id, Some(7));
+        assert_eq!((history.min_amount, history.max_amount), (Some(1250.into()), None));
+        assert_eq!(history.search, None);
+        assert_eq!(history.tag.as_deref(), Some("car"));
+        // An explicit user wins over "mine".
+        assert_eq!(Filter { user: Some(3), mine: true, ..Default::default() }.history(7).user_id, Some(3));
+        assert_eq!(Filter::default().history(7).user_id, None);
+    }
+}
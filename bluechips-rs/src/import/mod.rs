@@ -0,0 +1,355 @@
+//! Turning bank statements into expenditures.
+//!
+//! A statement is read into `StatementRow`s using a saved
+//! `import_mapping` that says which columns hold the date, amount and
+//! description. OFX and CAMT.053 statements say all that themselves, and
+//! are read without a mapping. Each row gets a fingerprint so that importing
+//! an overlapping statement later skips whatever was already brought in.
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::NaiveDate;
+use sha2::{Digest, Sha256};
+
+use crate::entities::{import_mapping, prelude::*, user};
+
+mod camt;
+mod ofx;
+pub mod splitwise;
+
+/// Who a statement row is split between, until someone edits it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitPreset {
+    /// Evenly between residents, like a new expenditure.
+    Residents,
+    Everyone,
+    /// Entirely one user's; the spender's own purchase or one to charge back.
+    Only(i32),
+}
+
+impl SplitPreset {
+    /// The presets to offer, with their labels.
+    pub fn choices(users: &[user::Model]) -> Vec<(SplitPreset, String)> {
+        let mut choices = vec![
+            (SplitPreset::Residents, "Residents evenly".to_string()),
+            (SplitPreset::Everyone, "Everyone evenly".to_string()),
+        ];
+        choices.extend(users.iter().map(|u| (
+            SplitPreset::Only(u.id),
+            format!("Only {}", u.name.as_ref().unwrap_or(&u.username)),
+        )));
+        choices
+    }
+
+    /// Shares in the form `Mutation::save_expenditure` takes.
+    pub fn splits(&self, users: &[user::Model]) -> HashMap<i32, Currency> {
+        users
+            .iter()
+            .filter(|u| match self {
+                SplitPreset::Residents => u.resident,
+                SplitPreset::Everyone => true,
+                SplitPreset::Only(id) => u.id == *id,
+            })
+            .map(|u| (u.id, Currency::from(100)))
+            .collect()
+    }
+}
+
+impl fmt::Display for SplitPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitPreset::Residents => write!(f, "residents"),
+            SplitPreset::Everyone => write!(f, "everyone"),
+            SplitPreset::Only(id) => write!(f, "user:{}", id),
+        }
+    }
+}
+
+impl std::str::FromStr for SplitPreset {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "residents" => Ok(SplitPreset::Residents),
+            "everyone" => Ok(SplitPreset::Everyone),
+            _ => s
+                .strip_prefix("user:")
+                .and_then(|id| id.parse().ok())
+                .map(SplitPreset::Only)
+                .ok_or_else(|| format!("unknown split preset {:?}", s)),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'v> rocket::form::FromFormField<'v> for SplitPreset {
+    fn from_value(field: rocket::form::ValueField<'v>) -> rocket::form::Result<'v, Self> {
+        field.value.parse().map_err(|e: String| rocket::form::Error::validation(e).into())
+    }
+}
+
+/// Date formats banks commonly use, with how to describe them.
+pub const DATE_FORMATS: &[(&str, &str)] = &[
+    ("%m/%d/%Y", "MM/DD/YYYY"),
+    ("%m/%d/%y", "MM/DD/YY"),
+    ("%Y-%m-%d", "YYYY-MM-DD"),
+    ("%d/%m/%Y", "DD/MM/YYYY"),
+    ("%d.%m.%Y", "DD.MM.YYYY"),
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum ImportError {
+    #[error("Couldn't read the file: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("The file has no column named \"{0}\".")]
+    MissingColumn(String),
+    #[error("Line {line}: \"{value}\" is not a date like {format}.")]
+    Date { line: u64, value: String, format: String },
+    #[error("Line {line}: \"{value}\" is not an amount.")]
+    Amount { line: u64, value: String },
+    #[error("Couldn't read the file: {0}")]
+    Xml(#[from] quick_xml::Error),
+    #[error("A transaction in the statement has no {0}.")]
+    Incomplete(&'static str),
+    #[error("\"{0}\" in the statement is not a valid {1}.")]
+    Invalid(String, &'static str),
+    #[error("Couldn't read the file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("The file isn't a Splitwise group export.")]
+    NotSplitwise,
+}
+
+/// The kinds of statement that can be imported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// Needs an `import_mapping` to say which column is which.
+    Csv,
+    /// OFX, including Quicken's QFX, in either the SGML or XML flavour.
+    Ofx,
+    Camt053,
+}
+
+impl Format {
+    fn detect(data: &str) -> Format {
+        let start: String = data.chars().take(4096).collect::<String>().to_uppercase();
+        if start.contains("OFXHEADER") || start.contains("<OFX>") {
+            Format::Ofx
+        } else if start.contains("CAMT.053") || start.contains("<BKTOCSTMRSTMT") {
+            Format::Camt053
+        } else {
+            Format::Csv
+        }
+    }
+}
+
+/// Read an OFX or CAMT.053 statement, which identify each transaction
+/// themselves. `None` for anything else, which is taken to be CSV and needs
+/// a mapping.
+pub fn parse_structured(data: &str) -> Option<Result<Vec<StatementRow>, ImportError>> {
+    match Format::detect(data) {
+        Format::Ofx => Some(ofx::parse(data)),
+        Format::Camt053 => Some(camt::parse(data)),
+        Format::Csv => None,
+    }
+}
+
+/// One transaction from a statement, ready to become an expenditure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatementRow {
+    pub date: NaiveDate,
+    /// Positive for money spent.
+    pub amount: Currency,
+    pub description: String,
+    pub fingerprint: String,
+}
+
+fn reader(data: &str) -> csv::Reader<&[u8]> {
+    csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes())
+}
+
+/// The header row and the first `limit` rows, for picking columns.
+pub fn sample(data: &str, limit: usize) -> Result<(Vec<String>, Vec<Vec<String>>), ImportError> {
+    let mut reader = reader(data);
+    let headers = reader.headers()?.iter().map(str::to_string).collect();
+    let rows = reader
+        .records()
+        .take(limit)
+        .map(|r| r.map(|r| r.iter().map(str::to_string).collect()))
+        .collect::<Result<_, _>>()?;
+    Ok((headers, rows))
+}
+
+/// The first header that looks like one of `names`, ignoring case.
+pub fn guess_column(headers: &[String], names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| headers.iter().find(|h| h.to_lowercase().contains(name)))
+        .cloned()
+}
+
+/// Amounts as banks write them: `$1,234.56`, `-12.00` or `(12.00)`.
+pub fn parse_amount(value: &str) -> Option<Currency> {
+    let value = value.trim();
+    let (negative, value) = match value.strip_prefix('(').and_then(|v| v.strip_suffix(')')) {
+        Some(inner) => (true, inner),
+        None => (false, value),
+    };
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (!negative, rest),
+        None => (negative, value),
+    };
+    let value: String = value.chars().filter(|c| !matches!(c, '$' | ',' | ' ')).collect();
+    if value.is_empty() {
+        return None;
+    }
+    let amount = Currency::try_from(value.as_str()).ok()?;
+    Some(if negative { -amount } else { amount })
+}
+
+/// Identifies a row across uploads. `source` is the bank or account the
+/// `parts` are unique within.
+pub fn fingerprint(source: &str, parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(source.as_bytes());
+    for part in parts {
+        hasher.update([0]);
+        hasher.update(part.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Fingerprints for rows without a transaction ID, made from what the row
+/// says. Identical rows in one file, like two coffees on the same day, are
+/// told apart by how many came before.
+struct ContentFingerprints {
+    source: String,
+    seen: HashMap<(NaiveDate, i32, String), usize>,
+}
+
+impl ContentFingerprints {
+    fn new(source: String) -> Self {
+        Self { source, seen: HashMap::new() }
+    }
+
+    fn next(&mut self, date: NaiveDate, amount: &Currency, description: &str) -> String {
+        let occurrence = self.seen.entry((date, i32::from(amount.clone()), description.to_string())).or_default();
+        *occurrence += 1;
+        fingerprint(&self.source, &[
+            &date.to_string(),
+            &amount.to_decimal_string(),
+            description,
+            &occurrence.to_string(),
+        ])
+    }
+}
+
+/// Read a CSV statement with `mapping`. Rows without a date or amount, like
+/// the balance lines some banks add, are skipped.
+pub fn parse_csv(data: &str, mapping: &import_mapping::Model) -> Result<Vec<StatementRow>, ImportError> {
+    let mut reader = reader(data);
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers
+        .iter()
+        .position(|h| h == name)
+        .ok_or_else(|| ImportError::MissingColumn(name.to_string()));
+    let (date_column, amount_column, description_column) = (
+        column(&mapping.date_column)?,
+        column(&mapping.amount_column)?,
+        column(&mapping.description_column)?,
+    );
+    let mut fingerprints = ContentFingerprints::new(mapping.name.clone());
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |p| p.line());
+        let (date, amount) = match (record.get(date_column), record.get(amount_column)) {
+            (Some(date), Some(amount)) if !date.is_empty() && !amount.is_empty() => (date, amount),
+            _ => continue,
+        };
+        let date = NaiveDate::parse_from_str(date, &mapping.date_format).map_err(|_| ImportError::Date {
+            line,
+            value: date.to_string(),
+            format: mapping.date_format.clone(),
+        })?;
+        let amount = parse_amount(amount).ok_or_else(|| ImportError::Amount { line, value: amount.to_string() })?;
+        let amount = if mapping.negate { -amount } else { amount };
+        let description = record.get(description_column).unwrap_or_default().to_string();
+        rows.push(StatementRow {
+            fingerprint: fingerprints.next(date, &amount, &description),
+            date,
+            amount,
+            description,
+        });
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping() -> import_mapping::Model {
+        import_mapping::Model {
+            id: 1,
+            name: "Card".to_string(),
+            date_column: "Posted Date".to_string(),
+            amount_column: "Amount".to_string(),
+            description_column: "Payee".to_string(),
+            date_format: "%m/%d/%Y".to_string(),
+            negate: true,
+            default_split: "residents".to_string(),
+        }
+    }
+
+    #[test]
+    fn amounts_in_bank_formats() {
+        assert_eq!(parse_amount("$1,234.56"), Some(Currency::from(123456)));
+        assert_eq!(parse_amount("-12.00"), Some(Currency::from(-1200)));
+        assert_eq!(parse_amount("(7.5)"), Some(Currency::from(-750)));
+        assert_eq!(parse_amount(""), None);
+        assert_eq!(parse_amount("n/a"), None);
+    }
+
+    #[test]
+    fn csv_rows_are_mapped_and_fingerprinted() {
+        let data = "Posted Date,Payee,Amount\n\
+            10/01/2026,Grocery Co,-54.20\n\
+            10/02/2026,\"Coffee, Inc\",-3.00\n\
+            10/02/2026,\"Coffee, Inc\",-3.00\n\
+            10/03/2026,Payment - thank you,120.00\n\
+            ,Ending balance,\n";
+        let rows = parse_csv(data, &mapping()).unwrap();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0].date, NaiveDate::from_ymd_opt(2026, 10, 1).unwrap());
+        assert_eq!(rows[0].amount, Currency::from(5420));
+        assert_eq!(rows[1].description, "Coffee, Inc");
+        assert_ne!(rows[1].fingerprint, rows[2].fingerprint);
+        assert_eq!(rows[3].amount, Currency::from(-12000));
+        assert_eq!(parse_csv(data, &mapping()).unwrap(), rows);
+    }
+
+    #[test]
+    fn bad_dates_name_the_line() {
+        let err = parse_csv("Posted Date,Payee,Amount\n2026-10-01,Grocery Co,-54.20\n", &mapping()).unwrap_err();
+        assert_eq!(err.to_string(), "Line 2: \"2026-10-01\" is not a date like %m/%d/%Y.");
+        assert!(matches!(parse_csv("Date,Payee,Amount\n", &mapping()), Err(ImportError::MissingColumn(_))));
+    }
+
+    #[test]
+    fn formats_are_detected() {
+        assert_eq!(Format::detect("OFXHEADER:100\nDATA:OFXSGML\n\n<OFX>"), Format::Ofx);
+        assert_eq!(Format::detect("<?xml version=\"1.0\"?>\n<Document xmlns=\"urn:iso:std:iso:20022:tech:xsd:camt.053.001.02\">"), Format::Camt053);
+        assert_eq!(Format::detect("Date,Description,Amount\n"), Format::Csv);
+        assert!(parse_structured("Date,Description,Amount\n").is_none());
+    }
+
+    #[test]
+    fn presets_round_trip() {
+        for preset in [SplitPreset::Residents, SplitPreset::Everyone, SplitPreset::Only(3)] {
+            assert_eq!(preset.to_string().parse(), Ok(preset));
+        }
+        assert!("user:x".parse::<SplitPreset>().is_err());
+    }
+}
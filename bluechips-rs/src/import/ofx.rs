@@ -0,0 +1,156 @@
+//! OFX statements, as most US banks and Quicken (QFX) export them.
+//!
+//! OFX 1.x is SGML where leaf elements are never closed, and 2.x is XML.
+//! Both are read as a flat run of tags, which is all a statement needs.
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+
+use super::{fingerprint, parse_amount, ImportError, StatementRow};
+
+/// `(tag, text following it)` pairs in document order. Closing tags keep
+/// their `/`.
+fn tokens(body: &str) -> impl Iterator<Item = (&str, &str)> {
+    body.split('<').skip(1).filter_map(|part| {
+        let (tag, text) = part.split_once('>')?;
+        Some((tag.trim(), text.trim()))
+    })
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+fn row(account: &str, fields: &HashMap<&str, String>) -> Result<StatementRow, ImportError> {
+    let field = |name: &'static str| fields
+        .get(name)
+        .filter(|v| !v.is_empty())
+        .ok_or(ImportError::Incomplete(name));
+    let posted = field("DTPOSTED")?;
+    // Like 20261001120000.000[-5:EST]; only the day matters here.
+    let date = posted
+        .get(..8)
+        .and_then(|d| NaiveDate::parse_from_str(d, "%Y%m%d").ok())
+        .ok_or_else(|| ImportError::Invalid(posted.clone(), "date"))?;
+    let amount = field("TRNAMT")?;
+    // Some European banks write a decimal comma.
+    let normalized = if amount.contains(',') && !amount.contains('.') { amount.replace(',', ".") } else { amount.clone() };
+    let amount = parse_amount(&normalized).ok_or_else(|| ImportError::Invalid(amount.clone(), "amount"))?;
+    let fitid = field("FITID")?;
+    let name = fields.get("NAME").map_or("", String::as_str);
+    let memo = fields.get("MEMO").map_or("", String::as_str);
+    let description = match (name, memo) {
+        ("", memo) => memo.to_string(),
+        (name, "") => name.to_string(),
+        (name, memo) if name == memo => name.to_string(),
+        (name, memo) => format!("{} - {}", name, memo),
+    };
+    Ok(StatementRow {
+        date,
+        // OFX amounts are from the account's side, so purchases are negative.
+        amount: -amount,
+        description,
+        fingerprint: fingerprint(&format!("ofx:{}", account), &[fitid]),
+    })
+}
+
+/// Every `STMTTRN`, deduplicated later on its `FITID`, which the bank keeps
+/// unique within the account.
+pub(super) fn parse(data: &str) -> Result<Vec<StatementRow>, ImportError> {
+    let body = data.find("<OFX>").map_or(data, |i| &data[i..]);
+    let mut account = String::new();
+    let mut transaction: Option<HashMap<&str, String>> = None;
+    let mut rows = Vec::new();
+    for (tag, text) in tokens(body) {
+        match tag {
+            "STMTTRN" => transaction = Some(HashMap::new()),
+            "/STMTTRN" => {
+                if let Some(fields) = transaction.take() {
+                    rows.push(row(&account, &fields)?);
+                }
+            }
+            // Transfers name the other account inside the transaction.
+            "ACCTID" if transaction.is_none() => account = unescape(text),
+            _ if tag.starts_with('/') => {}
+            _ => {
+                if let Some(fields) = &mut transaction {
+                    fields.insert(tag, unescape(text));
+                }
+            }
+        }
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::prelude::Currency;
+
+    const SGML: &str = "OFXHEADER:100
+DATA:OFXSGML
+VERSION:102
+
+<OFX>
+<CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS>
+<CCACCTFROM><ACCTID>4111000011112222</CCACCTFROM>
+<BANKTRANLIST>
+<DTSTART>20261001
+<STMTTRN>
+<TRNTYPE>DEBIT
+<DTPOSTED>20261002120000[-5:EST]
+<TRNAMT>-54.20
+<FITID>2026100201
+<NAME>GROCERY CO
+<MEMO>Store #12
+</STMTTRN>
+<STMTTRN>
+<TRNTYPE>CREDIT
+<DTPOSTED>20261005
+<TRNAMT>120.00
+<FITID>2026100502
+<NAME>PAYMENT - THANK YOU
+</STMTTRN>
+</BANKTRANLIST>
+</CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1>
+</OFX>
+";
+
+    #[test]
+    fn reads_sgml_statements() {
+        let rows = parse(SGML).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].date, NaiveDate::from_ymd_opt(2026, 10, 2).unwrap());
+        assert_eq!(rows[0].amount, Currency::from(5420));
+        assert_eq!(rows[0].description, "GROCERY CO - Store #12");
+        assert_eq!(rows[0].fingerprint, fingerprint("ofx:4111000011112222", &["2026100201"]));
+        assert_eq!(rows[1].amount, Currency::from(-12000));
+    }
+
+    #[test]
+    fn reads_xml_statements() {
+        let xml = r#"<?xml version="1.0"?><?OFX OFXHEADER="200" VERSION="211"?>
+<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS>
+<BANKACCTFROM><BANKID>123</BANKID><ACCTID>987</ACCTID></BANKACCTFROM>
+<BANKTRANLIST><STMTTRN><DTPOSTED>20261003</DTPOSTED><TRNAMT>-3,50</TRNAMT><FITID>A1</FITID>
+<NAME>Coffee &amp; Co</NAME>
+<BANKACCTTO><ACCTID>555</ACCTID></BANKACCTTO></STMTTRN></BANKTRANLIST>
+</STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>"#;
+        let rows = parse(xml).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].amount, Currency::from(350));
+        assert_eq!(rows[0].description, "Coffee & Co");
+        assert_eq!(rows[0].fingerprint, fingerprint("ofx:987", &["A1"]));
+    }
+
+    #[test]
+    fn transactions_need_an_id() {
+        let err = parse("<OFX><STMTTRN><DTPOSTED>20261003<TRNAMT>-1.00</STMTTRN></OFX>").unwrap_err();
+        assert_eq!(err.to_string(), "A transaction in the statement has no FITID.");
+    }
+}
@@ -0,0 +1,408 @@
+//! Splitwise group exports.
+//!
+//! The CSV export has a column per member holding how much each expense
+//! changed their balance, positive for whoever paid, and a closing "Total
+//! balance" row. The JSON form is what Splitwise's API returns for a group's
+//! expenses, with each member's paid and owed share. Either way, everything
+//! is reduced to a single spender with exact shares, or a payment from one
+//! member to another; anything that doesn't fit is reported instead.
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::NaiveDate;
+use serde_json::Value;
+
+use super::{fingerprint, parse_amount, reader, ImportError};
+use crate::entities::prelude::*;
+use crate::service::{DateField, ExpenditureForm, TransferForm};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Entry {
+    Expense {
+        date: NaiveDate,
+        description: String,
+        cost: Currency,
+        paid_by: String,
+        /// Each member's share, adding up to `cost`.
+        shares: Vec<(String, Currency)>,
+    },
+    /// `from` paid `to`, which bluechips records as a transfer with `from`
+    /// as the debtor.
+    Payment {
+        date: NaiveDate,
+        description: String,
+        amount: Currency,
+        from: String,
+        to: String,
+    },
+}
+
+impl Entry {
+    /// Everything the entry says, for fingerprinting.
+    fn parts(&self) -> Vec<String> {
+        match self {
+            Entry::Expense { date, description, cost, paid_by, shares } => {
+                let mut parts = vec!["expense".to_string(), date.to_string(), description.clone(), cost.to_decimal_string(), paid_by.clone()];
+                for (member, share) in shares {
+                    parts.push(member.clone());
+                    parts.push(share.to_decimal_string());
+                }
+                parts
+            }
+            Entry::Payment { date, description, amount, from, to } => {
+                vec!["payment".to_string(), date.to_string(), description.clone(), amount.to_decimal_string(), from.clone(), to.clone()]
+            }
+        }
+    }
+}
+
+/// A row that can't be imported, and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Skipped {
+    /// Where it was, like `line 4` or `expense 123`.
+    pub row: String,
+    pub description: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Export {
+    /// In the order Splitwise lists them.
+    pub members: Vec<String>,
+    pub entries: Vec<Entry>,
+    pub skipped: Vec<Skipped>,
+    /// What Splitwise says each member's balance is at the end, positive
+    /// for people who are owed.
+    pub balances: BTreeMap<String, Currency>,
+}
+
+/// Read either form of export.
+pub fn parse(data: &str) -> Result<Export, ImportError> {
+    match data.trim_start().chars().next() {
+        Some('{') | Some('[') => parse_json(data),
+        _ => parse_csv(data),
+    }
+}
+
+/// Turn what a row did to each member's balance back into who paid and
+/// who owes what.
+fn from_changes(date: NaiveDate, description: String, payment: bool, cost: Currency, changes: &[(String, Currency)]) -> Result<Entry, String> {
+    if !changes.iter().map(|(_, change)| change).sum::<Currency>().is_zero() {
+        return Err("the members' amounts don't add up".to_string());
+    }
+    let gained: Vec<&(String, Currency)> = changes.iter().filter(|(_, c)| c.is_positive()).collect();
+    let lost: Vec<&(String, Currency)> = changes.iter().filter(|(_, c)| c.is_negative()).collect();
+    if payment {
+        return match (gained.as_slice(), lost.as_slice()) {
+            ([(from, amount)], [(to, _)]) => Ok(Entry::Payment { date, description, amount: amount.clone(), from: from.clone(), to: to.clone() }),
+            _ => Err("payments must be from one member to one other".to_string()),
+        };
+    }
+    let (paid_by, paid_by_change) = match gained.as_slice() {
+        [(paid_by, change)] => (paid_by, change),
+        [] => return Err("it doesn't change anyone's balance".to_string()),
+        _ => return Err("it was paid for by more than one member".to_string()),
+    };
+    let paid_by_share = cost.clone() - paid_by_change.clone();
+    if paid_by_share.is_negative() {
+        return Err("the payer's share is more than the cost".to_string());
+    }
+    let shares = changes
+        .iter()
+        .filter_map(|(member, change)| match member == paid_by {
+            true => Some((member.clone(), paid_by_share.clone())),
+            false => change.is_negative().then(|| (member.clone(), -change.clone())),
+        })
+        .filter(|(_, share)| !share.is_zero())
+        .collect();
+    Ok(Entry::Expense { date, description, cost, paid_by: paid_by.clone(), shares })
+}
+
+fn parse_csv(data: &str) -> Result<Export, ImportError> {
+    let mut reader = reader(data);
+    let headers = reader.headers()?.clone();
+    let column = |name: &'static str| headers.iter().position(|h| h == name).ok_or(ImportError::MissingColumn(name.to_string()));
+    let (date_column, description_column, category_column, cost_column, currency_column) =
+        (column("Date")?, column("Description")?, column("Category")?, column("Cost")?, column("Currency")?);
+    // Members come after the fixed columns.
+    let first_member = currency_column + 1;
+    let mut export = Export {
+        members: headers.iter().skip(first_member).map(str::to_string).collect(),
+        ..Default::default()
+    };
+    let mut currency: Option<String> = None;
+    let mut totals: BTreeMap<String, Currency> = BTreeMap::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |p| p.line());
+        let field = |i: usize| record.get(i).unwrap_or_default();
+        let description = field(description_column).to_string();
+        let changes: Vec<(String, Currency)> = export
+            .members
+            .iter()
+            .enumerate()
+            .map(|(i, member)| (member.clone(), parse_amount(field(first_member + i)).unwrap_or(0.into())))
+            .collect();
+        if description == "Total balance" && field(cost_column).is_empty() {
+            export.balances = changes.into_iter().collect();
+            continue;
+        }
+        if field(date_column).is_empty() {
+            continue;
+        }
+        let skip = |reason: String| Skipped { row: format!("line {}", line), description: description.clone(), reason };
+        let row_currency = field(currency_column).to_string();
+        if currency.get_or_insert_with(|| row_currency.clone()) != &row_currency {
+            export.skipped.push(skip(format!("it's in {} rather than {}", row_currency, currency.as_deref().unwrap_or_default())));
+            continue;
+        }
+        for (member, change) in &changes {
+            let total = totals.entry(member.clone()).or_insert(0.into());
+            *total = total.clone() + change.clone();
+        }
+        let Ok(date) = NaiveDate::parse_from_str(field(date_column), "%Y-%m-%d") else {
+            export.skipped.push(skip(format!("\"{}\" is not a date", field(date_column))));
+            continue;
+        };
+        let Some(cost) = parse_amount(field(cost_column)) else {
+            export.skipped.push(skip(format!("\"{}\" is not an amount", field(cost_column))));
+            continue;
+        };
+        let payment = field(category_column) == "Payment";
+        match from_changes(date, description.clone(), payment, cost, &changes) {
+            Ok(entry) => export.entries.push(entry),
+            Err(reason) => export.skipped.push(skip(reason)),
+        }
+    }
+    if export.balances.is_empty() {
+        export.balances = totals;
+    }
+    Ok(export)
+}
+
+fn member_name(user: &Value) -> String {
+    let name = [&user["first_name"], &user["last_name"]]
+        .iter()
+        .filter_map(|v| v.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    match name.trim() {
+        "" => format!("user {}", user["id"]),
+        name => name.to_string(),
+    }
+}
+
+fn json_amount(value: &Value) -> Option<Currency> {
+    match value {
+        Value::String(s) => parse_amount(s),
+        Value::Number(n) => parse_amount(&n.to_string()),
+        Value::Null => Some(0.into()),
+        _ => None,
+    }
+}
+
+fn parse_json(data: &str) -> Result<Export, ImportError> {
+    let value: Value = serde_json::from_str(data)?;
+    let expenses = match &value {
+        Value::Array(expenses) => expenses,
+        Value::Object(object) => object.get("expenses").and_then(Value::as_array).ok_or(ImportError::NotSplitwise)?,
+        _ => return Err(ImportError::NotSplitwise),
+    };
+    let mut export = Export::default();
+    let mut currency: Option<String> = None;
+    for expense in expenses {
+        if !expense["deleted_at"].is_null() {
+            continue;
+        }
+        let description = expense["description"].as_str().unwrap_or_default().to_string();
+        let skip = |reason: String| Skipped { row: format!("expense {}", expense["id"]), description: description.clone(), reason };
+        let mut changes = Vec::new();
+        let mut paid = Vec::new();
+        for user in expense["users"].as_array().into_iter().flatten() {
+            let member = member_name(&user["user"]);
+            if !export.members.contains(&member) {
+                export.members.push(member.clone());
+            }
+            let (Some(paid_share), Some(owed_share)) = (json_amount(&user["paid_share"]), json_amount(&user["owed_share"])) else {
+                continue;
+            };
+            let change = paid_share.clone() - owed_share;
+            let balance = export.balances.entry(member.clone()).or_insert(0.into());
+            *balance = balance.clone() + change.clone();
+            changes.push((member.clone(), change));
+            paid.push((member, paid_share));
+        }
+        let expense_currency = expense["currency_code"].as_str().unwrap_or_default().to_string();
+        if currency.get_or_insert_with(|| expense_currency.clone()) != &expense_currency {
+            export.skipped.push(skip(format!("it's in {} rather than {}", expense_currency, currency.as_deref().unwrap_or_default())));
+            continue;
+        }
+        let date = expense["date"]
+            .as_str()
+            .and_then(|d| d.get(..10))
+            .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok());
+        let (Some(date), Some(cost)) = (date, json_amount(&expense["cost"])) else {
+            export.skipped.push(skip("it has no date or cost".to_string()));
+            continue;
+        };
+        let payment = expense["payment"].as_bool().unwrap_or(false);
+        match from_changes(date, description.clone(), payment, cost, &changes) {
+            Ok(entry) => export.entries.push(entry),
+            Err(reason) => export.skipped.push(skip(reason)),
+        }
+    }
+    Ok(export)
+}
+
+impl Export {
+    /// The expenditures and transfers to create, with members replaced by
+    /// the users they were matched to. Rows that make no sense once members
+    /// are merged, like a payment to oneself, are added to `skipped`.
+    ///
+    /// Each form comes with a fingerprint of its entry, which is the same in
+    /// any later export of the group.
+    pub fn to_forms(&mut self, users: &HashMap<String, i32>) -> (Vec<(String, ExpenditureForm)>, Vec<(String, TransferForm)>) {
+        let mut expenditures = Vec::new();
+        let mut transfers = Vec::new();
+        let mut seen: HashMap<Vec<String>, usize> = HashMap::new();
+        for entry in &self.entries {
+            let (date, description) = match entry {
+                Entry::Expense { date, description, .. } | Entry::Payment { date, description, .. } => (date, description),
+            };
+            // Identical entries are told apart by how many came before.
+            let mut parts = entry.parts();
+            let occurrence = seen.entry(parts.clone()).or_default();
+            *occurrence += 1;
+            parts.push(occurrence.to_string());
+            let fingerprint = fingerprint("splitwise", &parts.iter().map(String::as_str).collect::<Vec<_>>());
+            let skip = |reason: String| Skipped { row: date.to_string(), description: description.clone(), reason };
+            let members: Vec<&String> = match entry {
+                Entry::Expense { paid_by, shares, .. } => std::iter::once(paid_by).chain(shares.iter().map(|(m, _)| m)).collect(),
+                Entry::Payment { from, to, .. } => vec![from, to],
+            };
+            if let Some(member) = members.iter().find(|m| !users.contains_key(**m)) {
+                self.skipped.push(skip(format!("{} isn't matched to a user", member)));
+                continue;
+            }
+            match entry {
+                Entry::Expense { cost, paid_by, shares, .. } => {
+                    let mut splits: HashMap<i32, Currency> = HashMap::new();
+                    for (member, share) in shares {
+                        let split = splits.entry(users[member]).or_insert(0.into());
+                        *split = split.clone() + share.clone();
+                    }
+                    expenditures.push((fingerprint, ExpenditureForm {
+                        spender_id: users[paid_by],
+                        amount: cost.clone(),
+                        description: description.clone(),
+                        date: DateField(*date),
+                        splits,
+                        category_id: None,
+                        tags: String::new(),
+                    }));
+                }
+                Entry::Payment { amount, from, to, .. } => {
+                    if users[from] == users[to] {
+                        self.skipped.push(skip(format!("{} and {} are the same user here", from, to)));
+                        continue;
+                    }
+                    transfers.push((fingerprint, TransferForm {
+                        debtor_id: users[from],
+                        creditor_id: users[to],
+                        amount: amount.clone(),
+                        description: description.clone(),
+                        date: DateField(*date),
+                    }));
+                }
+            }
+        }
+        (expenditures, transfers)
+    }
+
+    /// How much each user's debt, in `get_debts` terms, should change: the
+    /// opposite of their members' Splitwise balances.
+    pub fn expected_debt_changes(&self, users: &HashMap<String, i32>) -> BTreeMap<i32, Currency> {
+        let mut changes = BTreeMap::new();
+        for (member, balance) in &self.balances {
+            if let Some(user_id) = users.get(member) {
+                let change = changes.entry(*user_id).or_insert(Currency::from(0));
+                *change = change.clone() - balance.clone();
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "Date,Description,Category,Cost,Currency,Alice,Bob,Carol
+
+2026-10-01,Groceries,Groceries,90.00,USD,60.00,-30.00,-30.00
+2026-10-02,Dinner out,Dining out,40.00,USD,-25.00,40.00,-15.00
+2026-10-03,Bob paid Alice,Payment,15.00,USD,-15.00,15.00,0.00
+2026-10-04,Taxi,Taxi,20.00,USD,10.00,10.00,-20.00
+2026-10-05,Souvenir,General,30.00,EUR,-30.00,30.00,0.00
+
+2026-10-05,Total balance, , ,USD,30.00,35.00,-65.00
+";
+
+    #[test]
+    fn csv_rows_become_expenses_and_payments() {
+        let export = parse(CSV).unwrap();
+        assert_eq!(export.members, vec!["Alice", "Bob", "Carol"]);
+        assert_eq!(export.entries.len(), 3);
+        assert_eq!(export.entries[1], Entry::Expense {
+            date: NaiveDate::from_ymd_opt(2026, 10, 2).unwrap(),
+            description: "Dinner out".to_string(),
+            cost: 4000.into(),
+            paid_by: "Bob".to_string(),
+            shares: vec![("Alice".to_string(), 2500.into()), ("Carol".to_string(), 1500.into())],
+        });
+        assert!(matches!(&export.entries[2], Entry::Payment { from, to, .. } if from == "Bob" && to == "Alice"));
+        let reasons: Vec<_> = export.skipped.iter().map(|s| (s.row.as_str(), s.reason.as_str())).collect();
+        assert_eq!(reasons, vec![
+            ("line 6", "it was paid for by more than one member"),
+            ("line 7", "it's in EUR rather than USD"),
+        ]);
+        assert_eq!(export.balances["Carol"], Currency::from(-6500));
+    }
+
+    #[test]
+    fn json_shares_are_exact() {
+        let json = r#"{"expenses": [
+            {"id": 1, "description": "Rent", "cost": "1000.0", "currency_code": "USD", "date": "2026-10-01T00:00:00Z",
+             "payment": false, "deleted_at": null, "users": [
+                {"user": {"id": 7, "first_name": "Alice", "last_name": null}, "paid_share": "1000.0", "owed_share": "333.34"},
+                {"user": {"id": 8, "first_name": "Bob", "last_name": "Smith"}, "paid_share": "0.0", "owed_share": "333.33"},
+                {"user": {"id": 9, "first_name": "Carol", "last_name": ""}, "paid_share": "0.0", "owed_share": "333.33"}]},
+            {"id": 2, "description": "Old", "cost": "5.0", "currency_code": "USD", "date": "2026-10-02T00:00:00Z",
+             "payment": false, "deleted_at": "2026-10-03T00:00:00Z", "users": []},
+            {"id": 3, "description": "Settle", "cost": "333.33", "currency_code": "USD", "date": "2026-10-04T00:00:00Z",
+             "payment": true, "deleted_at": null, "users": [
+                {"user": {"id": 8, "first_name": "Bob", "last_name": "Smith"}, "paid_share": "333.33", "owed_share": "0.0"},
+                {"user": {"id": 7, "first_name": "Alice"}, "paid_share": "0.0", "owed_share": "333.33"}]}
+        ]}"#;
+        let mut export = parse(json).unwrap();
+        assert_eq!(export.members, vec!["Alice", "Bob Smith", "Carol"]);
+        assert!(export.skipped.is_empty());
+        let users = HashMap::from([("Alice".to_string(), 1), ("Bob Smith".to_string(), 2), ("Carol".to_string(), 3)]);
+        let (expenditures, transfers) = export.to_forms(&users);
+        assert_eq!(expenditures[0].1.splits, HashMap::from([(1, 33334.into()), (2, 33333.into()), (3, 33333.into())]));
+        assert_eq!((transfers[0].1.debtor_id, transfers[0].1.creditor_id), (2, 1));
+        assert_eq!(export.expected_debt_changes(&users), BTreeMap::from([(1, (-33333).into()), (2, 0.into()), (3, 33333.into())]));
+    }
+
+    #[test]
+    fn fingerprints_survive_a_later_export() {
+        let users = HashMap::from([("Alice".to_string(), 1), ("Bob".to_string(), 2), ("Carol".to_string(), 3)]);
+        let (first, _) = parse(CSV).unwrap().to_forms(&users);
+        // The same groceries again, bought later the same day.
+        let later = CSV.replace("\n\n2026-10-05,Total", "\n2026-10-01,Groceries,Groceries,90.00,USD,60.00,-30.00,-30.00\n\n2026-10-05,Total");
+        let (second, _) = parse(&later).unwrap().to_forms(&users);
+        let fingerprints = |forms: &[(String, ExpenditureForm)]| forms.iter().map(|(f, _)| f.clone()).collect::<Vec<_>>();
+        assert_eq!(fingerprints(&second)[..first.len()], fingerprints(&first)[..]);
+        assert_eq!(second.len(), first.len() + 1);
+        assert!(!fingerprints(&first).contains(&second[first.len()].0));
+    }
+}
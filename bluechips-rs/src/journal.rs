@@ -0,0 +1,411 @@
+//! Plain-text accounting journals, for reconciling against personal
+//! ledger, hledger or beancount books.
+//!
+//! A user's journal is their side of every expenditure and transfer they're
+//! part of: what they paid comes out of a cash account, their share is an
+//! expense, and what each other person owes them or is owed by them goes to
+//! a receivable or payable account named after that person. Together those
+//! accounts come to minus what `get_debts` says the user owes.
+//!
+//! The household journal instead keeps one receivable account per person,
+//! each of which comes to exactly what `get_debts` says they owe.
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use chrono::NaiveDate;
+use rocket::futures::TryStreamExt;
+use rocket::http::{ContentType, Header};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::Deserialize;
+use sea_orm::{DatabaseConnection, DbErr};
+
+use crate::entities::{prelude::*, transfer};
+use crate::service::{HistoryFilter, Query, SplitRow};
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Where a user's own shares go.
+    pub journal_expense_account: String,
+    /// What a user paid with or was paid into.
+    pub journal_cash_account: String,
+    /// Followed by `:` and the person who owes.
+    pub journal_receivable_account: String,
+    /// Followed by `:` and the person who is owed.
+    pub journal_payable_account: String,
+    pub journal_commodity: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            journal_expense_account: "Expenses:BlueChips".to_string(),
+            journal_cash_account: "Assets:Cash".to_string(),
+            journal_receivable_account: "Assets:Receivable".to_string(),
+            journal_payable_account: "Liabilities:Payable".to_string(),
+            journal_commodity: "USD".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, FromFormField)]
+pub enum Format {
+    /// Read by both ledger and hledger.
+    Ledger,
+    Beancount,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Ledger => "journal",
+            Format::Beancount => "beancount",
+        }
+    }
+}
+
+/// Whose books a journal is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum View {
+    User(i32),
+    Household,
+}
+
+/// An expenditure with its splits, put back together from `SplitRow`s.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expenditure {
+    pub id: i32,
+    pub date: Option<NaiveDate>,
+    pub description: String,
+    pub spender_id: i32,
+    pub amount: Currency,
+    pub shares: Vec<(i32, Currency)>,
+}
+
+/// Everything the journal for `filter` needs. Splits come back grouped by
+/// expenditure, which is what lets them be put together here.
+pub async fn load(db: &DatabaseConnection, filter: &HistoryFilter) -> Result<(Vec<Expenditure>, Vec<transfer::Model>), DbErr> {
+    let splits: Vec<SplitRow> = Query::stream_expenditure_splits(db, filter).await?.try_collect().await?;
+    let mut expenditures: Vec<Expenditure> = Vec::new();
+    for split in splits {
+        match expenditures.last_mut() {
+            Some(e) if e.id == split.expenditure_id => e.shares.push((split.user_id, split.share)),
+            _ => expenditures.push(Expenditure {
+                id: split.expenditure_id,
+                date: split.date,
+                description: split.description.unwrap_or_default(),
+                spender_id: split.spender_id,
+                amount: split.amount,
+                shares: vec![(split.user_id, split.share)],
+            }),
+        }
+    }
+    let transfers = Query::stream_transfers(db, filter).await?.try_collect().await?;
+    Ok((expenditures, transfers))
+}
+
+/// Journals need a date; records without one go first with this one.
+fn undated() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).expect("valid date")
+}
+
+struct Transaction {
+    date: Option<NaiveDate>,
+    /// Like `E12` or `T3`, so entries can be traced back.
+    code: String,
+    description: String,
+    postings: Vec<(String, Currency)>,
+}
+
+/// A name as one component of an account: letters, digits and dashes,
+/// starting with a capital, which suits ledger and beancount alike.
+fn component(name: &str, id: i32) -> String {
+    let words: Vec<&str> = name.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect();
+    let mut component = words.join("-");
+    match component.chars().next() {
+        Some(first) if first.is_alphabetic() => {
+            component.replace_range(..first.len_utf8(), &first.to_uppercase().to_string());
+            component
+        }
+        Some(first) if first.is_ascii_digit() => component,
+        _ => format!("User{}", id),
+    }
+}
+
+/// Postings with the same account are combined and zeroes dropped.
+fn combine(postings: Vec<(String, Currency)>) -> Vec<(String, Currency)> {
+    let mut combined: Vec<(String, Currency)> = Vec::new();
+    for (account, amount) in postings {
+        match combined.iter_mut().find(|(a, _)| *a == account) {
+            Some((_, total)) => *total = total.clone() + amount,
+            None => combined.push((account, amount)),
+        }
+    }
+    combined.retain(|(_, amount)| !amount.is_zero());
+    combined
+}
+
+fn transactions(view: View, config: &Config, names: &HashMap<i32, String>, expenditures: &[Expenditure], transfers: &[transfer::Model]) -> Vec<Transaction> {
+    let person = |id: i32| component(names.get(&id).map_or("", String::as_str), id);
+    let receivable = |id: i32| format!("{}:{}", config.journal_receivable_account, person(id));
+    let payable = |id: i32| format!("{}:{}", config.journal_payable_account, person(id));
+    let mut transactions = Vec::new();
+    for e in expenditures {
+        let postings = match view {
+            View::Household => e.shares
+                .iter()
+                .map(|(user_id, share)| (receivable(*user_id), share.clone()))
+                .chain([(receivable(e.spender_id), -e.amount.clone())])
+                .collect(),
+            View::User(me) if me == e.spender_id => e.shares
+                .iter()
+                .map(|(user_id, share)| if *user_id == me {
+                    (config.journal_expense_account.clone(), share.clone())
+                } else {
+                    (receivable(*user_id), share.clone())
+                })
+                .chain([(config.journal_cash_account.clone(), -e.amount.clone())])
+                .collect(),
+            View::User(me) => e.shares
+                .iter()
+                .filter(|(user_id, _)| *user_id == me)
+                .flat_map(|(_, share)| [
+                    (config.journal_expense_account.clone(), share.clone()),
+                    (payable(e.spender_id), -share.clone()),
+                ])
+                .collect(),
+        };
+        transactions.push(Transaction {
+            date: e.date,
+            code: format!("E{}", e.id),
+            description: e.description.clone(),
+            postings: combine(postings),
+        });
+    }
+    for t in transfers {
+        let amount = t.amount.clone();
+        let postings = match view {
+            View::Household => vec![(receivable(t.debtor_id), -amount.clone()), (receivable(t.creditor_id), amount)],
+            View::User(me) if me == t.debtor_id => vec![(payable(t.creditor_id), amount.clone()), (config.journal_cash_account.clone(), -amount)],
+            View::User(me) if me == t.creditor_id => vec![(config.journal_cash_account.clone(), amount.clone()), (receivable(t.debtor_id), -amount)],
+            View::User(_) => vec![],
+        };
+        transactions.push(Transaction {
+            date: t.date,
+            code: format!("T{}", t.id),
+            description: t.description.clone().unwrap_or_default(),
+            postings: combine(postings),
+        });
+    }
+    transactions.retain(|t| !t.postings.is_empty());
+    transactions.sort_by_key(|t| (t.date.unwrap_or_else(undated), t.code.starts_with('T'), t.code[1..].parse::<i32>().unwrap_or(0)));
+    transactions
+}
+
+/// The whole journal, with every account declared up front so it passes
+/// `hledger check accounts` and beancount's checks.
+pub fn render(format: Format, view: View, config: &Config, names: &HashMap<i32, String>, expenditures: &[Expenditure], transfers: &[transfer::Model]) -> String {
+    let transactions = transactions(view, config, names, expenditures, transfers);
+    let accounts: BTreeSet<&str> = transactions.iter().flat_map(|t| t.postings.iter().map(|(a, _)| a.as_str())).collect();
+    let commodity = &config.journal_commodity;
+    let title = match view {
+        View::User(id) => format!("{}'s side of BlueChips", names.get(&id).map_or("someone", String::as_str)),
+        View::Household => "BlueChips, everyone's balances".to_string(),
+    };
+    let mut out = String::new();
+    match format {
+        Format::Ledger => {
+            writeln!(out, "; {}", title).unwrap();
+            writeln!(out, "commodity {}", commodity).unwrap();
+            for account in &accounts {
+                writeln!(out, "account {}", account).unwrap();
+            }
+        }
+        Format::Beancount => {
+            writeln!(out, "; {}", title).unwrap();
+            writeln!(out, "option \"operating_currency\" \"{}\"", commodity).unwrap();
+            for account in &accounts {
+                writeln!(out, "{} open {} {}", undated(), account, commodity).unwrap();
+            }
+        }
+    }
+    for t in transactions {
+        let date = t.date.unwrap_or_else(undated);
+        let description = if t.description.trim().is_empty() {
+            if t.code.starts_with('E') { "Expenditure" } else { "Transfer" }.to_string()
+        } else {
+            t.description.split_whitespace().collect::<Vec<_>>().join(" ")
+        };
+        out.push('\n');
+        match format {
+            Format::Ledger => {
+                // `;` would start a comment in the middle of the description.
+                writeln!(out, "{} ({}) {}", date, t.code, description.replace(';', ",")).unwrap();
+                if t.date.is_none() {
+                    writeln!(out, "    ; undated in BlueChips").unwrap();
+                }
+            }
+            Format::Beancount => {
+                writeln!(out, "{} * \"{}\"", date, description.replace('\\', "\\\\").replace('"', "\\\"")).unwrap();
+                writeln!(out, "  bluechips: \"{}\"", t.code).unwrap();
+            }
+        }
+        for (account, amount) in t.postings {
+            writeln!(out, "    {:<40}  {:>12} {}", account, amount.to_decimal_string(), commodity).unwrap();
+        }
+    }
+    out
+}
+
+/// A journal, offered as a download.
+pub struct Download {
+    pub filename: String,
+    pub body: String,
+}
+
+impl<'r> Responder<'r, 'static> for Download {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        Response::build_from(self.body.respond_to(request)?)
+            .header(ContentType::Plain)
+            .header(Header::new("Content-Disposition", format!("attachment; filename=\"{}\"", self.filename)))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::import::parse_amount;
+
+    /// Just enough of a journal reader to check what `render` wrote: every
+    /// transaction balances, every account was declared, and the totals.
+    fn read_back(journal: &str) -> BTreeMap<String, Currency> {
+        let mut declared = BTreeSet::new();
+        let mut totals: BTreeMap<String, Currency> = BTreeMap::new();
+        let mut open: Option<Currency> = None;
+        let close = |open: &mut Option<Currency>| {
+            if let Some(sum) = open.take() {
+                assert!(sum.is_zero(), "transaction doesn't balance: {}", sum);
+            }
+        };
+        for line in journal.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => close(&mut open),
+                ["account", account] | [_, "open", account, _] => {
+                    declared.insert(account.to_string());
+                }
+                [date, ..] if date.parse::<NaiveDate>().is_ok() => {
+                    close(&mut open);
+                    open = Some(0.into());
+                }
+                [account, amount, "USD"] if line.starts_with("    ") => {
+                    assert!(declared.contains(*account), "{} wasn't declared", account);
+                    let amount = parse_amount(amount).unwrap();
+                    let sum = open.as_mut().expect("posting outside a transaction");
+                    *sum = sum.clone() + amount.clone();
+                    let total = totals.entry(account.to_string()).or_insert(0.into());
+                    *total = total.clone() + amount;
+                }
+                _ => {}
+            }
+        }
+        close(&mut open);
+        totals
+    }
+
+    fn names() -> HashMap<i32, String> {
+        HashMap::from([(1, "alice".to_string()), (2, "Bob Smith".to_string()), (3, "Carol".to_string())])
+    }
+
+    fn history() -> (Vec<Expenditure>, Vec<transfer::Model>) {
+        let expenditures = vec![
+            Expenditure {
+                id: 1,
+                date: NaiveDate::from_ymd_opt(2026, 10, 2),
+                description: "Groceries; \"bulk\"".to_string(),
+                spender_id: 1,
+                amount: 9000.into(),
+                shares: vec![(1, 3000.into()), (2, 3000.into()), (3, 3000.into())],
+            },
+            Expenditure {
+                id: 2,
+                date: None,
+                description: String::new(),
+                spender_id: 2,
+                amount: 2000.into(),
+                shares: vec![(1, 1000.into()), (2, 1000.into()), (3, 0.into())],
+            },
+        ];
+        let transfers = vec![transfer::Model {
+            id: 1,
+            debtor_id: 3,
+            creditor_id: 1,
+            amount: 1500.into(),
+            description: Some("Venmo".to_string()),
+            date: NaiveDate::from_ymd_opt(2026, 10, 3),
+            entered_time: None,
+        }];
+        (expenditures, transfers)
+    }
+
+    fn balance(totals: &BTreeMap<String, Currency>, prefixes: &[&str]) -> Currency {
+        totals.iter().filter(|(a, _)| prefixes.iter().any(|p| a.starts_with(p))).map(|(_, v)| v).sum()
+    }
+
+    #[test]
+    fn user_journals_balance_and_reconcile() {
+        let (expenditures, transfers) = history();
+        let config = Config::default();
+        // What get_debts would say each user owes.
+        let debts = [(1, Currency::from(-3500)), (2, Currency::from(2000)), (3, Currency::from(1500))];
+        for format in [Format::Ledger, Format::Beancount] {
+            for (user_id, owes) in &debts {
+                let journal = render(format, View::User(*user_id), &config, &names(), &expenditures, &transfers);
+                let totals = read_back(&journal);
+                assert_eq!(balance(&totals, &["Assets:Receivable", "Liabilities:Payable"]), -owes.clone(), "{}", journal);
+            }
+        }
+        let alice = read_back(&render(Format::Ledger, View::User(1), &config, &names(), &expenditures, &transfers));
+        assert_eq!(alice["Assets:Receivable:Bob-Smith"], Currency::from(3000));
+        assert_eq!(alice["Assets:Receivable:Carol"], Currency::from(1500));
+        assert_eq!(alice["Liabilities:Payable:Bob-Smith"], Currency::from(-1000));
+        assert_eq!(alice["Expenses:BlueChips"], Currency::from(4000));
+        assert_eq!(alice["Assets:Cash"], Currency::from(-7500));
+    }
+
+    #[test]
+    fn user_journals_only_have_their_postings() {
+        let (expenditures, transfers) = history();
+        let journal = render(Format::Ledger, View::User(3), &Config::default(), &names(), &expenditures, &transfers);
+        assert!(journal.contains("2026-10-02 (E1) Groceries, \"bulk\""));
+        assert!(!journal.contains("(E2)"));
+        assert!(!journal.contains("Bob-Smith"));
+    }
+
+    #[test]
+    fn household_journal_matches_debts() {
+        let (expenditures, transfers) = history();
+        let config = Config {
+            journal_receivable_account: "Assets:House".to_string(),
+            ..Config::default()
+        };
+        let journal = render(Format::Beancount, View::Household, &config, &names(), &expenditures, &transfers);
+        assert!(journal.contains("2026-10-02 * \"Groceries; \\\"bulk\\\"\""));
+        assert!(journal.contains("1970-01-01 * \"Expenditure\""));
+        let totals = read_back(&journal);
+        assert_eq!(totals["Assets:House:Alice"], Currency::from(-3500));
+        assert_eq!(totals["Assets:House:Bob-Smith"], Currency::from(2000));
+        assert_eq!(totals["Assets:House:Carol"], Currency::from(1500));
+    }
+
+    #[test]
+    fn names_become_account_components() {
+        assert_eq!(component("alice", 1), "Alice");
+        assert_eq!(component("José  O'Neil", 2), "José-O-Neil");
+        assert_eq!(component("2nd floor", 3), "2nd-floor");
+        assert_eq!(component("!!", 4), "User4");
+    }
+}
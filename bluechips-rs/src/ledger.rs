@@ -0,0 +1,161 @@
+//! How someone's balance came to be: every share, payment and transfer
+//! they're part of, in date order, with a running balance.
+//!
+//! Balances are what the person owes, the same way round as `get_debts`:
+//! their shares and transfers paid to them add to it, and expenditures and
+//! transfers they paid take away from it. So the last balance is exactly
+//! what `get_debts` says.
+//!
+//! Against a single other person, only what passes between the two counts:
+//! shares of what the other person paid, the other person's shares of what
+//! this one paid, and transfers between them.
+use chrono::NaiveDate;
+use sea_orm::{DatabaseConnection, DbErr};
+
+use crate::entities::{prelude::*, transfer};
+use crate::journal::{self, Expenditure};
+use crate::service::HistoryFilter;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Record {
+    Expenditure(i32),
+    Transfer(i32),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub date: Option<NaiveDate>,
+    pub record: Record,
+    pub description: String,
+    /// Who paid, or who the transfer was with, unless it's this person.
+    pub other_id: Option<i32>,
+    /// What this adds to what they owe.
+    pub debit: Currency,
+    /// What this takes off what they owe.
+    pub credit: Currency,
+    pub balance: Currency,
+}
+
+/// Everything `user_id` is part of, from `journal::load`.
+pub async fn load(db: &DatabaseConnection, user_id: i32) -> Result<(Vec<Expenditure>, Vec<transfer::Model>), DbErr> {
+    journal::load(db, &HistoryFilter { user_id: Some(user_id), ..Default::default() }).await
+}
+
+/// The ledger for `user_id`, or just what's between them and `with`.
+pub fn entries(user_id: i32, with: Option<i32>, expenditures: &[Expenditure], transfers: &[transfer::Model]) -> Vec<Entry> {
+    let share_of = |e: &Expenditure, id: i32| e.shares
+        .iter()
+        .filter(|(user_id, _)| *user_id == id)
+        .map(|(_, share)| share.clone())
+        .sum::<Currency>();
+    let mut entries = Vec::new();
+    for e in expenditures {
+        let (debit, credit) = match with {
+            None if e.spender_id == user_id => (share_of(e, user_id), e.amount.clone()),
+            None => (share_of(e, user_id), 0.into()),
+            Some(other) if e.spender_id == other => (share_of(e, user_id), 0.into()),
+            Some(other) if e.spender_id == user_id => (0.into(), share_of(e, other)),
+            Some(_) => continue,
+        };
+        entries.push(Entry {
+            date: e.date,
+            record: Record::Expenditure(e.id),
+            description: e.description.clone(),
+            other_id: Some(e.spender_id).filter(|id| *id != user_id),
+            debit,
+            credit,
+            balance: 0.into(),
+        });
+    }
+    for t in transfers {
+        let other_id = if t.debtor_id == user_id { t.creditor_id } else { t.debtor_id };
+        if with.map_or(false, |with| with != other_id) {
+            continue;
+        }
+        let amount_if = |involved: bool| if involved { t.amount.clone() } else { 0.into() };
+        entries.push(Entry {
+            date: t.date,
+            record: Record::Transfer(t.id),
+            description: t.description.clone().unwrap_or_default(),
+            other_id: Some(other_id).filter(|id| *id != user_id),
+            debit: amount_if(t.creditor_id == user_id),
+            credit: amount_if(t.debtor_id == user_id),
+            balance: 0.into(),
+        });
+    }
+    entries.retain(|e| !e.debit.is_zero() || !e.credit.is_zero());
+    entries.sort_by_key(|e| (e.date, matches!(e.record, Record::Transfer(_)), match e.record {
+        Record::Expenditure(id) | Record::Transfer(id) => id,
+    }));
+    let mut balance = Currency::from(0);
+    for e in &mut entries {
+        balance = balance + e.debit.clone() - e.credit.clone();
+        e.balance = balance.clone();
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> (Vec<Expenditure>, Vec<transfer::Model>) {
+        let expenditures = vec![
+            Expenditure {
+                id: 1,
+                date: NaiveDate::from_ymd_opt(2026, 10, 2),
+                description: "Groceries".to_string(),
+                spender_id: 1,
+                amount: 9000.into(),
+                shares: vec![(1, 3000.into()), (2, 3000.into()), (3, 3000.into())],
+            },
+            Expenditure {
+                id: 2,
+                date: NaiveDate::from_ymd_opt(2026, 10, 1),
+                description: "Internet".to_string(),
+                spender_id: 2,
+                amount: 6000.into(),
+                shares: vec![(1, 2000.into()), (2, 2000.into()), (3, 2000.into())],
+            },
+            Expenditure {
+                id: 3,
+                date: None,
+                description: "Old couch".to_string(),
+                spender_id: 3,
+                amount: 1000.into(),
+                shares: vec![(1, 500.into()), (3, 500.into())],
+            },
+        ];
+        let transfers = vec![transfer::Model {
+            id: 1,
+            debtor_id: 2,
+            creditor_id: 1,
+            amount: 1000.into(),
+            description: None,
+            date: NaiveDate::from_ymd_opt(2026, 10, 3),
+            entered_time: None,
+        }];
+        (expenditures, transfers)
+    }
+
+    #[test]
+    fn running_balance_ends_at_the_debt() {
+        let (expenditures, transfers) = history();
+        let entries = entries(1, None, &expenditures, &transfers);
+        let records: Vec<Record> = entries.iter().map(|e| e.record).collect();
+        assert_eq!(records, [Record::Expenditure(3), Record::Expenditure(2), Record::Expenditure(1), Record::Transfer(1)]);
+        // 500 + 2000 + 3000 - 9000 + 1000, as get_debts would have it.
+        assert_eq!(entries.last().unwrap().balance, Currency::from(-2500));
+        assert_eq!(entries[2].debit, Currency::from(3000));
+        assert_eq!(entries[2].credit, Currency::from(9000));
+    }
+
+    #[test]
+    fn pairwise_ledgers_add_up_to_the_whole() {
+        let (expenditures, transfers) = history();
+        let balance = |with| entries(1, with, &expenditures, &transfers).last().map_or(Currency::from(0), |e| e.balance.clone());
+        assert_eq!(balance(Some(2)), Currency::from(2000 - 3000 + 1000));
+        assert_eq!(balance(Some(3)), Currency::from(500 - 3000));
+        assert_eq!(balance(Some(2)) + balance(Some(3)), balance(None));
+    }
+}
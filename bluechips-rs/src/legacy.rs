@@ -0,0 +1,149 @@
+//! Copying data over from the Python app's database.
+//!
+//! The Python app's tables have the same names and columns as the ones the
+//! first migration creates, so the entities read them as they are, apart
+//! from users, who can be admins now, and expenditures, which have gained
+//! a category since. Rows keep
+//! their IDs. The copy goes into an empty database in one transaction, and
+//! is only committed if everyone's balance comes out the same.
+use std::collections::HashMap;
+
+use argon2::PasswordHash;
+use sea_orm::sea_query::Expr;
+use sea_orm::{DatabaseConnection, DbErr, EntityTrait, PaginatorTrait, QuerySelect, TransactionTrait};
+
+use crate::backup::{insert_all, reset_sequences};
+use crate::entities::{prelude::*, *};
+use crate::Query;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Db(#[from] DbErr),
+    #[error("The new database already has users in it; import into a freshly migrated one.")]
+    NotEmpty,
+    #[error("Balances changed during the import, so nothing was saved: {0}")]
+    Unbalanced(String),
+}
+
+/// What was copied.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub users: usize,
+    pub expenditures: usize,
+    pub splits: usize,
+    pub subitems: usize,
+    pub transfers: usize,
+    /// Users who can't log in with their old password and need a reset.
+    pub needs_reset: Vec<String>,
+}
+
+/// What to store for a legacy password. The Python app kept passwords in
+/// plain text, which are hashed now rather than on the user's next login.
+/// Anything that looks like a hash but can't be read is dropped, and so is
+/// an empty password; `None` means the user needs a reset.
+fn convert_password(password: Option<&str>) -> Option<String> {
+    match password {
+        None | Some("") => None,
+        Some(hash) if hash.starts_with('$') => PasswordHash::new(hash).is_ok().then(|| hash.to_string()),
+        Some(plain) => Some(password_auth::generate_hash(plain)),
+    }
+}
+
+/// Describe how `before` and `after` differ, or `None` if they don't.
+fn compare_debts(before: &HashMap<i32, Currency>, after: &HashMap<i32, Currency>) -> Option<String> {
+    let mut ids: Vec<&i32> = before.keys().chain(after.keys()).collect();
+    ids.sort();
+    ids.dedup();
+    let zero = Currency::from(0);
+    let differences: Vec<String> = ids
+        .into_iter()
+        .filter_map(|id| {
+            let (before, after) = (before.get(id).unwrap_or(&zero), after.get(id).unwrap_or(&zero));
+            (before != after).then(|| format!("user {} went from {} to {}", id, before, after))
+        })
+        .collect();
+    (!differences.is_empty()).then(|| differences.join(", "))
+}
+
+/// Copy everything in `legacy` into `db`.
+pub async fn import(legacy: &DatabaseConnection, db: &DatabaseConnection) -> Result<Report, Error> {
+    if User::find().count(db).await? > 0 {
+        return Err(Error::NotEmpty);
+    }
+    let mut report = Report::default();
+    let users: Vec<user::Model> = User::find()
+        .select_only()
+        .columns([
+            user::Column::Id,
+            user::Column::Username,
+            user::Column::Name,
+            user::Column::Resident,
+            user::Column::Email,
+            user::Column::Password,
+        ])
+        .column_as(Expr::value(false), "is_admin")
+        .all(legacy)
+        .await?
+        .into_iter()
+        .map(|user| {
+            let password = convert_password(user.password.as_deref());
+            if password.is_none() {
+                report.needs_reset.push(user.username.clone());
+            }
+            user::Model { password, ..user }
+        })
+        .collect();
+    let txn = db.begin().await?;
+    report.users = insert_all::<user::ActiveModel, _>(&txn, users).await?;
+    let expenditures = Expenditure::find()
+        .select_only()
+        .columns([
+            expenditure::Column::Id,
+            expenditure::Column::SpenderId,
+            expenditure::Column::Amount,
+            expenditure::Column::Description,
+            expenditure::Column::Date,
+            expenditure::Column::EnteredTime,
+        ])
+        .column_as(Expr::value(None::<i32>), "category_id")
+        .all(legacy)
+        .await?;
+    report.expenditures = insert_all::<expenditure::ActiveModel, _>(&txn, expenditures).await?;
+    report.splits = insert_all::<split::ActiveModel, _>(&txn, Split::find().all(legacy).await?).await?;
+    report.subitems = insert_all::<subitem::ActiveModel, _>(&txn, Subitem::find().all(legacy).await?).await?;
+    report.transfers = insert_all::<transfer::ActiveModel, _>(&txn, Transfer::find().all(legacy).await?).await?;
+    reset_sequences(&txn, &["users", "expenditures", "splits", "subitems", "transfers"]).await?;
+    let before = Query::get_debts(legacy).await?;
+    let after = Query::get_debts(&txn).await?;
+    if let Some(differences) = compare_debts(&before, &after) {
+        // Dropping the transaction rolls it back.
+        return Err(Error::Unbalanced(differences));
+    }
+    txn.commit().await?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords() {
+        assert_eq!(convert_password(None), None);
+        assert_eq!(convert_password(Some("")), None);
+        assert_eq!(convert_password(Some("$not a hash")), None);
+        let hash = password_auth::generate_hash("hunter2hunter2");
+        assert_eq!(convert_password(Some(&hash)), Some(hash.clone()));
+        let converted = convert_password(Some("hunter2hunter2")).unwrap();
+        assert!(password_auth::verify_password("hunter2hunter2", &converted).is_ok());
+    }
+
+    #[test]
+    fn debts_must_match() {
+        let before = HashMap::from([(1, Currency::from(500)), (2, Currency::from(-500))]);
+        assert_eq!(compare_debts(&before, &before.clone()), None);
+        let after = HashMap::from([(1, Currency::from(500))]);
+        assert!(compare_debts(&before, &after).unwrap().starts_with("user 2 went from"));
+    }
+}
@@ -4,8 +4,9 @@ use std::path::PathBuf;
 
 use entities::prelude::Currency;
 use rocket::either::Either;
-use rocket::fs::FileServer;
-use rocket::http::Status;
+use rocket::fs::{FileServer, TempFile};
+use rocket::futures::Stream;
+use rocket::http::{CookieJar, Status};
 use cookie::Key;
 use rocket::response::status::Custom;
 use rocket::response::{Flash, Redirect};
@@ -14,17 +15,32 @@ use rocket::fairing::AdHoc;
 use rocket::serde::Deserialize;
 use rocket_csrf::{form::CsrfForm, CsrfToken};
 use rocket::State;
+use rocket::tokio::io::AsyncReadExt;
 use askama::Template; // bring trait in scope
 use sea_orm::Database;
 
 mod entities;
 
 mod service;
-use service::{Query, Mutation, ExpenditureDisplay, TransferDisplay, SettleError, Totals, ExpenditureForm, TransferForm};
+use service::{Query, Mutation, CategoryPath, CategoryForm, category_paths, ExpenditureDisplay, TransferDisplay, SettleError, Totals, Periods, Span, ExpenditureForm, TransferForm, ApiTokenForm, WebhookForm, NotificationForm, ImportMappingForm, DateField, HistoryCursor};
 
 mod auth;
 use auth::SessionManager;
 
+mod mail;
+mod cli;
+mod api;
+mod backup;
+mod webhook;
+mod notify;
+mod digest;
+mod export;
+mod import;
+mod journal;
+mod ledger;
+mod legacy;
+mod report;
+
 use sea_orm::{prelude::*, *};
 
 #[derive(Template)] // this will generate the code...
@@ -33,6 +49,7 @@ use sea_orm::{prelude::*, *};
 struct StatusIndexTemplate<'a> { // the name of the struct can be anything
     title: Option<&'a str>,
     mobile_client: bool,
+    unread: u64,
     flash: Option<FlashMessage<'a>>,
     settle: Result<Vec<(Option<String>, Option<String>, Currency)>, SettleError>,
     net: Option<Currency>,
@@ -40,8 +57,18 @@ struct StatusIndexTemplate<'a> { // the name of the struct can be anything
     transfers: Vec<TransferDisplay>,
     totals: Totals,
 }
+impl StatusIndexTemplate<'_> {
+    /// History for a category this year, to go with the year-to-date totals.
+    fn category_filter(&self, category_id: &i32) -> export::Filter {
+        export::Filter {
+            from: self.totals.year_to_date_span.from.map(DateField),
+            category: Some(*category_id),
+            ..Default::default()
+        }
+    }
+}
 #[get("/")]
-async fn status_index<'a>(db: &State<DatabaseConnection>, flash: Option<FlashMessage<'a>>, user: auth::User) -> StatusIndexTemplate<'a> {
+async fn status_index<'a>(db: &State<DatabaseConnection>, periods: &State<Periods>, flash: Option<FlashMessage<'a>>, user: auth::User, unread: Unread) -> StatusIndexTemplate<'a> {
     let db = db as &DatabaseConnection;
     let users: HashMap<_, _> = Query::find_users(db).await.unwrap().into_iter().map(|u| (u.id, u)).collect();
     let debts = Query::get_debts(db).await.unwrap();
@@ -68,8 +95,58 @@ async fn status_index<'a>(db: &State<DatabaseConnection>, flash: Option<FlashMes
             |(from, _, amount)| Some(amount.clone()).filter(|_| from.is_none())
         ).sum()
     ).filter(|v| *v != 0.into());
-    let totals = Query::get_totals(db, user.id).await.unwrap();
-    StatusIndexTemplate{title: None, flash, mobile_client: false, settle, net, expenditures, transfers, totals}
+    let totals = Query::get_totals(db, user.id, periods).await.unwrap();
+    StatusIndexTemplate{title: None, flash, mobile_client: false, unread: unread.0, settle, net, expenditures, transfers, totals}
+}
+
+#[derive(Template)]
+#[template(path = "totals/index.html")]
+struct TotalsTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    period: service::PeriodTotal,
+    by_category: Vec<service::CategoryTotal>,
+}
+impl TotalsTemplate<'_> {
+    /// History for a category over the same dates.
+    fn category_filter(&self, category_id: &i32) -> export::Filter {
+        export::Filter {
+            from: self.period.span.from.map(DateField),
+            to: self.period.span.to.map(DateField),
+            category: Some(*category_id),
+            ..Default::default()
+        }
+    }
+}
+
+/// Totals between any two dates, the current billing cycle by default.
+#[get("/totals?<from>&<to>")]
+async fn totals_index<'a>(
+    db: &State<DatabaseConnection>,
+    periods: &State<Periods>,
+    flash: Option<FlashMessage<'a>>,
+    user: auth::User,
+    from: Option<DateField>,
+    to: Option<DateField>,
+    unread: Unread,
+) -> Result<TotalsTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let span = match (from, to) {
+        (None, None) => periods.cycle(chrono::Local::now().date_naive()),
+        (from, to) => Span::new(from.map(|d| d.0), to.map(|d| d.0)),
+    };
+    let period = Query::get_totals_for_span(db, user.id, span.to_string(), span).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let by_category = Query::get_totals_by_category_for_date_range(db, user.id, span).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(TotalsTemplate {
+        title: Some("Totals"),
+        mobile_client: false,
+        unread: unread.0,
+        flash,
+        period,
+        by_category,
+    })
 }
 
 #[derive(Template)] // this will generate the code...
@@ -78,11 +155,23 @@ async fn status_index<'a>(db: &State<DatabaseConnection>, flash: Option<FlashMes
 struct SpendTemplate<'a> { // the name of the struct can be anything
     title: Option<&'a str>,
     mobile_client: bool,
+    unread: u64,
     flash: Option<FlashMessage<'a>>,
     authenticity_token: String,
     users: Vec<entities::user::Model>,
     expenditure: entities::expenditure::ActiveModel,
     splits: HashMap<i32, entities::split::ActiveModel>,
+    categories: Vec<CategoryPath>,
+    /// This expenditure's tags, separated by commas.
+    expenditure_tags: String,
+    /// Every tag in use, to suggest.
+    tags: Vec<String>,
+}
+
+async fn categories_and_tags(db: &DatabaseConnection) -> Result<(Vec<CategoryPath>, Vec<String>), Custom<String>> {
+    let categories = Query::find_categories(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let tags = Query::find_tags(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok((category_paths(&categories), tags))
 }
 
 #[get("/spend")]
@@ -90,7 +179,8 @@ async fn spend_index<'a>(
     db: &State<DatabaseConnection>,
     flash: Option<FlashMessage<'a>>,
     user: auth::User,
-    csrf_token: CsrfToken
+    csrf_token: CsrfToken,
+    unread: Unread,
 ) -> Result<SpendTemplate<'a>, Custom<String>> {
     let db = db as &DatabaseConnection;
     let users = Query::find_users(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
@@ -99,9 +189,11 @@ async fn spend_index<'a>(
         share: ActiveValue::set(100.into()),
         ..Default::default()
     })).collect();
+    let (categories, tags) = categories_and_tags(db).await?;
     Ok(SpendTemplate {
         title: Some("Add a New Expenditure"),
         mobile_client: false,
+        unread: unread.0,
         flash,
         authenticity_token: csrf_token.authenticity_token(),
         users,
@@ -111,6 +203,9 @@ async fn spend_index<'a>(
             ..Default::default()
         },
         splits,
+        categories,
+        expenditure_tags: String::new(),
+        tags,
     })
 }
 #[get("/spend/<id>/edit")]
@@ -119,7 +214,8 @@ async fn spend_edit<'a>(
     db: &State<DatabaseConnection>,
     flash: Option<FlashMessage<'a>>,
     _user: auth::User,
-    csrf_token: CsrfToken
+    csrf_token: CsrfToken,
+    unread: Unread,
 ) -> Result<SpendTemplate<'a>, Custom<String>> {
     let db = db as &DatabaseConnection;
     let expenditure =
@@ -131,14 +227,20 @@ async fn spend_edit<'a>(
         expenditure.find_related(entities::split::Entity).all(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
     let splits = splits.into_iter().map(|s| (s.user_id, s.into_active_model())).collect();
     let users = Query::find_users(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let (categories, tags) = categories_and_tags(db).await?;
+    let expenditure_tags = Query::get_expenditure_tags(db, id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
     Ok(SpendTemplate {
         title: Some("Edit an Expenditure"),
         mobile_client: false,
+        unread: unread.0,
         flash,
         authenticity_token: csrf_token.authenticity_token(),
         users,
         expenditure: expenditure.into_active_model(),
         splits,
+        categories,
+        expenditure_tags: expenditure_tags.join(", "),
+        tags,
     })
 }
 
@@ -148,6 +250,7 @@ async fn spend_edit<'a>(
 struct SpendDeleteTemplate<'a> { // the name of the struct can be anything
     title: Option<&'a str>,
     mobile_client: bool,
+    unread: u64,
     flash: Option<FlashMessage<'a>>,
     authenticity_token: String,
     expenditure: ExpenditureDisplay,
@@ -159,6 +262,7 @@ async fn spend_delete<'a>(
     flash: Option<FlashMessage<'a>>,
     user: auth::User,
     csrf_token: CsrfToken,
+    unread: Unread,
 ) -> Result<SpendDeleteTemplate<'a>, Custom<String>> {
     let db = db as &DatabaseConnection;
     let expenditure = Query::get_one_expenditure(db, id, user.id)
@@ -167,6 +271,7 @@ async fn spend_delete<'a>(
     Ok(SpendDeleteTemplate{
         title: Some("Delete an Expenditure"),
         mobile_client: false,
+        unread: unread.0,
         flash,
         authenticity_token: csrf_token.authenticity_token(),
         expenditure,
@@ -175,15 +280,19 @@ async fn spend_delete<'a>(
 #[post("/spend", data="<form>")]
 async fn spend_new_post(
     db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
     user: auth::User,
     form: CsrfForm<ExpenditureForm>,
 ) -> Result<Flash<Redirect>, Custom<String>> {
-    spend_edit_post(None, db, user, form).await
+    spend_edit_post(None, db, mailer, mail_config, user, form).await
 }
 #[post("/spend/<id>", data="<form>")]
 async fn spend_edit_post(
     id: Option<i32>,
     db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
     user: auth::User,
     form: CsrfForm<ExpenditureForm>,
 ) -> Result<Flash<Redirect>, Custom<String>> {
@@ -191,19 +300,26 @@ async fn spend_edit_post(
     let spender = Query::get_user_by_id(db, form.spender_id).await
         .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
         .ok_or(Custom(Status::BadRequest, "spender not found".to_string()))?;
-    Mutation::save_expenditure(db, id, form.clone()).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
-    Ok(Flash::success(
-        Redirect::to(uri!(status_index())),
-        format!(
-            "Expenditure of {} paid for by {} {}.",
-            form.amount,
-            spender.name.unwrap_or(spender.username),
-            match id {
-                Some(_) => "updated",
-                None => "created",
-            }
-        )
-    ))
+    let old = match id {
+        Some(id) => notify::ExpenditureSummary::load(db, id).await
+            .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?,
+        None => None,
+    };
+    let expenditure = Mutation::save_expenditure(db, Some(user.id), id, form.clone()).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let action = match id {
+        Some(_) => entities::audit_log::Action::Update,
+        None => entities::audit_log::Action::Create,
+    };
+    let message = format!(
+        "Expenditure of {} paid for by {} {}.",
+        form.amount,
+        spender.name.unwrap_or(spender.username),
+        action,
+    );
+    let new = notify::ExpenditureSummary::load(db, expenditure.id).await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    notify::expenditure(db, mailer.map(|m| m.inner()), mail_config, action, &message, old.as_ref(), new.as_ref()).await;
+    Ok(Flash::success(Redirect::to(uri!(status_index())), message))
 }
 #[derive(FromForm, Clone, PartialEq, Eq)]
 pub struct DeleteForm<'a> {
@@ -214,6 +330,8 @@ pub struct DeleteForm<'a> {
 async fn spend_delete_post(
     id: i32,
     db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
     user: auth::User,
     form: CsrfForm<DeleteForm<'_>>,
 ) -> Result<Either<Flash<Redirect>, Redirect>, Custom<String>> {
@@ -223,20 +341,19 @@ async fn spend_delete_post(
             .await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
             .ok_or(Custom(Status::NotFound, "expenditure not found".to_string()))?;
     if form.delete.is_some() {
-        // TODO: Make sure foreign key constraints exist on splits
-        entities::expenditure::Entity::delete_by_id(expenditure.id)
-            .exec(db)
+        let old = notify::ExpenditureSummary::load(db, expenditure.id).await
+            .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+        Mutation::delete_expenditure(db, Some(user.id), expenditure.id)
             .await
             .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+        let message = format!(
+            "Expenditure of {} paid for by {} deleted.",
+            expenditure.amount,
+            expenditure.spender_name.unwrap_or("me".to_owned()),
+        );
+        notify::expenditure(db, mailer.map(|m| m.inner()), mail_config, entities::audit_log::Action::Delete, &message, old.as_ref(), None).await;
 
-        Ok(Either::Left(Flash::success(
-            Redirect::to(uri!(status_index())),
-            format!(
-                "Expenditure of {} paid for by {} deleted.",
-                expenditure.amount,
-                expenditure.spender_name.unwrap_or("me".to_owned()),
-            )
-        )))
+        Ok(Either::Left(Flash::success(Redirect::to(uri!(status_index())), message)))
     } else {
         Ok(Either::Right(Redirect::to(uri!(status_index()))))
     }
@@ -248,6 +365,7 @@ async fn spend_delete_post(
 struct TransferTemplate<'a> { // the name of the struct can be anything
     title: Option<&'a str>,
     mobile_client: bool,
+    unread: u64,
     flash: Option<FlashMessage<'a>>,
     authenticity_token: String,
     users: Vec<entities::user::Model>,
@@ -259,13 +377,15 @@ async fn transfer_index<'a>(
     db: &State<DatabaseConnection>,
     flash: Option<FlashMessage<'a>>,
     user: auth::User,
-    csrf_token: CsrfToken
+    csrf_token: CsrfToken,
+    unread: Unread,
 ) -> Result<TransferTemplate<'a>, Custom<String>> {
     let db = db as &DatabaseConnection;
     let users = Query::find_users(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
     Ok(TransferTemplate {
         title: Some("Add a New Transfer"),
         mobile_client: false,
+        unread: unread.0,
         flash,
         authenticity_token: csrf_token.authenticity_token(),
         users,
@@ -283,7 +403,8 @@ async fn transfer_edit<'a>(
     db: &State<DatabaseConnection>,
     flash: Option<FlashMessage<'a>>,
     _user: auth::User,
-    csrf_token: CsrfToken
+    csrf_token: CsrfToken,
+    unread: Unread,
 ) -> Result<TransferTemplate<'a>, Custom<String>> {
     let db = db as &DatabaseConnection;
     let transfer =
@@ -295,6 +416,7 @@ async fn transfer_edit<'a>(
     Ok(TransferTemplate {
         title: Some("Edit an Expenditure"),
         mobile_client: false,
+        unread: unread.0,
         flash,
         authenticity_token: csrf_token.authenticity_token(),
         users,
@@ -304,15 +426,19 @@ async fn transfer_edit<'a>(
 #[post("/transfer", data="<form>")]
 async fn transfer_new_post(
     db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
     user: auth::User,
     form: CsrfForm<TransferForm>,
 ) -> Result<Flash<Redirect>, Custom<String>> {
-    transfer_edit_post(None, db, user, form).await
+    transfer_edit_post(None, db, mailer, mail_config, user, form).await
 }
 #[post("/transfer/<id>", data="<form>")]
 async fn transfer_edit_post(
     id: Option<i32>,
     db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
     user: auth::User,
     form: CsrfForm<TransferForm>,
 ) -> Result<Flash<Redirect>, Custom<String>> {
@@ -323,20 +449,27 @@ async fn transfer_edit_post(
     let creditor = Query::get_user_by_id(db, form.creditor_id).await
         .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
         .ok_or(Custom(Status::BadRequest, "creditor not found".to_string()))?;
-    Mutation::save_transfer(db, id, form.clone()).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
-    Ok(Flash::success(
-        Redirect::to(uri!(status_index())),
-        format!(
-            "Transfer of {} from {} to {} {}.",
-            form.amount,
-            debtor.name.unwrap_or(debtor.username),
-            creditor.name.unwrap_or(creditor.username),
-            match id {
-                Some(_) => "updated",
-                None => "created",
-            }
-        )
-    ))
+    let old = match id {
+        Some(id) => notify::TransferSummary::load(db, id).await
+            .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?,
+        None => None,
+    };
+    let transfer = Mutation::save_transfer(db, Some(user.id), id, form.clone()).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let action = match id {
+        Some(_) => entities::audit_log::Action::Update,
+        None => entities::audit_log::Action::Create,
+    };
+    let message = format!(
+        "Transfer of {} from {} to {} {}.",
+        form.amount,
+        debtor.name.unwrap_or(debtor.username),
+        creditor.name.unwrap_or(creditor.username),
+        action,
+    );
+    let new = notify::TransferSummary::load(db, transfer.id).await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    notify::transfer(db, mailer.map(|m| m.inner()), mail_config, action, &message, old.as_ref(), new.as_ref()).await;
+    Ok(Flash::success(Redirect::to(uri!(status_index())), message))
 }
 
 #[derive(Template)] // this will generate the code...
@@ -345,6 +478,7 @@ async fn transfer_edit_post(
 struct TransferDeleteTemplate<'a> { // the name of the struct can be anything
     title: Option<&'a str>,
     mobile_client: bool,
+    unread: u64,
     flash: Option<FlashMessage<'a>>,
     authenticity_token: String,
     transfer: TransferDisplay,
@@ -356,6 +490,7 @@ async fn transfer_delete<'a>(
     flash: Option<FlashMessage<'a>>,
     user: auth::User,
     csrf_token: CsrfToken,
+    unread: Unread,
 ) -> Result<TransferDeleteTemplate<'a>, Custom<String>> {
     let db = db as &DatabaseConnection;
     let transfer = Query::get_one_transfer(db, id, user.id)
@@ -365,6 +500,7 @@ async fn transfer_delete<'a>(
     Ok(TransferDeleteTemplate{
         title: Some("Delete a Transfer"),
         mobile_client: false,
+        unread: unread.0,
         flash,
         authenticity_token: csrf_token.authenticity_token(),
         transfer,
@@ -374,6 +510,8 @@ async fn transfer_delete<'a>(
 async fn transfer_delete_post(
     id: i32,
     db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
     user: auth::User,
     form: CsrfForm<DeleteForm<'_>>,
 ) -> Result<Either<Flash<Redirect>, Redirect>, Custom<String>> {
@@ -383,39 +521,166 @@ async fn transfer_delete_post(
         .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
         .ok_or(Custom(Status::NotFound, "transfer not found".to_string()))?;
     if form.delete.is_some() {
-        entities::transfer::Entity::delete_by_id(transfer.id)
-            .exec(db)
+        let old = notify::TransferSummary::load(db, transfer.id).await
+            .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+        Mutation::delete_transfer(db, Some(user.id), transfer.id)
             .await
             .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+        let message = format!(
+            "Transfer of {} from {} to {} deleted.",
+            transfer.amount,
+            transfer.debtor_name.unwrap_or("me".to_owned()),
+            transfer.creditor_name.unwrap_or("me".to_owned()),
+        );
+        notify::transfer(db, mailer.map(|m| m.inner()), mail_config, entities::audit_log::Action::Delete, &message, old.as_ref(), None).await;
 
-        Ok(Either::Left(Flash::success(
-            Redirect::to(uri!(status_index())),
-            format!(
-                "Transfer of {} from {} to {} deleted.",
-                transfer.amount,
-                transfer.debtor_name.unwrap_or("me".to_owned()),
-                transfer.creditor_name.unwrap_or("me".to_owned()),
-            )
-        )))
+        Ok(Either::Left(Flash::success(Redirect::to(uri!(status_index())), message)))
     } else {
         Ok(Either::Right(Redirect::to(uri!(status_index()))))
     }
 }
 
+#[derive(Template)]
+#[template(path = "activity/index.html")]
+struct ActivityTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    entries: Vec<service::AuditDisplay>,
+    /// Only set on the global log, which is paginated.
+    page: Option<u64>,
+    more: bool,
+}
+
+const ACTIVITY_PAGE_SIZE: u64 = 50;
+
+#[get("/activity?<page>")]
+async fn activity_index<'a>(
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    _user: auth::User,
+    page: Option<u64>,
+    unread: Unread,
+) -> Result<ActivityTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let page = page.unwrap_or(0);
+    let mut entries = Query::find_audit_log(db, None, Some((ACTIVITY_PAGE_SIZE + 1, page * ACTIVITY_PAGE_SIZE)))
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let more = entries.len() as u64 > ACTIVITY_PAGE_SIZE;
+    entries.truncate(ACTIVITY_PAGE_SIZE as usize);
+    Ok(ActivityTemplate {
+        title: Some("Activity"),
+        mobile_client: false,
+        unread: unread.0,
+        flash,
+        entries,
+        page: Some(page),
+        more,
+    })
+}
+
+async fn record_history<'a>(
+    db: &DatabaseConnection,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    title: &'a str,
+    record_type: entities::audit_log::RecordType,
+    id: i32,
+) -> Result<ActivityTemplate<'a>, Custom<String>> {
+    let entries = Query::find_audit_log(db, Some((record_type, id)), None)
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    if entries.is_empty() {
+        return Err(Custom(Status::NotFound, "no history for this record".to_string()));
+    }
+    Ok(ActivityTemplate {
+        title: Some(title),
+        mobile_client: false,
+        unread,
+        flash,
+        entries,
+        page: None,
+        more: false,
+    })
+}
+
+#[get("/spend/<id>/history")]
+async fn spend_history<'a>(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    _user: auth::User,
+    unread: Unread,
+) -> Result<ActivityTemplate<'a>, Custom<String>> {
+    record_history(db, unread.0, flash, "Expenditure History", entities::audit_log::RecordType::Expenditure, id).await
+}
+
+#[get("/transfer/<id>/history")]
+async fn transfer_history<'a>(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    _user: auth::User,
+    unread: Unread,
+) -> Result<ActivityTemplate<'a>, Custom<String>> {
+    record_history(db, unread.0, flash, "Transfer History", entities::audit_log::RecordType::Transfer, id).await
+}
+
+#[get("/admin/users/<id>/history")]
+async fn admin_user_history<'a>(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    _admin: auth::Admin,
+    unread: Unread,
+) -> Result<ActivityTemplate<'a>, Custom<String>> {
+    record_history(db, unread.0, flash, "User History", entities::audit_log::RecordType::User, id).await
+}
+
 #[derive(Template)] // this will generate the code...
 #[template(path = "auth/login.html")] // using the template in this path, relative
 // to the `templates` dir in the crate root
 struct AuthLoginTemplate<'a> { // the name of the struct can be anything
     title: Option<&'a str>,
     mobile_client: bool,
+    unread: u64,
     flash: Option<FlashMessage<'a>>,
     authenticity_token: String,
+    oidc_display_name: Option<String>,
 }
 
 #[get("/login")]
-fn auth_login<'a>(flash: Option<FlashMessage<'a>>, csrf_token: CsrfToken) -> AuthLoginTemplate<'a> {
+fn auth_login<'a>(flash: Option<FlashMessage<'a>>, csrf_token: CsrfToken, oidc: Option<&State<auth::oidc::Provider>>) -> AuthLoginTemplate<'a> {
     let authenticity_token = csrf_token.authenticity_token();
-    AuthLoginTemplate{title: Some("Login"), flash, mobile_client: false, authenticity_token}
+    let oidc_display_name = oidc.map(|p| p.display_name.clone());
+    AuthLoginTemplate{title: Some("Login"), flash, mobile_client: false, unread: 0, authenticity_token, oidc_display_name}
+}
+
+#[get("/login/oidc")]
+fn auth_oidc_login(oidc: &State<auth::oidc::Provider>, cookies: &CookieJar<'_>) -> Redirect {
+    Redirect::to(oidc.authorize_url(cookies))
+}
+
+#[get("/login/oidc/callback?<code>&<state>&<error>")]
+async fn auth_oidc_callback(
+    code: Option<&str>,
+    state: Option<&str>,
+    error: Option<&str>,
+    db: &State<DatabaseConnection>,
+    oidc: &State<auth::oidc::Provider>,
+    config: &State<auth::oidc::Config>,
+    auth: auth::Auth<'_>,
+) -> Result<Redirect, Flash<Redirect>> {
+    let db = db as &DatabaseConnection;
+    let (code, state) = match (code, state) {
+        (Some(code), Some(state)) => (code, state),
+        _ => return Err(Flash::error(unauthorized(), format!("Login failed: {}", error.unwrap_or("no authorization code")))),
+    };
+    auth.login_oidc(oidc, config, code, state, db).await
+        .map_err(|e| Flash::error(unauthorized(), format!("{}", e)))?;
+    Ok(Redirect::to(uri!(status_index())))
 }
 
 #[post("/login", data="<form>")]
@@ -425,9 +690,194 @@ async fn auth_login_post<'a>(
     auth: auth::Auth<'_>,
 ) -> Result<Redirect, Flash<Redirect>> {
     let db = db as &DatabaseConnection;
-    auth.login(&form, db).await
-        .map_err(|e| Flash::error(unauthorized(), format!("{:?}", e)))?;
-    Ok(Redirect::to(uri!(status_index())))
+    match auth.login(&form, db).await {
+        Ok(auth::LoginOutcome::LoggedIn) => Ok(Redirect::to(uri!(status_index()))),
+        Ok(auth::LoginOutcome::SecondFactorRequired) => Ok(Redirect::to(uri!(auth_second_factor()))),
+        Err(e) => Err(Flash::error(unauthorized(), e.to_string())),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "auth/second_factor.html")]
+struct AuthSecondFactorTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+}
+
+#[get("/login/2fa")]
+fn auth_second_factor<'a>(flash: Option<FlashMessage<'a>>, csrf_token: CsrfToken, auth: auth::Auth<'_>) -> Result<AuthSecondFactorTemplate<'a>, Redirect> {
+    if !auth.second_factor_pending() {
+        return Err(Redirect::to(uri!(auth_login())));
+    }
+    let authenticity_token = csrf_token.authenticity_token();
+    Ok(AuthSecondFactorTemplate{title: Some("Two-Factor Authentication"), flash, mobile_client: false, unread: 0, authenticity_token})
+}
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct CodeForm {
+    pub code: String,
+}
+
+#[post("/login/2fa", data="<form>")]
+async fn auth_second_factor_post(
+    db: &State<DatabaseConnection>,
+    form: CsrfForm<CodeForm>,
+    auth: auth::Auth<'_>,
+) -> Result<Redirect, Flash<Redirect>> {
+    let db = db as &DatabaseConnection;
+    match auth.login_second_factor(&form.code, db).await {
+        Ok(()) => Ok(Redirect::to(uri!(status_index()))),
+        Err(e @ auth::Error::UnauthenticatedError) => Err(Flash::error(unauthorized(), e.to_string())),
+        Err(e) => Err(Flash::error(Redirect::to(uri!(auth_second_factor())), e.to_string())),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "auth/forgot.html")]
+struct AuthForgotTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+}
+
+#[get("/password/forgot")]
+fn password_forgot<'a>(flash: Option<FlashMessage<'a>>, csrf_token: CsrfToken) -> AuthForgotTemplate<'a> {
+    let authenticity_token = csrf_token.authenticity_token();
+    AuthForgotTemplate{title: Some("Forgot Password"), flash, mobile_client: false, unread: 0, authenticity_token}
+}
+
+#[derive(Template)]
+#[template(path = "emails/password_reset.txt")]
+struct PasswordResetEmail<'a> {
+    user: &'a entities::user::Model,
+    url: String,
+}
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct ForgotForm {
+    pub username: String,
+}
+
+#[post("/password/forgot", data="<form>")]
+async fn password_forgot_post(
+    db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
+    form: CsrfForm<ForgotForm>,
+    auth: auth::Auth<'_>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let mailer = mailer.ok_or(Custom(Status::ServiceUnavailable, "email is not configured; ask an admin for a reset link".to_string()))?;
+    match auth.throttle_reset(db, &form.username).await {
+        Ok(()) => {}
+        Err(e @ auth::Error::ThrottledError(_)) => return Ok(Flash::error(Redirect::to(uri!(password_forgot())), e.to_string())),
+        Err(e) => return Err(Custom(Status::InternalServerError, format!("{:?}", e))),
+    }
+    let user = Query::find_user_by_username(db, &form.username).await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    // Say the same thing either way so this can't be used to find accounts.
+    let message = "If that account has an email address, a reset link has been sent to it.";
+    let Some((user, email)) = user.and_then(|u| u.email.clone().map(|email| (u, email))) else {
+        return Ok(Flash::success(Redirect::to(uri!(auth_login())), message));
+    };
+    let token = auth::password::issue_reset(db, user.id, chrono::Duration::hours(1)).await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let body = PasswordResetEmail {
+        user: &user,
+        url: mail_config.url(uri!(password_reset(token = token.as_str()))),
+    }
+        .render()
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    if let Err(e) = mailer.send(&email, "BlueChips password reset", body).await {
+        error!("Failed to send password reset to {}: {}", email, e);
+    }
+    Ok(Flash::success(Redirect::to(uri!(auth_login())), message))
+}
+
+#[derive(Template)]
+#[template(path = "auth/reset.html")]
+struct AuthResetTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    token: &'a str,
+    user: entities::user::Model,
+}
+
+/// Used both for resets and for invited users who have never had a password.
+#[get("/password/reset/<token>")]
+async fn password_reset<'a>(
+    token: &'a str,
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    csrf_token: CsrfToken,
+) -> Result<AuthResetTemplate<'a>, Flash<Redirect>> {
+    let db = db as &DatabaseConnection;
+    let invalid = || Flash::error(Redirect::to(uri!(auth_login())), "That link is invalid or has expired.");
+    let reset = Query::find_password_reset_by_hash(db, &auth::api_token::hash(token)).await
+        .map_err(|_| invalid())?
+        .ok_or_else(invalid)?;
+    let user = Query::get_user_by_id(db, reset.user_id).await
+        .map_err(|_| invalid())?
+        .ok_or_else(invalid)?;
+    Ok(AuthResetTemplate {
+        title: Some(if user.password.is_some() { "Reset Password" } else { "Set Password" }),
+        mobile_client: false,
+        unread: 0,
+        flash,
+        authenticity_token: csrf_token.authenticity_token(),
+        token,
+        user,
+    })
+}
+
+#[post("/password/reset/<token>", data="<form>")]
+async fn password_reset_post(
+    token: &str,
+    db: &State<DatabaseConnection>,
+    form: CsrfForm<auth::password::SetPassword>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let db = db as &DatabaseConnection;
+    let invalid = || Flash::error(Redirect::to(uri!(auth_login())), "That link is invalid or has expired.");
+    let reset = Query::find_password_reset_by_hash(db, &auth::api_token::hash(token)).await
+        .map_err(|_| invalid())?
+        .ok_or_else(invalid)?;
+    let user = Query::get_user_by_id(db, reset.user_id).await
+        .map_err(|_| invalid())?
+        .ok_or_else(invalid)?;
+    auth::password::check_strength(&form.password, &user)
+        .map_err(|e| Flash::error(Redirect::to(uri!(password_reset(token = token))), e.to_string()))?;
+    match Mutation::reset_password(db, reset, password_auth::generate_hash(&form.password)).await {
+        Ok(_) => {}
+        Err(TransactionError::Transaction(DbErr::Custom(message))) => return Err(Flash::error(Redirect::to(uri!(auth_login())), message)),
+        Err(e) => return Err(Flash::error(Redirect::to(uri!(password_reset(token = token))), format!("{:?}", e))),
+    }
+    Ok(Flash::success(Redirect::to(uri!(auth_login())), "Password set. You can log in now."))
+}
+
+#[post("/user/password", data="<form>")]
+async fn user_password_post(
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+    form: CsrfForm<auth::password::ChangePassword>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let db = db as &DatabaseConnection;
+    let fail = |message: String| Flash::error(Redirect::to(uri!(user_index())), message);
+    if let Some(current) = &user.password {
+        auth::password::verify(form.current_password.as_deref().unwrap_or_default(), current)
+            .map_err(|_| fail("Your current password is incorrect.".to_string()))?;
+    }
+    auth::password::check_strength(&form.password, &user).map_err(|e| fail(e.to_string()))?;
+    Mutation::set_password(db, user.id, password_auth::generate_hash(&form.password)).await
+        .map_err(|e| fail(format!("{:?}", e)))?;
+    Ok(Flash::success(Redirect::to(uri!(user_index())), "Password changed."))
 }
 
 #[derive(Template)]
@@ -435,58 +885,1532 @@ async fn auth_login_post<'a>(
 struct HistoryIndexTemplate<'a> { // the name of the struct can be anything
     title: Option<&'a str>,
     mobile_client: bool,
+    unread: u64,
     flash: Option<FlashMessage<'a>>,
     expenditures: Vec<ExpenditureDisplay>,
     transfers: Vec<TransferDisplay>,
+    users: Vec<entities::user::Model>,
+    categories: Vec<CategoryPath>,
+    tags: Vec<String>,
+    filter: export::Filter,
+    /// Links to the next page of each list, if there is one.
+    older_expenditures: Option<String>,
+    older_transfers: Option<String>,
+    /// Whether either list is past its first page.
+    paged: bool,
 }
 
-#[get("/history")]
-async fn history_index<'a>(db: &State<DatabaseConnection>, flash: Option<FlashMessage<'a>>, user: auth::User) -> HistoryIndexTemplate<'a> {
+const HISTORY_PAGE_SIZE: u64 = 50;
+
+/// Expenditures and transfers are paged separately, each picking up after
+/// the last one shown.
+#[get("/history?<expenditures_after>&<transfers_after>&<filter..>")]
+async fn history_index<'a>(
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    user: auth::User,
+    expenditures_after: Option<HistoryCursor>,
+    transfers_after: Option<HistoryCursor>,
+    filter: Option<export::Filter>,
+    unread: Unread,
+) -> Result<HistoryIndexTemplate<'a>, Custom<String>> {
     let db = db as &DatabaseConnection;
-    let expenditures = Query::find_all_expenditures(db, user.id).await.unwrap();
-    let transfers = Query::find_all_transfers(db, user.id).await.unwrap();
-    HistoryIndexTemplate{title: Some("History"), flash: flash, mobile_client: false, expenditures, transfers}
+    let filter = filter.unwrap_or_default();
+    let history = filter.history(user.id);
+    let mut expenditures = Query::find_expenditures(db, user.id, &history, expenditures_after, Some(HISTORY_PAGE_SIZE + 1))
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let mut transfers = Query::find_transfers(db, user.id, &history, transfers_after, Some(HISTORY_PAGE_SIZE + 1))
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let users = Query::find_users(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let (categories, tags) = categories_and_tags(db).await?;
+    let mut older_expenditures = None;
+    if expenditures.len() as u64 > HISTORY_PAGE_SIZE {
+        expenditures.truncate(HISTORY_PAGE_SIZE as usize);
+        let last = expenditures.last().map(|e| HistoryCursor::new(e.date, e.id));
+        older_expenditures = Some(uri!(history_index(last, transfers_after, Some(filter.clone()))).to_string());
+    }
+    let mut older_transfers = None;
+    if transfers.len() as u64 > HISTORY_PAGE_SIZE {
+        transfers.truncate(HISTORY_PAGE_SIZE as usize);
+        let last = transfers.last().map(|t| HistoryCursor::new(t.date, t.id));
+        older_transfers = Some(uri!(history_index(expenditures_after, last, Some(filter.clone()))).to_string());
+    }
+    Ok(HistoryIndexTemplate {
+        title: Some("History"),
+        flash,
+        mobile_client: false,
+        unread: unread.0,
+        expenditures,
+        transfers,
+        users,
+        categories,
+        tags,
+        filter,
+        older_expenditures,
+        older_transfers,
+        paged: expenditures_after.is_some() || transfers_after.is_some(),
+    })
 }
 
-#[get("/user")]
-fn user_index() -> Option<()> {
-    None
+async fn export_names(db: &DatabaseConnection) -> Result<HashMap<i32, String>, Custom<String>> {
+    Ok(Query::find_users(db)
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+        .into_iter()
+        .map(|u| (u.id, u.name.unwrap_or(u.username)))
+        .collect())
 }
 
-#[catch(401)]
-fn unauthorized() -> Redirect {
-    Redirect::to(uri!(auth_login()))
+#[get("/history/expenditures.csv?<filter..>")]
+async fn history_expenditures_csv<'r>(
+    db: &'r State<DatabaseConnection>,
+    user: auth::User,
+    filter: Option<export::Filter>,
+) -> Result<export::Csv<impl Stream<Item = String> + Send + 'r>, Custom<String>> {
+    let db = db.inner();
+    let names = export_names(db).await?;
+    Ok(export::Csv {
+        filename: "expenditures.csv".to_string(),
+        rows: export::expenditures(db, names, filter.unwrap_or_default().history(user.id)),
+    })
 }
 
-#[derive(Deserialize)]
-#[serde(default)]
-pub struct Config {
-    db_uri: String,
-    public_path: PathBuf,
+#[get("/history/transfers.csv?<filter..>")]
+async fn history_transfers_csv<'r>(
+    db: &'r State<DatabaseConnection>,
+    user: auth::User,
+    filter: Option<export::Filter>,
+) -> Result<export::Csv<impl Stream<Item = String> + Send + 'r>, Custom<String>> {
+    let db = db.inner();
+    let names = export_names(db).await?;
+    Ok(export::Csv {
+        filename: "transfers.csv".to_string(),
+        rows: export::transfers(db, names, filter.unwrap_or_default().history(user.id)),
+    })
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            db_uri: "sqlite://database.sqlite3".to_string(),
-            public_path: option_env!("ROCKET_PUBLIC_PATH").unwrap_or("public").into(),
+/// Defaults to the current user's statement.
+#[get("/history/statement.csv?<filter..>")]
+async fn history_statement_csv<'r>(
+    db: &'r State<DatabaseConnection>,
+    user: auth::User,
+    filter: Option<export::Filter>,
+) -> Result<export::Csv<impl Stream<Item = String> + Send + 'r>, Custom<String>> {
+    let db = db.inner();
+    let filter = filter.unwrap_or_default();
+    let subject = Query::get_user_by_id(db, filter.user.unwrap_or(user.id))
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+        .ok_or(Custom(Status::NotFound, "user not found".to_string()))?;
+    Ok(export::Csv {
+        filename: format!("statement-{}.csv", subject.username),
+        rows: export::statement(db, subject.id, filter.history(user.id)),
+    })
+}
+
+/// A user's own journal, or everyone's balances if no user is given.
+/// Defaults to ledger format.
+#[get("/history/journal?<format>&<filter..>")]
+async fn history_journal(
+    db: &State<DatabaseConnection>,
+    config: &State<journal::Config>,
+    user: auth::User,
+    format: Option<journal::Format>,
+    filter: Option<export::Filter>,
+) -> Result<journal::Download, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let format = format.unwrap_or(journal::Format::Ledger);
+    let filter = filter.unwrap_or_default();
+    let names = export_names(db).await?;
+    let view = match filter.user {
+        Some(id) => journal::View::User(id),
+        None => journal::View::Household,
+    };
+    let filename = match view {
+        journal::View::User(id) => Query::get_user_by_id(db, id)
+            .await
+            .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+            .ok_or(Custom(Status::NotFound, "user not found".to_string()))?
+            .username,
+        journal::View::Household => "household".to_string(),
+    };
+    let (expenditures, transfers) = journal::load(db, &filter.history(user.id))
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(journal::Download {
+        filename: format!("bluechips-{}.{}", filename, format.extension()),
+        body: journal::render(format, view, config, &names, &expenditures, &transfers),
+    })
+}
+
+#[derive(Template)]
+#[template(path = "ledger/index.html")]
+struct LedgerTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    users: Vec<entities::user::Model>,
+    user_id: i32,
+    with: Option<i32>,
+    entries: Vec<ledger::Entry>,
+    balance: Currency,
+    /// What `get_debts` says, which the whole ledger should come to.
+    debt: Currency,
+}
+
+impl LedgerTemplate<'_> {
+    fn name(&self, id: &i32) -> &str {
+        self.users.iter().find(|u| u.id == *id).map_or("someone", |u| u.name.as_deref().unwrap_or(u.username.as_str()))
+    }
+
+    fn link(&self, record: &ledger::Record) -> String {
+        match record {
+            ledger::Record::Expenditure(id) => uri!(spend_edit(id = *id)).to_string(),
+            ledger::Record::Transfer(id) => uri!(transfer_edit(id = *id)).to_string(),
+        }
+    }
+
+    /// Where things stand at the end, in words.
+    fn summary(&self) -> String {
+        let name = self.name(&self.user_id);
+        let zero = Currency::from(0);
+        match self.with {
+            None if self.balance > zero => format!("{} owes {}.", name, self.balance),
+            None if self.balance < zero => format!("{} is owed {}.", name, -self.balance.clone()),
+            None => format!("{} is all square.", name),
+            Some(with) if self.balance > zero => format!("{} owes {} {}.", name, self.name(&with), self.balance),
+            Some(with) if self.balance < zero => format!("{} owes {} {}.", self.name(&with), name, -self.balance.clone()),
+            Some(with) => format!("{} and {} are square.", name, self.name(&with)),
         }
     }
 }
 
-#[launch]
-async fn rocket() -> _ {
-    let figment = rocket::Config::figment()
-        .join(("secret_key", Key::generate().master()));
-    let config: Config = figment.extract().unwrap();
-    let db = Database::connect(config.db_uri).await.unwrap();
-    rocket::custom(figment)
-        .attach(AdHoc::config::<auth::Config>())
+/// Someone's running balance, mine by default, or just what's between them
+/// and one other person.
+#[get("/ledger?<user>&<with>")]
+async fn ledger_index<'a>(
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    current: auth::User,
+    user: Option<i32>,
+    with: Option<i32>,
+    unread: Unread,
+) -> Result<LedgerTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let user_id = user.unwrap_or(current.id);
+    let with = with.filter(|with| *with != user_id);
+    let users = Query::find_users(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    if !users.iter().any(|u| u.id == user_id) || with.map_or(false, |with| !users.iter().any(|u| u.id == with)) {
+        return Err(Custom(Status::NotFound, "user not found".to_string()));
+    }
+    let (expenditures, transfers) = ledger::load(db, user_id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let entries = ledger::entries(user_id, with, &expenditures, &transfers);
+    let debt = Query::get_debts(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+        .remove(&user_id)
+        .unwrap_or(0.into());
+    if with.is_none() && entries.last().map_or(Currency::from(0), |e| e.balance.clone()) != debt {
+        error!("Ledger for user {} doesn't come to their debt of {}", user_id, debt);
+    }
+    Ok(LedgerTemplate {
+        title: Some("Ledger"),
+        mobile_client: false,
+        unread: unread.0,
+        flash,
+        users,
+        user_id,
+        with,
+        balance: entries.last().map_or(0.into(), |e| e.balance.clone()),
+        entries,
+        debt,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "reports/index.html")]
+struct ReportsTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    params: report::Params,
+    from: chrono::NaiveDate,
+    to: chrono::NaiveDate,
+    table: report::Table,
+    /// The table drawn as SVG.
+    chart: String,
+}
+
+impl ReportsTemplate<'_> {
+    /// The same dates, as another kind of report.
+    fn switch_to(&self, kind: &report::Kind) -> report::Params {
+        report::Params { kind: *kind, ..self.params.clone() }
+    }
+}
+
+#[get("/reports?<params..>")]
+async fn reports_index<'a>(
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    _user: auth::User,
+    params: Option<report::Params>,
+    unread: Unread,
+) -> Result<ReportsTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let params = params.unwrap_or_default();
+    let (from, to) = params.range(chrono::Local::now().date_naive());
+    let table = report::build(db, params.kind, from, to).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(ReportsTemplate {
+        title: Some("Reports"),
+        mobile_client: false,
+        unread: unread.0,
+        flash,
+        chart: table.svg(),
+        params,
+        from,
+        to,
+        table,
+    })
+}
+
+#[get("/reports/report.csv?<params..>")]
+async fn reports_csv(
+    db: &State<DatabaseConnection>,
+    _user: auth::User,
+    params: Option<report::Params>,
+) -> Result<export::Csv<impl Stream<Item = String> + Send>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let params = params.unwrap_or_default();
+    let (from, to) = params.range(chrono::Local::now().date_naive());
+    let table = report::build(db, params.kind, from, to).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(export::Csv {
+        filename: format!("report-{}-{}-{}.csv", params.kind.slug(), from, to),
+        rows: rocket::futures::stream::iter(table.csv()),
+    })
+}
+
+#[derive(Template)]
+#[template(path = "import/index.html")]
+struct ImportTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    mappings: Vec<entities::import_mapping::Model>,
+}
+
+#[get("/import")]
+async fn import_index<'a>(
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    _user: auth::User,
+    csrf_token: CsrfToken,
+    unread: Unread,
+) -> Result<ImportTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let mappings = Query::find_import_mappings(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(ImportTemplate {
+        title: Some("Import a Statement"),
+        mobile_client: false,
+        unread: unread.0,
+        flash,
+        authenticity_token: csrf_token.authenticity_token(),
+        mappings,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "import/map.html")]
+struct ImportMapTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    /// Why the last attempt to read the file with this mapping failed.
+    error: Option<String>,
+    /// The uploaded file, passed along to the preview.
+    csv: String,
+    headers: Vec<String>,
+    sample: Vec<Vec<String>>,
+    mapping: entities::import_mapping::Model,
+    date_formats: &'static [(&'static str, &'static str)],
+    presets: Vec<(import::SplitPreset, String)>,
+}
+
+const IMPORT_SAMPLE_ROWS: usize = 5;
+
+async fn import_map_page<'a>(
+    db: &DatabaseConnection,
+    unread: u64,
+    csrf_token: CsrfToken,
+    csv: String,
+    mapping: entities::import_mapping::Model,
+    error: Option<String>,
+) -> Result<ImportMapTemplate<'a>, Custom<String>> {
+    let (headers, sample) = import::sample(&csv, IMPORT_SAMPLE_ROWS).map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+    let users = Query::find_users(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let guess = |current: String, names: &[&str]| {
+        if headers.contains(&current) {
+            current
+        } else {
+            import::guess_column(&headers, names).unwrap_or_default()
+        }
+    };
+    let mapping = entities::import_mapping::Model {
+        date_column: guess(mapping.date_column, &["date"]),
+        amount_column: guess(mapping.amount_column, &["amount", "debit"]),
+        description_column: guess(mapping.description_column, &["description", "payee", "memo", "name"]),
+        ..mapping
+    };
+    Ok(ImportMapTemplate {
+        title: Some("Import a Statement"),
+        mobile_client: false,
+        unread,
+        flash: None,
+        authenticity_token: csrf_token.authenticity_token(),
+        error,
+        csv,
+        headers,
+        sample,
+        mapping,
+        date_formats: import::DATE_FORMATS,
+        presets: import::SplitPreset::choices(&users),
+    })
+}
+
+#[derive(FromForm)]
+struct ImportUploadForm<'r> {
+    statement: TempFile<'r>,
+    /// A saved mapping to start from.
+    mapping: Option<i32>,
+}
+
+#[post("/import/map", data="<form>")]
+async fn import_map_post<'a>(
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+    csrf_token: CsrfToken,
+    form: CsrfForm<ImportUploadForm<'_>>,
+    unread: Unread,
+) -> Result<Either<ImportMapTemplate<'a>, ImportPreviewTemplate<'a>>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let mut data = Vec::new();
+    form.statement.open().await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+        .read_to_end(&mut data).await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let csv = String::from_utf8_lossy(&data).into_owned();
+    let saved = match form.mapping {
+        Some(id) => Query::get_import_mapping(db, id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?,
+        None => None,
+    };
+    // OFX and CAMT.053 say which field is which, so go straight to the
+    // preview.
+    if let Some(rows) = import::parse_structured(&csv) {
+        let rows = rows.map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+        let default_split = saved
+            .and_then(|m| m.default_split.parse().ok())
+            .unwrap_or(import::SplitPreset::Residents);
+        return Ok(Either::Right(import_preview_page(db, unread.0, csrf_token, user.id, rows, default_split).await?));
+    }
+    let mapping = saved.unwrap_or_else(|| entities::import_mapping::Model {
+        id: 0,
+        name: String::new(),
+        date_column: String::new(),
+        amount_column: String::new(),
+        description_column: String::new(),
+        date_format: import::DATE_FORMATS[0].0.to_string(),
+        negate: false,
+        default_split: import::SplitPreset::Residents.to_string(),
+    });
+    Ok(Either::Left(import_map_page(db, unread.0, csrf_token, csv, mapping, None).await?))
+}
+
+#[derive(Template)]
+#[template(path = "import/preview.html")]
+struct ImportPreviewTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    /// Each row and whether it was already imported.
+    rows: Vec<(import::StatementRow, bool)>,
+    users: Vec<entities::user::Model>,
+    presets: Vec<(import::SplitPreset, String)>,
+    default_split: import::SplitPreset,
+    spender_id: i32,
+}
+
+async fn import_preview_page<'a>(
+    db: &DatabaseConnection,
+    unread: u64,
+    csrf_token: CsrfToken,
+    spender_id: i32,
+    rows: Vec<import::StatementRow>,
+    default_split: import::SplitPreset,
+) -> Result<ImportPreviewTemplate<'a>, Custom<String>> {
+    let imported = Query::find_imported_fingerprints(db, rows.iter().map(|r| r.fingerprint.clone()).collect())
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let users = Query::find_users(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(ImportPreviewTemplate {
+        title: Some("Import a Statement"),
+        mobile_client: false,
+        unread,
+        flash: None,
+        authenticity_token: csrf_token.authenticity_token(),
+        rows: rows.into_iter().map(|r| {
+            let done = imported.contains(&r.fingerprint);
+            (r, done)
+        }).collect(),
+        presets: import::SplitPreset::choices(&users),
+        users,
+        default_split,
+        spender_id,
+    })
+}
+
+#[derive(FromForm, Clone)]
+struct ImportPreviewForm {
+    csv: String,
+    mapping: ImportMappingForm,
+    /// Remember the mapping for this bank's next statement.
+    save: bool,
+}
+
+#[post("/import/preview", data="<form>")]
+async fn import_preview_post<'a>(
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+    csrf_token: CsrfToken,
+    form: CsrfForm<ImportPreviewForm>,
+    unread: Unread,
+) -> Result<Either<ImportPreviewTemplate<'a>, ImportMapTemplate<'a>>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let form = form.clone();
+    let mapping = entities::import_mapping::Model {
+        id: 0,
+        name: form.mapping.name.clone(),
+        date_column: form.mapping.date_column.clone(),
+        amount_column: form.mapping.amount_column.clone(),
+        description_column: form.mapping.description_column.clone(),
+        date_format: form.mapping.date_format.clone(),
+        negate: form.mapping.negate,
+        default_split: form.mapping.default_split.to_string(),
+    };
+    let rows = match import::parse_csv(&form.csv, &mapping) {
+        Ok(rows) => rows,
+        Err(e) => return Ok(Either::Right(import_map_page(db, unread.0, csrf_token, form.csv, mapping, Some(e.to_string())).await?)),
+    };
+    if form.save {
+        Mutation::save_import_mapping(db, form.mapping.clone()).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    }
+    Ok(Either::Left(import_preview_page(db, unread.0, csrf_token, user.id, rows, form.mapping.default_split).await?))
+}
+
+#[derive(FromForm, Clone)]
+struct ImportRowForm {
+    include: bool,
+    fingerprint: String,
+    date: DateField,
+    amount: Currency,
+    description: String,
+    spender_id: i32,
+    split: import::SplitPreset,
+}
+
+#[derive(FromForm, Clone)]
+struct ImportForm {
+    rows: Vec<ImportRowForm>,
+}
+
+#[post("/import", data="<form>")]
+async fn import_post(
+    db: &State<DatabaseConnection>,
+    mailer: Option<&State<mail::Mailer>>,
+    mail_config: &State<mail::Config>,
+    user: auth::User,
+    form: CsrfForm<ImportForm>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let users = Query::find_users(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let mut rows = Vec::new();
+    for row in form.clone().rows.into_iter().filter(|r| r.include) {
+        let splits = row.split.splits(&users);
+        if splits.is_empty() {
+            return Err(Custom(Status::BadRequest, format!("nobody to split \"{}\" between", row.description)));
+        }
+        rows.push((row.fingerprint, ExpenditureForm {
+            spender_id: row.spender_id,
+            amount: row.amount,
+            description: row.description,
+            date: row.date,
+            splits,
+            category_id: None,
+            tags: String::new(),
+        }));
+    }
+    let chosen = rows.len();
+    let created = Mutation::import_expenditures(db, Some(user.id), rows).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    for expenditure in &created {
+        let new = notify::ExpenditureSummary::load(db, expenditure.id).await
+            .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+        if let Some(new) = new {
+            let message = new.message(entities::audit_log::Action::Create);
+            notify::expenditure(db, mailer.map(|m| m.inner()), mail_config, entities::audit_log::Action::Create, &message, None, Some(&new)).await;
+        }
+    }
+    let mut message = format!("Imported {} expenditures.", created.len());
+    if created.len() < chosen {
+        message.push_str(&format!(" Skipped {} that were already imported.", chosen - created.len()));
+    }
+    Ok(Flash::success(Redirect::to(uri!(history_index(_, _, _))), message))
+}
+
+#[derive(Template)]
+#[template(path = "import/splitwise_match.html")]
+struct SplitwiseMatchTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    /// The uploaded export, passed along to the import.
+    data: String,
+    /// Each member and the user they look like.
+    members: Vec<(String, Option<i32>)>,
+    users: Vec<entities::user::Model>,
+}
+
+#[derive(FromForm)]
+struct SplitwiseUploadForm<'r> {
+    export: TempFile<'r>,
+}
+
+#[post("/import/splitwise/match", data="<form>")]
+async fn import_splitwise_match_post<'a>(
+    db: &State<DatabaseConnection>,
+    _user: auth::User,
+    csrf_token: CsrfToken,
+    form: CsrfForm<SplitwiseUploadForm<'_>>,
+    unread: Unread,
+) -> Result<SplitwiseMatchTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let mut data = Vec::new();
+    form.export.open().await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+        .read_to_end(&mut data).await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let data = String::from_utf8_lossy(&data).into_owned();
+    let export = import::splitwise::parse(&data).map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+    let users = Query::find_users(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    // Splitwise only has people's names, so match on the name or username,
+    // or failing that the first name.
+    let members = export
+        .members
+        .into_iter()
+        .map(|member| {
+            let lower = member.to_lowercase();
+            let first = lower.split_whitespace().next().unwrap_or_default().to_string();
+            let user = users
+                .iter()
+                .find(|u| u.name.as_ref().is_some_and(|n| n.to_lowercase() == lower) || u.username.to_lowercase() == lower)
+                .or_else(|| users.iter().find(|u| {
+                    u.username.to_lowercase() == first
+                        || u.name.as_ref().is_some_and(|n| n.to_lowercase().split_whitespace().next() == Some(first.as_str()))
+                }));
+            (member, user.map(|u| u.id))
+        })
+        .collect();
+    Ok(SplitwiseMatchTemplate {
+        title: Some("Import from Splitwise"),
+        mobile_client: false,
+        unread: unread.0,
+        flash: None,
+        authenticity_token: csrf_token.authenticity_token(),
+        data,
+        members,
+        users,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "import/splitwise_result.html")]
+struct SplitwiseResultTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    expenditures: usize,
+    transfers: usize,
+    already_imported: usize,
+    skipped: Vec<import::splitwise::Skipped>,
+    /// For each user: how much their debt should have changed going by
+    /// Splitwise's balances, and how much it did.
+    balances: Vec<(String, Currency, Currency)>,
+}
+
+#[derive(FromForm, Clone)]
+struct SplitwiseMemberForm {
+    name: String,
+    user_id: i32,
+}
+
+#[derive(FromForm, Clone)]
+struct SplitwiseImportForm {
+    data: String,
+    members: Vec<SplitwiseMemberForm>,
+}
+
+#[post("/import/splitwise", data="<form>")]
+async fn import_splitwise_post<'a>(
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+    form: CsrfForm<SplitwiseImportForm>,
+    unread: Unread,
+) -> Result<SplitwiseResultTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let form = form.clone();
+    let mut export = import::splitwise::parse(&form.data).map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+    let matches: HashMap<String, i32> = form.members.into_iter().map(|m| (m.name, m.user_id)).collect();
+    let (expenditures, transfers) = export.to_forms(&matches);
+    // Rows an earlier upload brought in are left out, and nobody is notified
+    // about each row.
+    let created = Mutation::import_splitwise(db, Some(user.id), expenditures, transfers)
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let expected = export.expected_debt_changes(&matches);
+    let users = Query::find_users(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let balances = users
+        .iter()
+        .filter(|u| expected.contains_key(&u.id))
+        .map(|u| {
+            let debt = |debts: &HashMap<i32, Currency>| debts.get(&u.id).cloned().unwrap_or(0.into());
+            (
+                u.name.clone().unwrap_or(u.username.clone()),
+                expected[&u.id].clone(),
+                debt(&created.debts_after) - debt(&created.debts_before),
+            )
+        })
+        .collect();
+    Ok(SplitwiseResultTemplate {
+        title: Some("Import from Splitwise"),
+        mobile_client: false,
+        unread: unread.0,
+        flash: None,
+        expenditures: created.expenditures.len(),
+        transfers: created.transfers.len(),
+        already_imported: created.already_imported,
+        skipped: export.skipped,
+        balances,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "notifications/index.html")]
+struct NotificationsTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    notifications: Vec<entities::notification::Model>,
+}
+
+const NOTIFICATIONS_SHOWN: u64 = 100;
+
+/// How many unread notifications whoever is logged in has, for the
+/// navigation bar. Zero when nobody is.
+struct Unread(u64);
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for Unread {
+    type Error = std::convert::Infallible;
+    async fn from_request(req: &'r rocket::Request<'_>) -> rocket::request::Outcome<Unread, Self::Error> {
+        let user = req.guard::<auth::User>().await.succeeded();
+        let count = match (user, req.rocket().state::<DatabaseConnection>()) {
+            (Some(user), Some(db)) => Query::count_unread_notifications(db, user.id).await.unwrap_or_default(),
+            _ => 0,
+        };
+        rocket::request::Outcome::Success(Unread(count))
+    }
+}
+
+#[get("/notifications")]
+async fn notifications_index<'a>(
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    user: auth::User,
+    csrf_token: CsrfToken,
+) -> Result<NotificationsTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let notifications = Query::find_notifications(db, user.id, NOTIFICATIONS_SHOWN).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let unread = Query::count_unread_notifications(db, user.id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(NotificationsTemplate {
+        title: Some("Notifications"),
+        mobile_client: false,
+        unread,
+        flash,
+        authenticity_token: csrf_token.authenticity_token(),
+        notifications,
+    })
+}
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct NotificationsReadForm {
+    /// The newest notification on the page when it was shown.
+    pub up_to: i32,
+}
+
+#[post("/notifications/read", data="<form>")]
+async fn notifications_read_post(
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+    form: CsrfForm<NotificationsReadForm>,
+) -> Result<Redirect, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    Mutation::mark_notifications_read(db, user.id, form.up_to).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(Redirect::to(uri!(notifications_index())))
+}
+
+#[derive(Template)]
+#[template(path = "categories/index.html")]
+struct CategoriesTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    /// Each with its path, in the order of `category_paths`.
+    categories: Vec<(CategoryPath, entities::category::Model)>,
+}
+
+#[get("/categories")]
+async fn categories_index<'a>(
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    _user: auth::User,
+    csrf_token: CsrfToken,
+    unread: Unread,
+) -> Result<CategoriesTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let models = Query::find_categories(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let by_id: HashMap<i32, &entities::category::Model> = models.iter().map(|c| (c.id, c)).collect();
+    let categories = category_paths(&models)
+        .into_iter()
+        .filter_map(|path| by_id.get(&path.id).map(|c| (path, (*c).clone())))
+        .collect();
+    Ok(CategoriesTemplate {
+        title: Some("Categories"),
+        mobile_client: false,
+        unread: unread.0,
+        flash,
+        authenticity_token: csrf_token.authenticity_token(),
+        categories,
+    })
+}
+
+#[post("/categories", data="<form>")]
+async fn category_new_post(
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+    form: CsrfForm<CategoryForm>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    category_edit_post(None, db, user, form).await
+}
+
+#[post("/categories/<id>", data="<form>")]
+async fn category_edit_post(
+    id: Option<i32>,
+    db: &State<DatabaseConnection>,
+    _user: auth::User,
+    form: CsrfForm<CategoryForm>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let category = match Mutation::save_category(db, id, form.clone()).await {
+        Ok(category) => category,
+        Err(DbErr::Custom(message)) => return Ok(Flash::error(Redirect::to(uri!(categories_index())), message)),
+        Err(e) => return Err(Custom(Status::InternalServerError, format!("{:?}", e))),
+    };
+    Ok(Flash::success(Redirect::to(uri!(categories_index())), format!("Saved {}.", category.name)))
+}
+
+#[post("/categories/<id>/delete", data="<_form>")]
+async fn category_delete_post(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+    _form: CsrfForm<DeleteForm<'_>>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    Mutation::delete_category(db, Some(user.id), id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(Flash::success(Redirect::to(uri!(categories_index())), "Category deleted; anything under it moved up."))
+}
+
+#[derive(Template)]
+#[template(path = "user/index.html")]
+struct UserIndexTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    user: auth::User,
+    tokens: Vec<entities::api_token::Model>,
+    totp: Option<entities::user_totp::Model>,
+    recovery_codes_left: u64,
+    notifications: entities::notification_preference::Model,
+    reminder_threshold: Option<Currency>,
+    oidc_display_name: Option<String>,
+}
+
+#[get("/user")]
+async fn user_index<'a>(
+    db: &State<DatabaseConnection>,
+    digest_config: &State<digest::Config>,
+    flash: Option<FlashMessage<'a>>,
+    user: auth::User,
+    csrf_token: CsrfToken,
+    oidc: Option<&State<auth::oidc::Provider>>,
+    unread: Unread,
+) -> Result<UserIndexTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let tokens = Query::find_api_tokens(db, user.id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let totp = Query::get_user_totp(db, user.id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let recovery_codes_left = Query::count_unused_recovery_codes(db, user.id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let notifications = Query::get_notification_preference(db, user.id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(UserIndexTemplate {
+        title: Some("User Settings"),
+        mobile_client: false,
+        unread: unread.0,
+        flash,
+        authenticity_token: csrf_token.authenticity_token(),
+        user,
+        tokens,
+        totp,
+        recovery_codes_left,
+        notifications,
+        reminder_threshold: digest_config.reminder_threshold.clone(),
+        oidc_display_name: oidc.map(|p| p.display_name.clone()),
+    })
+}
+
+#[post("/user/notifications", data="<form>")]
+async fn user_notifications_post(
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+    form: CsrfForm<NotificationForm>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    Mutation::set_notification_preferences(db, user.id, form.clone()).await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(Flash::success(Redirect::to(uri!(user_index())), "Notification settings saved."))
+}
+
+#[derive(Template)]
+#[template(path = "user/totp.html")]
+struct UserTotpTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    secret: String,
+    otpauth_url: String,
+    qr_code: String,
+}
+
+/// Holds the secret between showing it and the user confirming a code.
+const TOTP_ENROLMENT_COOKIE: &str = "totp_enrolment";
+
+#[get("/user/totp")]
+async fn user_totp<'a>(
+    flash: Option<FlashMessage<'a>>,
+    user: auth::User,
+    csrf_token: CsrfToken,
+    cookies: &CookieJar<'_>,
+    unread: Unread,
+) -> Result<UserTotpTemplate<'a>, Custom<String>> {
+    let secret = auth::totp::generate_secret();
+    let otpauth_url = auth::totp::otpauth_url(&secret, &user.username).map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    let qr_code = auth::totp::qr_code(&secret, &user.username).map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    cookies.add_private((TOTP_ENROLMENT_COOKIE, secret.clone()));
+    Ok(UserTotpTemplate {
+        title: Some("Set Up Two-Factor Authentication"),
+        mobile_client: false,
+        unread: unread.0,
+        flash,
+        authenticity_token: csrf_token.authenticity_token(),
+        secret,
+        otpauth_url,
+        qr_code,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "user/recovery_codes.html")]
+struct UserRecoveryCodesTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    codes: Vec<String>,
+}
+
+#[post("/user/totp", data="<form>")]
+async fn user_totp_post<'a>(
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+    form: CsrfForm<CodeForm>,
+    cookies: &CookieJar<'_>,
+    unread: Unread,
+) -> Result<UserRecoveryCodesTemplate<'a>, Flash<Redirect>> {
+    let db = db as &DatabaseConnection;
+    let fail = |message: String| Flash::error(Redirect::to(uri!(user_totp())), message);
+    let secret = cookies.get_private(TOTP_ENROLMENT_COOKIE)
+        .map(|c| c.value().to_string())
+        .ok_or_else(|| fail("Setup expired; scan the new code and try again.".to_string()))?;
+    let code: String = form.code.chars().filter(|c| !c.is_whitespace()).collect();
+    let step = auth::totp::check(&secret, &code, chrono::Utc::now().timestamp() as u64)
+        .map_err(|e| fail(e.to_string()))?
+        .ok_or_else(|| fail("That code didn't match; scan the new code and try again.".to_string()))?;
+    let codes = auth::totp::generate_recovery_codes();
+    Mutation::enable_totp(db, user.id, secret, step, codes.iter().map(|c| auth::totp::hash_recovery_code(c)).collect())
+        .await
+        .map_err(|e| fail(format!("{:?}", e)))?;
+    cookies.remove_private(TOTP_ENROLMENT_COOKIE);
+    // Like API tokens, the codes aren't stored, so show them now.
+    Ok(UserRecoveryCodesTemplate {
+        title: Some("Recovery Codes"),
+        mobile_client: false,
+        unread: unread.0,
+        flash: None,
+        codes,
+    })
+}
+
+#[post("/user/totp/disable", data="<form>")]
+async fn user_totp_disable_post(
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+    form: CsrfForm<CodeForm>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let db = db as &DatabaseConnection;
+    let fail = |message: String| Flash::error(Redirect::to(uri!(user_index())), message);
+    if !auth::totp::verify(db, user.id, &form.code).await.map_err(|e| fail(e.to_string()))? {
+        return Err(fail("Incorrect code.".to_string()));
+    }
+    Mutation::disable_totp(db, user.id).await.map_err(|e| fail(format!("{:?}", e)))?;
+    Ok(Flash::success(Redirect::to(uri!(user_index())), "Two-factor authentication turned off."))
+}
+
+#[derive(Template)]
+#[template(path = "user/token.html")]
+struct UserTokenTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    token: entities::api_token::Model,
+    secret: String,
+}
+
+#[post("/user/tokens", data="<form>")]
+async fn user_token_new_post<'a>(
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+    form: CsrfForm<ApiTokenForm>,
+    unread: Unread,
+) -> Result<UserTokenTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let secret = auth::api_token::generate();
+    let token = Mutation::create_api_token(db, user.id, auth::api_token::hash(&secret), form.clone())
+        .await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    // The secret isn't stored, so render it here instead of redirecting.
+    Ok(UserTokenTemplate {
+        title: Some("New API Token"),
+        mobile_client: false,
+        unread: unread.0,
+        flash: None,
+        token,
+        secret,
+    })
+}
+
+#[post("/user/tokens/<id>/delete", data="<form>")]
+async fn user_token_delete_post(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    user: auth::User,
+    form: CsrfForm<DeleteForm<'_>>,
+) -> Result<Either<Flash<Redirect>, Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    if form.delete.is_some() {
+        let result = Mutation::delete_api_token(db, user.id, id)
+            .await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+        if result.rows_affected == 0 {
+            return Err(Custom(Status::NotFound, "token not found".to_string()));
+        }
+        Ok(Either::Left(Flash::success(Redirect::to(uri!(user_index())), "API token revoked.")))
+    } else {
+        Ok(Either::Right(Redirect::to(uri!(user_index()))))
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/identities.html")]
+struct AdminIdentitiesTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    users: Vec<entities::user::Model>,
+    identities: Vec<(entities::external_identity::Model, Option<entities::user::Model>)>,
+}
+
+#[get("/admin/identities")]
+async fn admin_identities<'a>(
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    _admin: auth::Admin,
+    csrf_token: CsrfToken,
+    unread: Unread,
+) -> Result<AdminIdentitiesTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let users = Query::find_users(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let identities = Query::find_identities(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(AdminIdentitiesTemplate {
+        title: Some("Identities"),
+        mobile_client: false,
+        unread: unread.0,
+        flash,
+        authenticity_token: csrf_token.authenticity_token(),
+        users,
+        identities,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "admin/users.html")]
+struct AdminUsersTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    /// Each user and whether they have two-factor authentication on.
+    users: Vec<(entities::user::Model, bool)>,
+}
+
+#[get("/admin/users")]
+async fn admin_users<'a>(
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    _admin: auth::Admin,
+    csrf_token: CsrfToken,
+    unread: Unread,
+) -> Result<AdminUsersTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let users = Query::find_users(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let totp_user_ids = Query::find_totp_user_ids(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let users = users.into_iter().map(|u| {
+        let totp = totp_user_ids.contains(&u.id);
+        (u, totp)
+    }).collect();
+    Ok(AdminUsersTemplate {
+        title: Some("Users"),
+        mobile_client: false,
+        unread: unread.0,
+        flash,
+        authenticity_token: csrf_token.authenticity_token(),
+        users,
+    })
+}
+
+#[post("/admin/users/<id>/totp/reset", data="<_form>")]
+async fn admin_user_totp_reset_post(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    _admin: auth::Admin,
+    _form: CsrfForm<DeleteForm<'_>>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let user = Query::get_user_by_id(db, id).await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+        .ok_or(Custom(Status::NotFound, "user not found".to_string()))?;
+    Mutation::disable_totp(db, user.id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    info!("Two-factor authentication reset for {}", user.username);
+    Ok(Flash::success(
+        Redirect::to(uri!(admin_users())),
+        format!("Two-factor authentication turned off for {}.", user.name.unwrap_or(user.username)),
+    ))
+}
+
+#[derive(Template)]
+#[template(path = "admin/lockouts.html")]
+struct AdminLockoutsTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    now: chrono::DateTime<chrono::Utc>,
+    throttles: Vec<entities::login_throttle::Model>,
+}
+
+#[get("/admin/lockouts")]
+async fn admin_lockouts<'a>(
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    _admin: auth::Admin,
+    csrf_token: CsrfToken,
+    unread: Unread,
+) -> Result<AdminLockoutsTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let throttles = Query::find_login_throttles(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(AdminLockoutsTemplate {
+        title: Some("Failed Logins"),
+        mobile_client: false,
+        unread: unread.0,
+        flash,
+        authenticity_token: csrf_token.authenticity_token(),
+        now: chrono::Utc::now(),
+        throttles,
+    })
+}
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct UnlockForm {
+    pub target: String,
+}
+#[post("/admin/lockouts/clear", data="<form>")]
+async fn admin_lockout_clear_post(
+    db: &State<DatabaseConnection>,
+    _admin: auth::Admin,
+    form: CsrfForm<UnlockForm>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    Mutation::clear_login_throttle(db, &form.target).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(Flash::success(Redirect::to(uri!(admin_lockouts())), format!("Cleared failed logins for {}.", form.target)))
+}
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct LinkIdentityForm {
+    pub user_id: i32,
+}
+#[post("/admin/identities/<id>/link", data="<form>")]
+async fn admin_identity_link_post(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    _admin: auth::Admin,
+    form: CsrfForm<LinkIdentityForm>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let user = Query::get_user_by_id(db, form.user_id).await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+        .ok_or(Custom(Status::BadRequest, "user not found".to_string()))?;
+    let identity = Mutation::link_identity(db, id, user.id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(Flash::success(
+        Redirect::to(uri!(admin_identities())),
+        format!(
+            "Identity {} from {} linked to {}.",
+            identity.subject,
+            identity.provider,
+            user.name.unwrap_or(user.username),
+        )
+    ))
+}
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct MergeUsersForm {
+    pub from_user_id: i32,
+    pub into_user_id: i32,
+}
+#[post("/admin/users/merge", data="<form>")]
+async fn admin_merge_users_post(
+    db: &State<DatabaseConnection>,
+    admin: auth::Admin,
+    form: CsrfForm<MergeUsersForm>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let from = Query::get_user_by_id(db, form.from_user_id).await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+        .ok_or(Custom(Status::BadRequest, "user not found".to_string()))?;
+    let into = Query::get_user_by_id(db, form.into_user_id).await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+        .ok_or(Custom(Status::BadRequest, "user not found".to_string()))?;
+    if from.id == into.id {
+        return Ok(Flash::error(Redirect::to(uri!(admin_identities())), "Cannot merge a user into itself."));
+    }
+    let summary = Mutation::merge_users(db, Some(admin.id), from.id, into.id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    let (from, into) = (from.name.unwrap_or(from.username), into.name.unwrap_or(into.username));
+    let mut message = format!("Merged {} into {}.", from, into);
+    if summary.api_tokens > 0 {
+        message.push_str(&format!(" {} API token{} now act as {}.", summary.api_tokens, if summary.api_tokens == 1 { "" } else { "s" }, into));
+    }
+    if summary.two_factor_removed {
+        message.push_str(&format!(" {}'s two-factor authentication was removed.", from));
+    }
+    Ok(Flash::success(Redirect::to(uri!(admin_identities())), message))
+}
+
+#[derive(Template)]
+#[template(path = "admin/webhooks.html")]
+struct AdminWebhooksTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    webhooks: Vec<entities::webhook::Model>,
+    events: Vec<&'static str>,
+    /// Set right after creating a webhook, the only time it's shown.
+    new_secret: Option<String>,
+}
+
+async fn admin_webhooks_page<'a>(
+    db: &DatabaseConnection,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    csrf_token: CsrfToken,
+    new_secret: Option<String>,
+) -> Result<AdminWebhooksTemplate<'a>, Custom<String>> {
+    let webhooks = Query::find_webhooks(db).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(AdminWebhooksTemplate {
+        title: Some("Webhooks"),
+        mobile_client: false,
+        unread,
+        flash,
+        authenticity_token: csrf_token.authenticity_token(),
+        webhooks,
+        events: webhook::Event::ALL.iter().map(|e| e.name()).collect(),
+        new_secret,
+    })
+}
+
+#[get("/admin/webhooks")]
+async fn admin_webhooks<'a>(
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    _admin: auth::Admin,
+    csrf_token: CsrfToken,
+    unread: Unread,
+) -> Result<AdminWebhooksTemplate<'a>, Custom<String>> {
+    admin_webhooks_page(db, unread.0, flash, csrf_token, None).await
+}
+
+#[post("/admin/webhooks", data="<form>")]
+async fn admin_webhook_new_post<'a>(
+    db: &State<DatabaseConnection>,
+    _admin: auth::Admin,
+    csrf_token: CsrfToken,
+    form: CsrfForm<WebhookForm>,
+    unread: Unread,
+) -> Result<Either<AdminWebhooksTemplate<'a>, Flash<Redirect>>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    if let Err(e) = webhook::check_target(&form.url).await {
+        return Ok(Either::Right(Flash::error(Redirect::to(uri!(admin_webhooks())), format!("Can't send webhooks to {}: {}", form.url, e))));
+    }
+    let secret = webhook::generate_secret();
+    let created = Mutation::create_webhook(db, form.clone(), secret.clone()).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    info!("Webhook {} created for {}", created.id, created.url);
+    admin_webhooks_page(db, unread.0, None, csrf_token, Some(secret)).await.map(Either::Left)
+}
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct WebhookActiveForm {
+    pub active: bool,
+}
+
+#[post("/admin/webhooks/<id>/active", data="<form>")]
+async fn admin_webhook_active_post(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    _admin: auth::Admin,
+    form: CsrfForm<WebhookActiveForm>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let webhook = Mutation::set_webhook_active(db, id, form.active).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(Flash::success(
+        Redirect::to(uri!(admin_webhooks())),
+        format!("Webhook to {} {}.", webhook.url, if webhook.active { "resumed" } else { "paused" }),
+    ))
+}
+
+#[post("/admin/webhooks/<id>/delete", data="<_form>")]
+async fn admin_webhook_delete_post(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    _admin: auth::Admin,
+    _form: CsrfForm<DeleteForm<'_>>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    Mutation::delete_webhook(db, id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(Flash::success(Redirect::to(uri!(admin_webhooks())), "Webhook deleted."))
+}
+
+#[derive(Template)]
+#[template(path = "admin/webhook.html")]
+struct AdminWebhookTemplate<'a> {
+    title: Option<&'a str>,
+    mobile_client: bool,
+    unread: u64,
+    flash: Option<FlashMessage<'a>>,
+    authenticity_token: String,
+    webhook: entities::webhook::Model,
+    deliveries: Vec<entities::webhook_delivery::Model>,
+}
+
+#[get("/admin/webhooks/<id>")]
+async fn admin_webhook<'a>(
+    id: i32,
+    db: &State<DatabaseConnection>,
+    flash: Option<FlashMessage<'a>>,
+    _admin: auth::Admin,
+    csrf_token: CsrfToken,
+    unread: Unread,
+) -> Result<AdminWebhookTemplate<'a>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    let webhook = Query::get_webhook(db, id).await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+        .ok_or(Custom(Status::NotFound, "webhook not found".to_string()))?;
+    let deliveries = Query::find_webhook_deliveries(db, id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(AdminWebhookTemplate {
+        title: Some("Webhook Deliveries"),
+        mobile_client: false,
+        unread: unread.0,
+        flash,
+        authenticity_token: csrf_token.authenticity_token(),
+        webhook,
+        deliveries,
+    })
+}
+
+#[post("/admin/webhooks/<id>/deliveries/<delivery_id>/redeliver", data="<_form>")]
+async fn admin_webhook_redeliver_post(
+    id: i32,
+    delivery_id: i32,
+    db: &State<DatabaseConnection>,
+    _admin: auth::Admin,
+    _form: CsrfForm<DeleteForm<'_>>,
+) -> Result<Flash<Redirect>, Custom<String>> {
+    let db = db as &DatabaseConnection;
+    Mutation::redeliver_webhook(db, delivery_id).await.map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(Flash::success(Redirect::to(uri!(admin_webhook(id = id))), "Delivery queued."))
+}
+
+#[catch(401)]
+fn unauthorized() -> Redirect {
+    Redirect::to(uri!(auth_login()))
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    db_uri: String,
+    public_path: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            db_uri: "sqlite://database.sqlite3".to_string(),
+            public_path: option_env!("ROCKET_PUBLIC_PATH").unwrap_or("public").into(),
+        }
+    }
+}
+
+#[rocket::main]
+async fn main() -> Result<(), rocket::Error> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = cli::run(&args).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    rocket().await.launch().await?;
+    Ok(())
+}
+
+async fn rocket() -> rocket::Rocket<rocket::Build> {
+    let figment = rocket::Config::figment()
+        .join(("secret_key", Key::generate().master()));
+    let config: Config = figment.extract().unwrap();
+    let oidc_config: auth::oidc::Config = figment.extract().unwrap();
+    let mail_config: mail::Config = figment.extract().unwrap();
+    let db = Database::connect(config.db_uri).await.unwrap();
+    let oidc = match auth::oidc::Provider::discover(&oidc_config).await {
+        Ok(oidc) => oidc,
+        Err(e) => {
+            error!("OIDC login disabled: {}", e);
+            None
+        }
+    };
+    let rocket = match oidc {
+        Some(oidc) => rocket::custom(figment).manage(oidc),
+        None => rocket::custom(figment),
+    };
+    let rocket = match mail::Mailer::new(&mail_config) {
+        Ok(Some(mailer)) => rocket.manage(mailer),
+        Ok(None) => rocket,
+        Err(e) => {
+            error!("Email disabled: {}", e);
+            rocket
+        }
+    };
+    rocket
+        .attach(AdHoc::config::<auth::Config>())
+        .attach(AdHoc::config::<auth::oidc::Config>())
+        .attach(AdHoc::config::<mail::Config>())
+        .attach(AdHoc::config::<digest::Config>())
+        .attach(AdHoc::config::<journal::Config>())
+        .attach(AdHoc::try_on_ignite("Periods", |rocket| async move {
+            match rocket.figment().extract::<Periods>().map_err(|e| e.to_string()).and_then(|p| p.validate().map(|_| p)) {
+                Ok(periods) => Ok(rocket.manage(periods)),
+                Err(e) => {
+                    error!("Invalid periods: {}", e);
+                    Err(rocket)
+                }
+            }
+        }))
+        .attach(AdHoc::on_liftoff("Webhooks", |rocket| Box::pin(async move {
+            let db = rocket.state::<DatabaseConnection>().expect("database is managed").clone();
+            match rocket.figment().extract::<webhook::Config>() {
+                Ok(config) => webhook::spawn(db, config),
+                Err(e) => error!("Webhooks disabled: {}", e),
+            }
+        })))
+        .attach(AdHoc::on_liftoff("Backups", |rocket| Box::pin(async move {
+            let db = rocket.state::<DatabaseConnection>().expect("database is managed").clone();
+            match rocket.figment().extract::<backup::Config>() {
+                Ok(config) => backup::spawn(db, config),
+                Err(e) => error!("Scheduled backups disabled: {}", e),
+            }
+        })))
+        .attach(AdHoc::on_liftoff("Digests", |rocket| Box::pin(async move {
+            let db = rocket.state::<DatabaseConnection>().expect("database is managed").clone();
+            let periods = rocket.state::<Periods>().expect("periods are managed").clone();
+            match (rocket.figment().extract::<mail::Config>(), rocket.figment().extract::<digest::Config>()) {
+                (Ok(mail_config), Ok(config)) => digest::spawn(db, mail_config, config, periods),
+                (Err(e), _) | (_, Err(e)) => error!("Digests disabled: {}", e),
+            }
+        })))
         .attach(rocket_csrf::Fairing::default())
         .manage(db)
         .register("/", catchers![unauthorized])
+        .register("/api", api::catchers())
+        .mount("/api/v1", api::routes())
         .mount("/", routes![
             status_index,
+            totals_index,
             spend_index,
             spend_edit,
             spend_new_post,
@@ -499,10 +2423,61 @@ async fn rocket() -> _ {
             transfer_edit_post,
             transfer_delete,
             transfer_delete_post,
+            activity_index,
+            notifications_index,
+            notifications_read_post,
+            spend_history,
+            transfer_history,
+            admin_user_history,
             history_index,
+            history_expenditures_csv,
+            history_transfers_csv,
+            history_statement_csv,
+            history_journal,
+            ledger_index,
+            reports_index,
+            reports_csv,
+            import_index,
+            import_map_post,
+            import_preview_post,
+            import_post,
+            import_splitwise_match_post,
+            import_splitwise_post,
+            categories_index,
+            category_new_post,
+            category_edit_post,
+            category_delete_post,
             user_index,
+            user_token_new_post,
+            user_token_delete_post,
+            user_totp,
+            user_totp_post,
+            user_totp_disable_post,
+            user_notifications_post,
+            admin_identities,
+            admin_users,
+            admin_webhooks,
+            admin_webhook_new_post,
+            admin_webhook_active_post,
+            admin_webhook_delete_post,
+            admin_webhook,
+            admin_webhook_redeliver_post,
+            admin_user_totp_reset_post,
+            admin_lockouts,
+            admin_lockout_clear_post,
+            admin_identity_link_post,
+            admin_merge_users_post,
             auth_login,
-            auth_login_post])
+            auth_login_post,
+            auth_second_factor,
+            auth_second_factor_post,
+            auth_oidc_login,
+            auth_oidc_callback,
+            password_forgot,
+            password_forgot_post,
+            password_reset,
+            password_reset_post,
+            user_password_post])
         .mount("/js", FileServer::new(config.public_path.join("js/")))
         .mount("/css", FileServer::new(config.public_path.join("css/")))
         .mount("/icons", FileServer::new(config.public_path.join("icons/")))
@@ -0,0 +1,280 @@
+//! Mail about changes to expenditures and transfers.
+//!
+//! As in the Python app, everyone involved in either the old or the new
+//! version of a record hears about a change, but only if they have an email
+//! address and have opted in. Routes call these after `Mutation` has
+//! returned, so nothing is sent for changes that were rolled back, and a
+//! failure to send is logged rather than failing the request.
+use std::collections::{BTreeSet, HashMap};
+
+use askama::Template;
+use sea_orm::{DatabaseConnection, DbErr};
+
+use crate::entities::{audit_log::Action, prelude::*};
+use crate::mail;
+use crate::service::Query;
+
+/// An expenditure as it reads in mail, with user names filled in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpenditureSummary {
+    pub id: i32,
+    pub spender_id: i32,
+    pub spender: String,
+    pub amount: Currency,
+    pub description: String,
+    /// `(user ID, name, share)` for each split.
+    pub shares: Vec<(i32, String, Currency)>,
+}
+
+/// A transfer as it reads in mail, with user names filled in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransferSummary {
+    pub id: i32,
+    pub debtor_id: i32,
+    pub debtor: String,
+    pub creditor_id: i32,
+    pub creditor: String,
+    pub amount: Currency,
+    pub description: String,
+}
+
+async fn user_names(db: &DatabaseConnection) -> Result<HashMap<i32, String>, DbErr> {
+    Ok(Query::find_users(db)
+        .await?
+        .into_iter()
+        .map(|u| (u.id, u.name.unwrap_or(u.username)))
+        .collect())
+}
+
+fn name(names: &HashMap<i32, String>, id: i32) -> String {
+    names.get(&id).cloned().unwrap_or_else(|| id.to_string())
+}
+
+impl ExpenditureSummary {
+    pub async fn load(db: &DatabaseConnection, id: i32) -> Result<Option<Self>, DbErr> {
+        let Some((expenditure, splits)) = Query::get_expenditure_with_splits(db, id).await? else {
+            return Ok(None);
+        };
+        let names = user_names(db).await?;
+        let mut shares: Vec<_> = splits
+            .into_iter()
+            .map(|s| (s.user_id, name(&names, s.user_id), s.share))
+            .collect();
+        shares.sort_by_key(|(user_id, ..)| *user_id);
+        Ok(Some(Self {
+            id: expenditure.id,
+            spender_id: expenditure.spender_id,
+            spender: name(&names, expenditure.spender_id),
+            amount: expenditure.amount,
+            description: expenditure.description.unwrap_or_default(),
+            shares,
+        }))
+    }
+
+    /// The same message the web interface flashes after a change.
+    pub fn message(&self, action: Action) -> String {
+        format!("Expenditure of {} paid for by {} {}.", self.amount, self.spender, action)
+    }
+
+    /// The spender and everyone with a share.
+    fn involved(&self) -> impl Iterator<Item = i32> + '_ {
+        std::iter::once(self.spender_id).chain(
+            self.shares.iter().filter(|(_, _, share)| !share.is_zero()).map(|(user_id, ..)| *user_id))
+    }
+}
+
+impl TransferSummary {
+    pub async fn load(db: &DatabaseConnection, id: i32) -> Result<Option<Self>, DbErr> {
+        let Some(transfer) = Query::get_transfer(db, id).await? else {
+            return Ok(None);
+        };
+        let names = user_names(db).await?;
+        Ok(Some(Self {
+            id: transfer.id,
+            debtor_id: transfer.debtor_id,
+            debtor: name(&names, transfer.debtor_id),
+            creditor_id: transfer.creditor_id,
+            creditor: name(&names, transfer.creditor_id),
+            amount: transfer.amount,
+            description: transfer.description.unwrap_or_default(),
+        }))
+    }
+
+    /// The same message the web interface flashes after a change.
+    pub fn message(&self, action: Action) -> String {
+        format!("Transfer of {} from {} to {} {}.", self.amount, self.debtor, self.creditor, action)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "emails/expenditure.txt")]
+struct ExpenditureEmail<'a> {
+    op: &'a str,
+    expenditure: &'a ExpenditureSummary,
+    /// Lines like `Alice: $5.00 -> $7.50` for shares that changed.
+    share_changes: Vec<String>,
+    /// The previous version, already rendered, when this is an update.
+    previous: Option<String>,
+    url: String,
+}
+
+#[derive(Template)]
+#[template(path = "emails/transfer.txt")]
+struct TransferEmail<'a> {
+    op: &'a str,
+    transfer: &'a TransferSummary,
+    url: String,
+}
+
+fn share_changes(old: &ExpenditureSummary, new: &ExpenditureSummary) -> Vec<String> {
+    let zero = || Currency::from(0);
+    let old_shares: HashMap<_, _> = old.shares.iter().map(|(id, name, share)| (*id, (name, share))).collect();
+    let new_shares: HashMap<_, _> = new.shares.iter().map(|(id, name, share)| (*id, (name, share))).collect();
+    let user_ids: BTreeSet<i32> = old_shares.keys().chain(new_shares.keys()).copied().collect();
+    user_ids
+        .into_iter()
+        .filter_map(|id| {
+            let (name, before) = old_shares.get(&id).map_or((None, zero()), |(name, share)| (Some(*name), (*share).clone()));
+            let (name, after) = new_shares.get(&id).map_or((name, zero()), |(name, share)| (Some(*name), (*share).clone()));
+            (before != after).then(|| format!("{}: {} -> {}", name.map_or("", String::as_str), before, after))
+        })
+        .collect()
+}
+
+fn render_expenditure(config: &mail::Config, action: Action, old: Option<&ExpenditureSummary>, new: Option<&ExpenditureSummary>) -> askama::Result<Option<String>> {
+    let url = |e: &ExpenditureSummary| config.url(uri!(crate::spend_edit(id = e.id)));
+    let (expenditure, previous, share_changes) = match (old, new) {
+        (Some(old), Some(new)) => {
+            let previous = ExpenditureEmail {
+                op: "previously",
+                expenditure: old,
+                share_changes: Vec::new(),
+                previous: None,
+                url: url(old),
+            }
+                .render()?;
+            (new, Some(previous), share_changes(old, new))
+        }
+        (Some(e), None) | (None, Some(e)) => (e, None, Vec::new()),
+        (None, None) => return Ok(None),
+    };
+    ExpenditureEmail {
+        op: &action.to_string(),
+        expenditure,
+        share_changes,
+        previous,
+        url: url(expenditure),
+    }
+        .render()
+        .map(Some)
+}
+
+async fn send_all(db: &DatabaseConnection, mailer: &mail::Mailer, user_ids: BTreeSet<i32>, subject: &str, body: String) -> Result<(), DbErr> {
+    let subject = format!("BlueChips: {}", subject);
+    for user in Query::find_change_recipients(db, user_ids).await? {
+        let Some(email) = user.email else { continue };
+        if let Err(e) = mailer.send(&email, &subject, body.clone()).await {
+            error!("Failed to send change notification to {}: {}", email, e);
+        }
+    }
+    Ok(())
+}
+
+/// Mail everyone involved in an expenditure that was created (`old` is
+/// `None`), updated or deleted (`new` is `None`). `subject` is the message
+/// shown to the user who made the change.
+pub async fn expenditure(
+    db: &DatabaseConnection,
+    mailer: Option<&mail::Mailer>,
+    config: &mail::Config,
+    action: Action,
+    subject: &str,
+    old: Option<&ExpenditureSummary>,
+    new: Option<&ExpenditureSummary>,
+) {
+    let Some(mailer) = mailer else { return };
+    let body = match render_expenditure(config, action, old, new) {
+        Ok(Some(body)) => body,
+        Ok(None) => return,
+        Err(e) => {
+            error!("Failed to render change notification: {}", e);
+            return;
+        }
+    };
+    let user_ids = old.into_iter().chain(new).flat_map(ExpenditureSummary::involved).collect();
+    if let Err(e) = send_all(db, mailer, user_ids, subject, body).await {
+        error!("Failed to send change notifications: {:?}", e);
+    }
+}
+
+/// Mail the debtor and creditor of a transfer that was created, updated or
+/// deleted, including the old ones if they changed.
+pub async fn transfer(
+    db: &DatabaseConnection,
+    mailer: Option<&mail::Mailer>,
+    config: &mail::Config,
+    action: Action,
+    subject: &str,
+    old: Option<&TransferSummary>,
+    new: Option<&TransferSummary>,
+) {
+    let Some(mailer) = mailer else { return };
+    let Some(transfer) = new.or(old) else { return };
+    let body = match (TransferEmail {
+        op: &action.to_string(),
+        transfer,
+        url: config.url(uri!(crate::transfer_edit(id = transfer.id))),
+    })
+        .render()
+    {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to render change notification: {}", e);
+            return;
+        }
+    };
+    let user_ids = old.into_iter().chain(new).flat_map(|t| [t.debtor_id, t.creditor_id]).collect();
+    if let Err(e) = send_all(db, mailer, user_ids, subject, body).await {
+        error!("Failed to send change notifications: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expenditure(shares: &[(i32, &str, i32)]) -> ExpenditureSummary {
+        ExpenditureSummary {
+            id: 3,
+            spender_id: 1,
+            spender: "Alice".to_string(),
+            amount: Currency::from(shares.iter().map(|(.., share)| share).sum::<i32>()),
+            description: "Groceries".to_string(),
+            shares: shares.iter().map(|(id, name, share)| (*id, name.to_string(), Currency::from(*share))).collect(),
+        }
+    }
+
+    #[test]
+    fn update_shows_changed_shares_and_previous_version() {
+        let old = expenditure(&[(1, "Alice", 500), (2, "Bob", 500)]);
+        let new = expenditure(&[(1, "Alice", 500), (2, "Bob", 0), (3, "Carol", 500)]);
+        let body = render_expenditure(&mail::Config::default(), Action::Update, Some(&old), Some(&new)).unwrap().unwrap();
+        assert!(body.starts_with("The following expenditure was updated:"));
+        assert!(body.contains("Bob: $5.00 -> $0.00"));
+        assert!(body.contains("Carol: $0.00 -> $5.00"));
+        assert!(!body.contains("Alice: $5.00 -> "));
+        assert!(body.contains("The following expenditure was previously:"));
+        assert!(body.contains("http://localhost:8000/spend/3/edit"));
+        let involved: BTreeSet<i32> = [&old, &new].into_iter().flat_map(ExpenditureSummary::involved).collect();
+        assert_eq!(involved, BTreeSet::from([1, 2, 3]));
+    }
+
+    #[test]
+    fn create_has_no_previous_version() {
+        let new = expenditure(&[(1, "Alice", 250), (2, "Bob", 250)]);
+        let body = render_expenditure(&mail::Config::default(), Action::Create, None, Some(&new)).unwrap().unwrap();
+        assert!(body.contains("$5.00 paid for by Alice"));
+        assert!(body.contains("Bob: $2.50"));
+        assert!(!body.contains("previously"));
+    }
+}
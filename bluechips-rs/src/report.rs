@@ -0,0 +1,490 @@
+//! Spending reports, each a table of amounts that is drawn as a bar chart
+//! and can be downloaded as CSV.
+//!
+//! Charts are SVG written out here, so they show up without JavaScript.
+//! Every report is built from the splits of expenditures in its date range,
+//! which add up to the expenditures' amounts.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use chrono::{Datelike, Months, NaiveDate};
+use quick_xml::escape::escape;
+use rocket::futures::TryStreamExt;
+use rocket::http::uri::fmt::{Formatter as UriFormatter, Query as UriQuery, UriDisplay};
+use sea_orm::{DatabaseConnection, DbErr};
+
+use crate::entities::{category, prelude::Currency};
+use crate::export::record;
+use crate::service::{category_paths, DateField, HistoryFilter, Query, SplitRow};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, FromFormField)]
+pub enum Kind {
+    /// Spending each month, by top-level category.
+    #[default]
+    #[field(value = "category")]
+    Category,
+    /// Spending each month, by who paid.
+    #[field(value = "spender")]
+    Spender,
+    /// What each person paid against their share.
+    #[field(value = "payers")]
+    Payers,
+    /// Spending each month, one series per year.
+    #[field(value = "years")]
+    Years,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 4] = [Kind::Category, Kind::Spender, Kind::Payers, Kind::Years];
+
+    pub fn slug(&self) -> &'static str {
+        match self {
+            Kind::Category => "category",
+            Kind::Spender => "spender",
+            Kind::Payers => "payers",
+            Kind::Years => "years",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Kind::Category => "Monthly by Category",
+            Kind::Spender => "Monthly by Spender",
+            Kind::Payers => "Paid vs. Share",
+            Kind::Years => "Year over Year",
+        }
+    }
+
+    /// Where the report starts when no date is given: a year of months, or
+    /// for year over year, the start of last year.
+    fn default_from(&self, to: NaiveDate) -> NaiveDate {
+        let first_of_month = to.with_day(1).expect("valid date");
+        match self {
+            Kind::Years => NaiveDate::from_ymd_opt(to.year() - 1, 1, 1).expect("valid date"),
+            _ => first_of_month - Months::new(11),
+        }
+    }
+}
+
+impl UriDisplay<UriQuery> for Kind {
+    fn fmt(&self, f: &mut UriFormatter<'_, UriQuery>) -> std::fmt::Result {
+        f.write_value(self.slug())
+    }
+}
+
+rocket::http::impl_from_uri_param_identity!([UriQuery] Kind);
+
+/// A report's query string.
+#[derive(FromForm, UriDisplayQuery, Clone, Default)]
+pub struct Params {
+    #[field(default = Kind::default())]
+    pub kind: Kind,
+    pub from: Option<DateField>,
+    pub to: Option<DateField>,
+}
+
+/// The most a report covers, so a mistyped year doesn't ask for a row per
+/// month since year 1.
+const MAX_MONTHS: u32 = 10 * 12;
+
+impl Params {
+    /// The dates the report covers, filling in defaults as of `today`. A
+    /// `from` more than ten years before `to` is moved up.
+    pub fn range(&self, today: NaiveDate) -> (NaiveDate, NaiveDate) {
+        let to = self.to.as_ref().map_or(today, |d| d.0);
+        let from = self.from.as_ref().map_or_else(|| self.kind.default_from(to), |d| d.0);
+        (from.max(to - Months::new(MAX_MONTHS)), to)
+    }
+}
+
+/// A report's numbers: one row per month or person, one column per series.
+pub struct Table {
+    /// What the rows are, like `Month`.
+    pub label: &'static str,
+    pub series: Vec<String>,
+    pub rows: Vec<(String, Vec<Currency>)>,
+    /// Whether the series are parts of a whole, and so are drawn stacked.
+    pub stacked: bool,
+}
+
+/// Every month from `from` to `to`, inclusive.
+fn months(from: NaiveDate, to: NaiveDate) -> Vec<(i32, u32)> {
+    let mut months = Vec::new();
+    let mut month = from.with_day(1).expect("valid date");
+    while month <= to {
+        months.push((month.year(), month.month()));
+        month = month + Months::new(1);
+    }
+    months
+}
+
+fn add(total: &mut Currency, amount: &Currency) {
+    *total = total.clone() + amount.clone();
+}
+
+/// Sum `rows` into a table with a row per month and a column per key.
+fn monthly<K: Eq + std::hash::Hash + Copy>(rows: &[SplitRow], from: NaiveDate, to: NaiveDate, key: impl Fn(&SplitRow) -> K, series: &[K]) -> Vec<(String, Vec<Currency>)> {
+    let mut cells: HashMap<((i32, u32), K), Currency> = HashMap::new();
+    for row in rows {
+        let Some(date) = row.date else {
+            continue;
+        };
+        add(cells.entry(((date.year(), date.month()), key(row))).or_insert(0.into()), &row.share);
+    }
+    months(from, to)
+        .into_iter()
+        .map(|month| (
+            format!("{}-{:02}", month.0, month.1),
+            series.iter().map(|k| cells.get(&(month, *k)).cloned().unwrap_or(0.into())).collect(),
+        ))
+        .collect()
+}
+
+fn by_category(rows: &[SplitRow], categories: &[category::Model], from: NaiveDate, to: NaiveDate) -> Table {
+    let parents: HashMap<i32, Option<i32>> = categories.iter().map(|c| (c.id, c.parent_id)).collect();
+    // The top-level category each one is under, or itself.
+    let top = |id: Option<i32>| -> Option<i32> {
+        let mut id = id?;
+        for _ in 0..parents.len() {
+            match parents.get(&id).copied().flatten() {
+                Some(parent) if parents.contains_key(&parent) => id = parent,
+                _ => break,
+            }
+        }
+        Some(id)
+    };
+    let used: std::collections::HashSet<Option<i32>> = rows.iter().map(|r| top(r.category_id)).collect();
+    let mut keys: Vec<Option<i32>> = Vec::new();
+    let mut series = Vec::new();
+    for path in category_paths(categories).into_iter().filter(|c| c.depth == 0 && used.contains(&Some(c.id))) {
+        keys.push(Some(path.id));
+        series.push(path.path);
+    }
+    if used.contains(&None) {
+        keys.push(None);
+        series.push("Uncategorized".to_string());
+    }
+    Table {
+        label: "Month",
+        series,
+        rows: monthly(rows, from, to, |r| top(r.category_id), &keys),
+        stacked: true,
+    }
+}
+
+fn by_spender(rows: &[SplitRow], users: &[(i32, String)], from: NaiveDate, to: NaiveDate) -> Table {
+    let spenders: Vec<&(i32, String)> = users.iter().filter(|(id, _)| rows.iter().any(|r| r.spender_id == *id)).collect();
+    let keys: Vec<i32> = spenders.iter().map(|(id, _)| *id).collect();
+    Table {
+        label: "Month",
+        series: spenders.iter().map(|(_, name)| name.clone()).collect(),
+        rows: monthly(rows, from, to, |r| r.spender_id, &keys),
+        stacked: true,
+    }
+}
+
+fn payers(rows: &[SplitRow], users: &[(i32, String)]) -> Table {
+    let mut paid: HashMap<i32, Currency> = HashMap::new();
+    let mut shares: HashMap<i32, Currency> = HashMap::new();
+    for row in rows {
+        add(paid.entry(row.spender_id).or_insert(0.into()), &row.share);
+        add(shares.entry(row.user_id).or_insert(0.into()), &row.share);
+    }
+    Table {
+        label: "Person",
+        series: vec!["Paid".to_string(), "Share".to_string()],
+        rows: users
+            .iter()
+            .filter(|(id, _)| paid.contains_key(id) || shares.contains_key(id))
+            .map(|(id, name)| (
+                name.clone(),
+                vec![paid.get(id).cloned().unwrap_or(0.into()), shares.get(id).cloned().unwrap_or(0.into())],
+            ))
+            .collect(),
+        stacked: false,
+    }
+}
+
+fn years(rows: &[SplitRow], from: NaiveDate, to: NaiveDate) -> Table {
+    let mut cells: BTreeMap<(u32, i32), Currency> = BTreeMap::new();
+    for row in rows {
+        let Some(date) = row.date else {
+            continue;
+        };
+        add(cells.entry((date.month(), date.year())).or_insert(0.into()), &row.share);
+    }
+    let years: Vec<i32> = (from.year()..=to.year()).collect();
+    Table {
+        label: "Month",
+        series: years.iter().map(|y| y.to_string()).collect(),
+        rows: (1..=12)
+            .map(|month| (
+                NaiveDate::from_ymd_opt(2000, month, 1).expect("valid date").format("%b").to_string(),
+                years.iter().map(|year| cells.get(&(month, *year)).cloned().unwrap_or(0.into())).collect(),
+            ))
+            .collect(),
+        stacked: false,
+    }
+}
+
+/// Build the `kind` report for expenditures between `from` and `to`.
+pub async fn build(db: &DatabaseConnection, kind: Kind, from: NaiveDate, to: NaiveDate) -> Result<Table, DbErr> {
+    let filter = HistoryFilter { from: Some(from), to: Some(to), ..Default::default() };
+    let rows: Vec<SplitRow> = Query::stream_expenditure_splits(db, &filter).await?.try_collect().await?;
+    let users: Vec<(i32, String)> = Query::find_users(db)
+        .await?
+        .into_iter()
+        .map(|u| (u.id, u.name.unwrap_or(u.username)))
+        .collect();
+    Ok(match kind {
+        Kind::Category => by_category(&rows, &Query::find_categories(db).await?, from, to),
+        Kind::Spender => by_spender(&rows, &users, from, to),
+        Kind::Payers => payers(&rows, &users),
+        Kind::Years => years(&rows, from, to),
+    })
+}
+
+const WIDTH: f64 = 720.0;
+const HEIGHT: f64 = 300.0;
+const LEFT: f64 = 80.0;
+const RIGHT: f64 = 10.0;
+const TOP: f64 = 10.0;
+const BOTTOM: f64 = 30.0;
+const LEGEND_ROW: f64 = 18.0;
+const COLORS: [&str; 10] = [
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7", "#9c755f", "#bab0ac",
+];
+
+/// A round step for axis ticks of at least `raw` cents: 1, 2 or 5 times a
+/// power of ten.
+fn nice_step(raw: f64) -> f64 {
+    if raw <= 1.0 {
+        return 1.0;
+    }
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].into_iter().map(|m| m * magnitude).find(|step| *step >= raw).unwrap_or(10.0 * magnitude)
+}
+
+fn cents(amount: &Currency) -> f64 {
+    i32::from(amount.clone()) as f64
+}
+
+impl Table {
+    pub fn csv(&self) -> Vec<String> {
+        let mut lines = vec![record(std::iter::once(self.label.to_string()).chain(self.series.iter().cloned()))];
+        lines.extend(self.rows.iter().map(|(label, values)| {
+            record(std::iter::once(label.clone()).chain(values.iter().map(Currency::to_decimal_string)))
+        }));
+        lines
+    }
+
+    /// A bar chart of the table, with a legend underneath. Each bar has a
+    /// tooltip with its amount.
+    pub fn svg(&self) -> String {
+        let (mut low, mut high) = (0f64, 0f64);
+        for (_, values) in &self.rows {
+            let values = values.iter().map(cents);
+            if self.stacked {
+                let (up, down): (Vec<f64>, Vec<f64>) = values.partition(|v| *v >= 0.0);
+                high = high.max(up.iter().sum());
+                low = low.min(down.iter().sum());
+            } else {
+                for v in values {
+                    high = high.max(v);
+                    low = low.min(v);
+                }
+            }
+        }
+        let step = nice_step((high - low).max(100.0) / 5.0);
+        // With nothing but zeros, keep one step so the scale isn't 0/0.
+        let (low, high) = ((low / step).floor() * step, (high / step).ceil() * step);
+        let high = high.max(low + step);
+        let plot_width = WIDTH - LEFT - RIGHT;
+        let plot_height = HEIGHT - TOP - BOTTOM;
+        let y = |v: f64| TOP + (high - v) / (high - low) * plot_height;
+        let legend_per_row = 5;
+        let height = HEIGHT + LEGEND_ROW * self.series.len().div_ceil(legend_per_row) as f64 + 10.0;
+
+        let mut svg = String::new();
+        let _ = write!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" class="chart" viewBox="0 0 {WIDTH} {height}" width="{WIDTH}" height="{height}" font-family="sans-serif" font-size="11">"#,
+        );
+        let mut tick = low;
+        while tick <= high + step / 2.0 {
+            let _ = write!(
+                svg,
+                r##"<line x1="{LEFT}" x2="{}" y1="{y:.1}" y2="{y:.1}" stroke="#ddd" /><text x="{}" y="{:.1}" text-anchor="end">{}</text>"##,
+                WIDTH - RIGHT,
+                LEFT - 6.0,
+                y(tick) + 4.0,
+                Currency::from(tick.round() as i32),
+                y = y(tick),
+            );
+            tick += step;
+        }
+
+        let band = plot_width / self.rows.len().max(1) as f64;
+        // Leave out some labels when there are too many to fit.
+        let label_every = self.rows.len().div_ceil(12).max(1);
+        for (i, (label, values)) in self.rows.iter().enumerate() {
+            let x0 = LEFT + band * i as f64;
+            let (mut up, mut down) = (0f64, 0f64);
+            for (s, value) in values.iter().enumerate() {
+                let v = cents(value);
+                if v == 0.0 {
+                    continue;
+                }
+                let (x, width, top, bottom) = if self.stacked {
+                    let (top, bottom) = if v > 0.0 { (up + v, up) } else { (down, down + v) };
+                    if v > 0.0 { up += v } else { down += v }
+                    (x0 + band * 0.15, band * 0.7, top, bottom)
+                } else {
+                    let width = band * 0.8 / values.len() as f64;
+                    (x0 + band * 0.1 + width * s as f64, width, v.max(0.0), v.min(0.0))
+                };
+                let _ = write!(
+                    svg,
+                    r#"<rect x="{x:.1}" y="{:.1}" width="{width:.1}" height="{:.1}" fill="{}"><title>{}, {}: {}</title></rect>"#,
+                    y(top),
+                    y(bottom) - y(top),
+                    COLORS[s % COLORS.len()],
+                    escape(label.as_str()),
+                    escape(self.series[s].as_str()),
+                    value,
+                );
+            }
+            if i % label_every == 0 {
+                let _ = write!(
+                    svg,
+                    r#"<text x="{:.1}" y="{}" text-anchor="middle">{}</text>"#,
+                    x0 + band / 2.0,
+                    HEIGHT - BOTTOM + 16.0,
+                    escape(label.as_str()),
+                );
+            }
+        }
+        let _ = write!(svg, r##"<line x1="{LEFT}" x2="{}" y1="{y:.1}" y2="{y:.1}" stroke="#333" />"##, WIDTH - RIGHT, y = y(0.0));
+
+        let legend_width = plot_width / legend_per_row as f64;
+        for (s, name) in self.series.iter().enumerate() {
+            let x = LEFT + legend_width * (s % legend_per_row) as f64;
+            let y = HEIGHT + LEGEND_ROW * (s / legend_per_row) as f64;
+            let _ = write!(
+                svg,
+                r#"<rect x="{x:.1}" y="{y:.1}" width="10" height="10" fill="{}" /><text x="{:.1}" y="{:.1}">{}</text>"#,
+                COLORS[s % COLORS.len()],
+                x + 14.0,
+                y + 9.0,
+                escape(name.as_str()),
+            );
+        }
+        svg.push_str("</svg>");
+        svg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(date: (i32, u32, u32), spender_id: i32, user_id: i32, share: i32, category_id: Option<i32>) -> SplitRow {
+        SplitRow {
+            expenditure_id: 1,
+            date: NaiveDate::from_ymd_opt(date.0, date.1, date.2),
+            description: None,
+            spender_id,
+            amount: share.into(),
+            user_id,
+            share: share.into(),
+            category_id,
+        }
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn categories_roll_up_to_the_top_level() {
+        let categories = vec![
+            category::Model { id: 1, name: "Food".to_string(), parent_id: None },
+            category::Model { id: 2, name: "Groceries".to_string(), parent_id: Some(1) },
+            category::Model { id: 3, name: "Rent".to_string(), parent_id: None },
+        ];
+        let rows = vec![
+            row((2026, 9, 3), 1, 1, 500, Some(2)),
+            row((2026, 9, 3), 1, 2, 500, Some(1)),
+            row((2026, 10, 1), 2, 2, 700, None),
+        ];
+        let table = by_category(&rows, &categories, date(2026, 9, 1), date(2026, 10, 19));
+        assert_eq!(table.series, vec!["Food", "Uncategorized"]);
+        let rows: Vec<(String, Vec<i32>)> = table.rows.into_iter().map(|(m, v)| (m, v.into_iter().map(i32::from).collect())).collect();
+        assert_eq!(rows, vec![
+            ("2026-09".to_string(), vec![1000, 0]),
+            ("2026-10".to_string(), vec![0, 700]),
+        ]);
+    }
+
+    #[test]
+    fn paid_against_share() {
+        let users = vec![(1, "Alice".to_string()), (2, "Bob".to_string()), (3, "Carol".to_string())];
+        let rows = vec![row((2026, 9, 3), 1, 1, 500, None), row((2026, 9, 3), 1, 2, 300, None)];
+        let table = payers(&rows, &users);
+        let rows: Vec<(String, Vec<i32>)> = table.rows.into_iter().map(|(m, v)| (m, v.into_iter().map(i32::from).collect())).collect();
+        assert_eq!(rows, vec![("Alice".to_string(), vec![800, 500]), ("Bob".to_string(), vec![0, 300])]);
+    }
+
+    #[test]
+    fn years_line_up_by_month() {
+        let rows = vec![row((2025, 3, 3), 1, 1, 100, None), row((2026, 3, 9), 1, 1, 250, None)];
+        let table = years(&rows, date(2025, 1, 1), date(2026, 10, 19));
+        assert_eq!(table.series, vec!["2025", "2026"]);
+        assert_eq!(table.rows[2].0, "Mar");
+        assert_eq!(table.rows[2].1, vec![Currency::from(100), Currency::from(250)]);
+    }
+
+    #[test]
+    fn chart_escapes_labels() {
+        let table = Table {
+            label: "Person",
+            series: vec!["Paid".to_string()],
+            rows: vec![("Tom & Jerry".to_string(), vec![1234.into()]), ("<Nobody>".to_string(), vec![0.into()])],
+            stacked: false,
+        };
+        let svg = table.svg();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("Tom &amp; Jerry"));
+        assert!(svg.contains("&lt;Nobody&gt;"));
+        assert_eq!(svg.matches("<title>").count(), 1);
+        assert_eq!(table.csv(), vec!["Person,Paid\n", "Tom & Jerry,12.34\n", "<Nobody>,0.00\n"]);
+    }
+
+    #[test]
+    fn charts_of_zeros_have_a_scale() {
+        let table = Table {
+            label: "Month",
+            series: vec!["Food".to_string()],
+            rows: vec![("2026-09".to_string(), vec![0.into()]), ("2026-10".to_string(), vec![0.into()])],
+            stacked: true,
+        };
+        let svg = table.svg();
+        assert!(!svg.contains("NaN"));
+        assert!(!svg.contains("inf"));
+    }
+
+    #[test]
+    fn ranges_are_capped_at_ten_years() {
+        let params = Params { kind: Kind::Years, from: Some(DateField(date(1, 1, 1))), to: Some(DateField(date(2026, 10, 19))) };
+        assert_eq!(params.range(date(2026, 10, 19)), (date(2016, 10, 19), date(2026, 10, 19)));
+        let params = Params { kind: Kind::Category, from: None, to: None };
+        assert_eq!(params.range(date(2026, 10, 19)), (date(2025, 11, 1), date(2026, 10, 19)));
+    }
+
+    #[test]
+    fn steps_are_round() {
+        assert_eq!(nice_step(130.0), 200.0);
+        assert_eq!(nice_step(4100.0), 5000.0);
+        assert_eq!(nice_step(10000.0), 10000.0);
+    }
+}
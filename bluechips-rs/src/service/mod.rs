@@ -1,7 +1,46 @@
 mod mutation;
+mod period;
 mod query;
 
 pub use mutation::*;
+pub use period::*;
 pub use query::*;
 
 pub use sea_orm;
+
+/// An empty in-memory database with every table, for tests.
+#[cfg(test)]
+pub(crate) async fn test_db() -> sea_orm::DatabaseConnection {
+    use crate::entities::*;
+    use sea_orm::{ConnectionTrait, Database, EntityTrait, Schema};
+
+    async fn create<E: EntityTrait>(db: &sea_orm::DatabaseConnection, entity: E) {
+        let backend = db.get_database_backend();
+        let table = Schema::new(backend).create_table_from_entity(entity);
+        db.execute(backend.build(&table)).await.expect("create table");
+    }
+
+    let db = Database::connect("sqlite::memory:").await.expect("in-memory database");
+    create(&db, user::Entity).await;
+    create(&db, auth_session::Entity).await;
+    create(&db, category::Entity).await;
+    create(&db, expenditure::Entity).await;
+    create(&db, expenditure_tag::Entity).await;
+    create(&db, split::Entity).await;
+    create(&db, subitem::Entity).await;
+    create(&db, transfer::Entity).await;
+    create(&db, external_identity::Entity).await;
+    create(&db, api_token::Entity).await;
+    create(&db, password_reset::Entity).await;
+    create(&db, login_throttle::Entity).await;
+    create(&db, user_totp::Entity).await;
+    create(&db, recovery_code::Entity).await;
+    create(&db, notification_preference::Entity).await;
+    create(&db, import_mapping::Entity).await;
+    create(&db, audit_log::Entity).await;
+    create(&db, notification::Entity).await;
+    create(&db, webhook::Entity).await;
+    create(&db, webhook_delivery::Entity).await;
+    create(&db, imported_row::Entity).await;
+    db
+}
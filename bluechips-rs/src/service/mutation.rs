@@ -1,9 +1,14 @@
-use std::collections::{HashSet, HashMap};
+use std::collections::{BTreeSet, HashSet, HashMap};
 
 use crate::entities::{prelude::*, *};
+use crate::service::Query;
+use crate::webhook;
 use sea_orm::{prelude::*, *};
 use sea_orm::ActiveValue::{Set, NotSet, Unchanged};
 use rand::seq::IteratorRandom;
+use rocket::http::uri::fmt::{Formatter as UriFormatter, Query as UriQuery, UriDisplay};
+use rocket::serde::Deserialize;
+use utoipa::ToSchema;
 
 fn nonzero_splits<'v>(splits: &HashMap<i32, Currency>) -> rocket::form::Result<'v, ()> {
     if splits.values().sum::<Currency>().is_zero() {
@@ -15,32 +20,223 @@ fn nonzero_splits<'v>(splits: &HashMap<i32, Currency>) -> rocket::form::Result<'
 
 #[derive(Clone, Hash, PartialEq, Eq)]
 pub struct DateField(pub chrono::NaiveDate);
+/// The date picker's format, or ISO 8601 from API clients' query strings.
 #[rocket::async_trait]
 impl<'v> rocket::form::FromFormField<'v> for DateField {
     fn from_value(field: rocket::form::ValueField<'v>) -> rocket::form::Result<'v, Self> {
-        chrono::NaiveDate::parse_from_str(field.value, "%m/%d/%Y").map(|v| Self(v)).map_err(|e| rocket::form::Error::validation(format!("failed to parse date: {:?}", e)).into())
+        chrono::NaiveDate::parse_from_str(field.value, "%m/%d/%Y")
+            .or_else(|_| chrono::NaiveDate::parse_from_str(field.value, "%Y-%m-%d"))
+            .map(|v| Self(v))
+            .map_err(|e| rocket::form::Error::validation(format!("failed to parse date: {:?}", e)).into())
+    }
+}
+impl UriDisplay<UriQuery> for DateField {
+    fn fmt(&self, f: &mut UriFormatter<'_, UriQuery>) -> std::fmt::Result {
+        f.write_value(self.0.format("%m/%d/%Y").to_string())
+    }
+}
+rocket::http::impl_from_uri_param_identity!([UriQuery] DateField);
+/// The JSON API takes ISO 8601 dates rather than the date picker's format.
+impl<'de> Deserialize<'de> for DateField {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        chrono::NaiveDate::deserialize(deserializer).map(Self)
     }
 }
 
-#[derive(FromForm, Clone, PartialEq, Eq)]
+#[derive(FromForm, Deserialize, ToSchema, Clone, PartialEq, Eq)]
 pub struct ExpenditureForm {
     pub spender_id: i32,
     pub amount: Currency,
     pub description: String,
+    #[schema(value_type = String, format = Date)]
     pub date: DateField,
     #[field(validate=nonzero_splits())]
     pub splits: HashMap<i32, Currency>,
+    #[serde(default)]
+    pub category_id: Option<i32>,
+    /// Separated by commas, like `vacation, car`.
+    #[serde(default)]
+    #[field(default = String::new())]
+    pub tags: String,
+}
+
+impl ExpenditureForm {
+    /// The tags, trimmed, without blanks or repeats.
+    pub fn tag_list(&self) -> BTreeSet<String> {
+        self.tags
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(String::from)
+            .collect()
+    }
+}
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct CategoryForm {
+    #[field(validate=len(1..))]
+    pub name: String,
+    pub parent_id: Option<i32>,
+}
+
+/// What to tell each user involved in a change, other than whoever made it.
+/// `before` and `after` are the snapshots written to the audit log.
+fn notification_messages(
+    actor_id: Option<i32>,
+    action: audit_log::Action,
+    record_type: audit_log::RecordType,
+    before: Option<&serde_json::Value>,
+    after: Option<&serde_json::Value>,
+    names: &HashMap<i32, String>,
+) -> Vec<(i32, String)> {
+    use audit_log::{Action, RecordType};
+    let Some(record) = after.or(before) else {
+        return vec![];
+    };
+    let actor = actor_id.and_then(|id| names.get(&id)).map_or("Someone", String::as_str);
+    let amount = Currency::from(record["amount"].as_i64().unwrap_or(0) as i32);
+    let description = record["description"]
+        .as_str()
+        .filter(|d| !d.is_empty())
+        .map(|d| format!(": {}", d))
+        .unwrap_or_default();
+    let user_id = |snapshot: &serde_json::Value, key: &str| snapshot[key].as_i64().map(|id| id as i32);
+    let mut messages = vec![];
+    match record_type {
+        RecordType::Expenditure => {
+            let involved = |snapshot: Option<&serde_json::Value>| -> BTreeSet<i32> {
+                let Some(snapshot) = snapshot else {
+                    return BTreeSet::new();
+                };
+                let sharers = snapshot["splits"]
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .filter(|(_, share)| share.as_i64().unwrap_or(0) != 0)
+                    .filter_map(|(id, _)| id.parse().ok());
+                user_id(snapshot, "spender_id").into_iter().chain(sharers).collect()
+            };
+            let (was, is) = (involved(before), involved(after));
+            for id in was.union(&is).filter(|id| Some(**id) != actor_id) {
+                let message = match (action, was.contains(id), is.contains(id)) {
+                    (Action::Create, ..) => format!("{} added a {} expenditure that includes you{}", actor, amount, description),
+                    (Action::Delete, ..) => format!("{} deleted a {} expenditure that included you{}", actor, amount, description),
+                    (_, false, true) => format!("{} added you to a {} expenditure{}", actor, amount, description),
+                    (_, true, false) => format!("{} removed you from a {} expenditure{}", actor, amount, description),
+                    _ => format!("{} edited a {} expenditure that includes you{}", actor, amount, description),
+                };
+                messages.push((*id, message));
+            }
+        }
+        RecordType::Transfer => {
+            let parties = |snapshot: Option<&serde_json::Value>| -> BTreeSet<i32> {
+                snapshot
+                    .into_iter()
+                    .flat_map(|s| [user_id(s, "debtor_id"), user_id(s, "creditor_id")])
+                    .flatten()
+                    .collect()
+            };
+            let name = |id: Option<i32>, recipient: i32| match id {
+                Some(id) if id == recipient => "you".to_string(),
+                Some(id) => names.get(&id).cloned().unwrap_or_else(|| format!("user {}", id)),
+                None => "someone".to_string(),
+            };
+            let (debtor, creditor) = (user_id(record, "debtor_id"), user_id(record, "creditor_id"));
+            for id in parties(before).union(&parties(after)).filter(|id| Some(**id) != actor_id) {
+                let verb = match action {
+                    Action::Create => "recorded",
+                    Action::Delete => "deleted",
+                    _ => "edited",
+                };
+                messages.push((*id, format!(
+                    "{} {} a {} transfer from {} to {}{}",
+                    actor, verb, amount, name(debtor, *id), name(creditor, *id), description,
+                )));
+            }
+        }
+        RecordType::User | RecordType::Import => {}
+    }
+    messages
+}
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct ApiTokenForm {
+    #[field(validate=len(1..))]
+    pub name: String,
+    pub read_only: bool,
+    /// Leave empty for a token that never expires.
+    pub expires_in_days: Option<u32>,
+}
+
+fn http_url<'v>(url: &str) -> rocket::form::Result<'v, ()> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        Err(rocket::form::Error::validation("must be an http:// or https:// URL"))?;
+    }
+    Ok(())
+}
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct WebhookForm {
+    #[field(validate=http_url())]
+    pub url: String,
+    /// Event names or patterns such as `transfer.*`.
+    #[field(validate=len(1..))]
+    pub events: Vec<String>,
+}
+
+#[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct NotificationForm {
+    pub email_changes: bool,
+    pub digest: notification_preference::DigestFrequency,
+    pub remind: bool,
 }
 
 #[derive(FromForm, Clone, PartialEq, Eq)]
+pub struct ImportMappingForm {
+    #[field(validate=len(1..))]
+    pub name: String,
+    pub date_column: String,
+    pub amount_column: String,
+    pub description_column: String,
+    #[field(validate=len(1..))]
+    pub date_format: String,
+    pub negate: bool,
+    pub default_split: crate::import::SplitPreset,
+}
+
+#[derive(FromForm, Deserialize, ToSchema, Clone, PartialEq, Eq)]
 pub struct TransferForm {
     pub debtor_id: i32,
     pub creditor_id: i32,
     pub amount: Currency,
     pub description: String,
+    #[schema(value_type = String, format = Date)]
     pub date: DateField,
 }
 
+/// What `Mutation::import_splitwise` did.
+#[derive(Default)]
+pub struct SplitwiseImport {
+    pub expenditures: Vec<expenditure::Model>,
+    pub transfers: Vec<transfer::Model>,
+    /// Rows left out because an earlier import brought them in.
+    pub already_imported: usize,
+    pub debts_before: HashMap<i32, Currency>,
+    pub debts_after: HashMap<i32, Currency>,
+}
+
+/// The `provider` for users logged in by the trusted proxy's headers.
+pub const PROXY_PROVIDER: &str = "authentik";
+
+/// What `Mutation::merge_users` did besides moving records.
+pub struct MergeSummary {
+    /// API tokens that now act as the surviving user.
+    pub api_tokens: u64,
+    /// Whether the merged user had two-factor authentication, which is
+    /// deleted with them.
+    pub two_factor_removed: bool,
+}
+
 pub struct Mutation;
 
 impl Mutation {
@@ -97,32 +293,311 @@ impl Mutation {
             .await?;
         Ok(())
     }
-    pub async fn save_expenditure(db: &DbConn, id: Option<i32>, form_data: ExpenditureForm) -> Result<expenditure::Model, TransactionError<DbErr>> {
+    async fn set_tags<C: ConnectionTrait>(db: &C, expenditure_id: i32, tags: BTreeSet<String>) -> Result<(), DbErr> {
+        ExpenditureTag::delete_many()
+            .filter(expenditure_tag::Column::ExpenditureId.eq(expenditure_id))
+            .exec(db)
+            .await?;
+        if tags.is_empty() {
+            return Ok(());
+        }
+        ExpenditureTag::insert_many(tags.into_iter().map(|tag| expenditure_tag::ActiveModel {
+            expenditure_id: Set(expenditure_id),
+            tag: Set(tag),
+        }))
+            .exec_without_returning(db)
+            .await?;
+        Ok(())
+    }
+    /// Append a row to the audit log and queue webhooks for the change,
+    /// unless nothing actually changed.
+    async fn record_change<C: ConnectionTrait>(
+        db: &C,
+        actor_id: Option<i32>,
+        action: audit_log::Action,
+        record_type: audit_log::RecordType,
+        record_id: i32,
+        before: Option<serde_json::Value>,
+        after: Option<serde_json::Value>,
+    ) -> Result<(), DbErr> {
+        if action == audit_log::Action::Update && before == after {
+            return Ok(());
+        }
+        Self::append_audit(db, actor_id, action, record_type, record_id, before.as_ref(), after.as_ref()).await?;
+        Self::notify_involved(db, actor_id, action, record_type, record_id, before.as_ref(), after.as_ref()).await?;
+        let Some(event) = webhook::Event::for_change(record_type, action) else {
+            return Ok(());
+        };
+        let webhooks = Webhook::find()
+            .filter(crate::entities::webhook::Column::Active.eq(true))
+            .all(db)
+            .await?;
+        if webhooks.is_empty() {
+            return Ok(());
+        }
+        Self::queue_webhooks(db, &webhooks, event, serde_json::json!({
+            "id": record_id,
+            "actor_id": actor_id,
+            "before": before,
+            "after": after,
+        })).await?;
+        if webhooks.iter().any(|w| webhook::subscribed(&w.events, webhook::Event::BalanceChanged)) {
+            let debts: serde_json::Map<String, serde_json::Value> = Query::get_debts(db)
+                .await?
+                .into_iter()
+                .map(|(user_id, amount)| (user_id.to_string(), serde_json::json!(amount)))
+                .collect();
+            Self::queue_webhooks(db, &webhooks, webhook::Event::BalanceChanged, serde_json::json!({
+                "debts": debts,
+            })).await?;
+        }
+        Ok(())
+    }
+    async fn append_audit<C: ConnectionTrait>(
+        db: &C,
+        actor_id: Option<i32>,
+        action: audit_log::Action,
+        record_type: audit_log::RecordType,
+        record_id: i32,
+        before: Option<&serde_json::Value>,
+        after: Option<&serde_json::Value>,
+    ) -> Result<(), DbErr> {
+        audit_log::ActiveModel {
+            time: Set(chrono::Utc::now()),
+            actor_id: Set(actor_id),
+            action: Set(action),
+            record_type: Set(record_type),
+            record_id: Set(record_id),
+            before: Set(before.map(|v| v.to_string())),
+            after: Set(after.map(|v| v.to_string())),
+            ..Default::default()
+        }
+            .insert(db)
+            .await?;
+        Ok(())
+    }
+    async fn notify_involved<C: ConnectionTrait>(
+        db: &C,
+        actor_id: Option<i32>,
+        action: audit_log::Action,
+        record_type: audit_log::RecordType,
+        record_id: i32,
+        before: Option<&serde_json::Value>,
+        after: Option<&serde_json::Value>,
+    ) -> Result<(), DbErr> {
+        if matches!(record_type, audit_log::RecordType::User | audit_log::RecordType::Import) {
+            return Ok(());
+        }
+        // Messages only name whoever made the change and the parties to a
+        // transfer.
+        let named: HashSet<i32> = before
+            .into_iter()
+            .chain(after)
+            .flat_map(|snapshot| ["debtor_id", "creditor_id"].map(|key| snapshot[key].as_i64()))
+            .flatten()
+            .filter_map(|id| i32::try_from(id).ok())
+            .chain(actor_id)
+            .collect();
+        let names: HashMap<i32, String> = User::find()
+            .filter(user::Column::Id.is_in(named))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|u| (u.id, u.name.unwrap_or(u.username)))
+            .collect();
+        let messages = notification_messages(actor_id, action, record_type, before, after, &names);
+        if messages.is_empty() {
+            return Ok(());
+        }
+        let now = chrono::Utc::now();
+        Notification::insert_many(messages.into_iter().map(|(user_id, message)| notification::ActiveModel {
+            user_id: Set(user_id),
+            time: Set(now),
+            action: Set(action),
+            record_type: Set(record_type),
+            record_id: Set(record_id),
+            message: Set(message),
+            read: Set(None),
+            ..Default::default()
+        }))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+    async fn queue_webhooks<C: ConnectionTrait>(db: &C, webhooks: &[crate::entities::webhook::Model], event: webhook::Event, data: serde_json::Value) -> Result<(), DbErr> {
+        let now = chrono::Utc::now();
+        let payload = serde_json::json!({
+            "event": event.name(),
+            "created": now.to_rfc3339(),
+            "data": data,
+        }).to_string();
+        for subscription in webhooks.iter().filter(|w| webhook::subscribed(&w.events, event)) {
+            webhook_delivery::ActiveModel {
+                webhook_id: Set(subscription.id),
+                event: Set(event.name().to_string()),
+                payload: Set(payload.clone()),
+                created: Set(now),
+                attempts: Set(0),
+                status_code: Set(None),
+                error: Set(None),
+                next_attempt: Set(Some(now)),
+                delivered: Set(None),
+                ..Default::default()
+            }
+                .insert(db)
+                .await?;
+        }
+        Ok(())
+    }
+    /// An expenditure and its splits as they go into the audit log.
+    ///
+    /// Amounts are in cents so the JSON doesn't depend on how currency is
+    /// formatted.
+    ///
+    /// The category and tags are left out when there aren't any, so older
+    /// entries, from before they existed, don't show them as changed.
+    async fn expenditure_snapshot<C: ConnectionTrait>(db: &C, id: i32) -> Result<Option<serde_json::Value>, DbErr> {
+        let Some(expenditure) = Expenditure::find_by_id(id).one(db).await? else {
+            return Ok(None);
+        };
+        let splits: serde_json::Map<String, serde_json::Value> = Split::find()
+            .filter(split::Column::ExpenditureId.eq(id))
+            .order_by_asc(split::Column::UserId)
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|s| (s.user_id.to_string(), i32::from(s.share).into()))
+            .collect();
+        let mut snapshot = serde_json::json!({
+            "spender_id": expenditure.spender_id,
+            "amount": i32::from(expenditure.amount),
+            "description": expenditure.description,
+            "date": expenditure.date.map(|d| d.to_string()),
+            "splits": splits,
+        });
+        if let Some(category_id) = expenditure.category_id {
+            let category = Category::find_by_id(category_id).one(db).await?;
+            snapshot["category"] = category.map_or(category_id.to_string(), |c| c.name).into();
+        }
+        let tags = Query::get_expenditure_tags(db, id).await?;
+        if !tags.is_empty() {
+            snapshot["tags"] = tags.join(", ").into();
+        }
+        Ok(Some(snapshot))
+    }
+    fn transfer_snapshot(transfer: &transfer::Model) -> serde_json::Value {
+        serde_json::json!({
+            "debtor_id": transfer.debtor_id,
+            "creditor_id": transfer.creditor_id,
+            "amount": i32::from(transfer.amount.clone()),
+            "description": transfer.description,
+            "date": transfer.date.map(|d| d.to_string()),
+        })
+    }
+    /// Passwords and other credentials are left out.
+    fn user_snapshot(user: &user::Model) -> serde_json::Value {
+        serde_json::json!({
+            "username": user.username,
+            "name": user.name,
+            "email": user.email,
+            "resident": user.resident,
+            "admin": user.is_admin,
+        })
+    }
+    pub async fn save_expenditure<C: TransactionTrait>(db: &C, actor_id: Option<i32>, id: Option<i32>, form_data: ExpenditureForm) -> Result<expenditure::Model, TransactionError<DbErr>> {
         db.transaction::<_, expenditure::Model, DbErr>(|txn| {
             Box::pin(async move {
-                let expenditure = expenditure::ActiveModel {
-                    id: match id {
-                        Some(id) => Unchanged(id),
-                        None => NotSet,
-                    },
-                    spender_id: Set(form_data.spender_id),
-                    amount: Set(form_data.amount.clone()),
-                    description: Set(Some(form_data.description)),
-                    date: Set(Some(form_data.date.0)),
-                    ..Default::default()
+                let before = match id {
+                    Some(id) => Self::expenditure_snapshot(txn, id).await?,
+                    None => None,
                 };
-                let expenditure = match id {
-                    Some(_) => expenditure.update(txn),
-                    None => expenditure.insert(txn),
-                }
-                    .await?;
-                Self::set_splits(txn, expenditure.id, form_data.amount.clone(), form_data.splits).await?;
+                let expenditure = Self::write_expenditure(txn, id, form_data).await?;
+                let after = Self::expenditure_snapshot(txn, expenditure.id).await?;
+                Self::record_change(
+                    txn,
+                    actor_id,
+                    match id {
+                        Some(_) => audit_log::Action::Update,
+                        None => audit_log::Action::Create,
+                    },
+                    audit_log::RecordType::Expenditure,
+                    expenditure.id,
+                    before,
+                    after,
+                ).await?;
                 Ok(expenditure)
             })
         })
         .await
     }
-    pub async fn save_transfer(db: &DbConn, id: Option<i32>, form_data: TransferForm) -> Result<transfer::Model, DbErr> {
+    /// Save an expenditure with its tags and splits, without recording the
+    /// change.
+    async fn write_expenditure<C: ConnectionTrait>(db: &C, id: Option<i32>, form_data: ExpenditureForm) -> Result<expenditure::Model, DbErr> {
+        let expenditure = expenditure::ActiveModel {
+            id: match id {
+                Some(id) => Unchanged(id),
+                None => NotSet,
+            },
+            spender_id: Set(form_data.spender_id),
+            amount: Set(form_data.amount.clone()),
+            description: Set(Some(form_data.description.clone())),
+            category_id: Set(form_data.category_id),
+            date: Set(Some(form_data.date.0)),
+            entered_time: match id {
+                Some(_) => NotSet,
+                None => Set(Some(chrono::Utc::now().naive_utc())),
+            },
+        };
+        let expenditure = match id {
+            Some(_) => expenditure.update(db),
+            None => expenditure.insert(db),
+        }
+            .await?;
+        Self::set_tags(db, expenditure.id, form_data.tag_list()).await?;
+        Self::set_splits(db, expenditure.id, form_data.amount.clone(), form_data.splits).await?;
+        Ok(expenditure)
+    }
+    /// Delete an expenditure along with its splits.
+    pub async fn delete_expenditure(db: &DbConn, actor_id: Option<i32>, id: i32) -> Result<DeleteResult, TransactionError<DbErr>> {
+        db.transaction::<_, DeleteResult, DbErr>(|txn| {
+            Box::pin(async move {
+                let before = Self::expenditure_snapshot(txn, id).await?;
+                let result = Expenditure::delete_by_id(id).exec(txn).await?;
+                if result.rows_affected > 0 {
+                    Self::record_change(txn, actor_id, audit_log::Action::Delete, audit_log::RecordType::Expenditure, id, before, None).await?;
+                }
+                Ok(result)
+            })
+        })
+        .await
+    }
+    pub async fn save_transfer<C: TransactionTrait>(db: &C, actor_id: Option<i32>, id: Option<i32>, form_data: TransferForm) -> Result<transfer::Model, TransactionError<DbErr>> {
+        db.transaction::<_, transfer::Model, DbErr>(|txn| {
+            Box::pin(async move {
+                let before = match id {
+                    Some(id) => Transfer::find_by_id(id).one(txn).await?.as_ref().map(Self::transfer_snapshot),
+                    None => None,
+                };
+                let transfer = Self::write_transfer(txn, id, form_data).await?;
+                Self::record_change(
+                    txn,
+                    actor_id,
+                    match id {
+                        Some(_) => audit_log::Action::Update,
+                        None => audit_log::Action::Create,
+                    },
+                    audit_log::RecordType::Transfer,
+                    transfer.id,
+                    before,
+                    Some(Self::transfer_snapshot(&transfer)),
+                ).await?;
+                Ok(transfer)
+            })
+        })
+        .await
+    }
+    /// Save a transfer without recording the change.
+    async fn write_transfer<C: ConnectionTrait>(db: &C, id: Option<i32>, form_data: TransferForm) -> Result<transfer::Model, DbErr> {
         let mut model = transfer::ActiveModel {
             debtor_id: Set(form_data.debtor_id),
             creditor_id: Set(form_data.creditor_id),
@@ -134,29 +609,1010 @@ impl Mutation {
         match id {
             Some(id) => {
                model.id = Unchanged(id);
-               model.update(db)
+               model.update(db).await
+            }
+            None => {
+               model.entered_time = Set(Some(chrono::Utc::now().naive_utc()));
+               model.insert(db).await
             }
-            None => model.insert(db),
         }
-            .await
     }
-    pub async fn ensure_user(db: &DbConn, mut user: user::ActiveModel) -> Result<user::Model, TransactionError<DbErr>> {
+    pub async fn delete_transfer(db: &DbConn, actor_id: Option<i32>, id: i32) -> Result<DeleteResult, TransactionError<DbErr>> {
+        db.transaction::<_, DeleteResult, DbErr>(|txn| {
+            Box::pin(async move {
+                let before = Transfer::find_by_id(id).one(txn).await?.as_ref().map(Self::transfer_snapshot);
+                let result = Transfer::delete_by_id(id).exec(txn).await?;
+                if result.rows_affected > 0 {
+                    Self::record_change(txn, actor_id, audit_log::Action::Delete, audit_log::RecordType::Transfer, id, before, None).await?;
+                }
+                Ok(result)
+            })
+        })
+        .await
+    }
+    /// Create or update the user behind an external identity.
+    ///
+    /// Users are matched by `(provider, subject)` so that renaming an account
+    /// in the identity provider updates the existing user instead of creating
+    /// a new one. An identity that hasn't been seen before gets a new user,
+    /// never one that happens to share its username: that would let anyone
+    /// who can pick their username in an OIDC provider take over an account.
+    /// If the username is taken, the new user gets a numbered one, and an
+    /// admin can link the identity to the right user afterwards.
+    ///
+    /// The one exception is `PROXY_PROVIDER`. Users logged in through the
+    /// proxy by username before identities were recorded, so the first
+    /// proxy identity with a user's username takes them over, as long as
+    /// that user has no proxy identity yet.
+    pub async fn ensure_user(db: &DbConn, provider: &str, subject: &str, mut user: user::ActiveModel) -> Result<user::Model, TransactionError<DbErr>> {
+        let provider = provider.to_owned();
+        let subject = subject.to_owned();
         db.transaction::<_, user::Model, DbErr>(|txn| {
             Box::pin(async move {
-                let existing = User::find()
-                    .filter(user::Column::Username.eq(user.username.as_ref()))
+                let identity = ExternalIdentity::find()
+                    .filter(external_identity::Column::Provider.eq(&provider))
+                    .filter(external_identity::Column::Subject.eq(&subject))
+                    .find_also_related(User)
                     .one(txn)
                     .await?;
-                match existing {
+                let new_identity = identity.is_none();
+                let existing = match identity {
+                    Some((_, existing)) => existing,
+                    None if provider == PROXY_PROVIDER => Self::user_without_identity(txn, &provider, user.username.as_ref()).await?,
+                    None => None,
+                };
+                let user = match existing {
                     Some(existing) => {
                         user.id = Unchanged(existing.id);
-                        user.update(txn)
+                        user.username = Set(Self::free_username(txn, user.username.as_ref(), Some(existing.id)).await?);
+                        let user = user.update(txn).await?;
+                        Self::record_change(
+                            txn, None, audit_log::Action::Update, audit_log::RecordType::User, user.id,
+                            Some(Self::user_snapshot(&existing)), Some(Self::user_snapshot(&user)),
+                        ).await?;
+                        user
+                    }
+                    None => {
+                        user.username = Set(Self::free_username(txn, user.username.as_ref(), None).await?);
+                        let user = user.insert(txn).await?;
+                        Self::record_change(
+                            txn, None, audit_log::Action::Create, audit_log::RecordType::User, user.id,
+                            None, Some(Self::user_snapshot(&user)),
+                        ).await?;
+                        user
                     }
-                    None => user.insert(txn),
+                };
+                if new_identity {
+                    external_identity::ActiveModel {
+                        provider: Set(provider),
+                        subject: Set(subject),
+                        user_id: Set(user.id),
+                        created: Set(chrono::Utc::now()),
+                        ..Default::default()
+                    }
+                        .insert(txn)
+                        .await?;
                 }
-                .await
+                Ok(user)
             })
         })
         .await
     }
+    /// The user called `username`, unless they already have an identity
+    /// from `provider`.
+    async fn user_without_identity<C: ConnectionTrait>(db: &C, provider: &str, username: &str) -> Result<Option<user::Model>, DbErr> {
+        let claimed = ExternalIdentity::find()
+            .select_only()
+            .column(external_identity::Column::UserId)
+            .filter(external_identity::Column::Provider.eq(provider))
+            .into_query();
+        User::find()
+            .filter(user::Column::Username.eq(username))
+            .filter(user::Column::Id.not_in_subquery(claimed))
+            .one(db)
+            .await
+    }
+    /// `wanted`, or `wanted-2`, `wanted-3` and so on if someone other than
+    /// `user_id` already has it.
+    async fn free_username<C: ConnectionTrait>(db: &C, wanted: &str, user_id: Option<i32>) -> Result<String, DbErr> {
+        let mut username = wanted.to_owned();
+        for n in 2.. {
+            let mut others = User::find().filter(user::Column::Username.eq(&username));
+            if let Some(user_id) = user_id {
+                others = others.filter(user::Column::Id.ne(user_id));
+            }
+            if others.count(db).await? == 0 {
+                break;
+            }
+            username = format!("{}-{}", wanted, n);
+        }
+        Ok(username)
+    }
+    /// Attach an external identity to `user_id`, who has already logged in
+    /// some other way.
+    ///
+    /// Fails if the identity belongs to someone else; only an admin can
+    /// move it.
+    pub async fn connect_identity(db: &DbConn, provider: &str, subject: &str, user_id: i32) -> Result<external_identity::Model, DbErr> {
+        let existing = ExternalIdentity::find()
+            .filter(external_identity::Column::Provider.eq(provider))
+            .filter(external_identity::Column::Subject.eq(subject))
+            .one(db)
+            .await?;
+        match existing {
+            Some(identity) if identity.user_id == user_id => Ok(identity),
+            Some(_) => Err(DbErr::Custom("That account is already connected to another user.".to_owned())),
+            None => external_identity::ActiveModel {
+                provider: Set(provider.to_owned()),
+                subject: Set(subject.to_owned()),
+                user_id: Set(user_id),
+                created: Set(chrono::Utc::now()),
+                ..Default::default()
+            }
+                .insert(db)
+                .await,
+        }
+    }
+    /// Let `user_id` use the admin pages, or stop them.
+    pub async fn set_admin(db: &DbConn, user_id: i32, is_admin: bool) -> Result<user::Model, TransactionError<DbErr>> {
+        db.transaction::<_, user::Model, DbErr>(|txn| {
+            Box::pin(async move {
+                let before = User::find_by_id(user_id).one(txn).await?;
+                let user = user::ActiveModel {
+                    id: Unchanged(user_id),
+                    is_admin: Set(is_admin),
+                    ..Default::default()
+                }
+                    .update(txn)
+                    .await?;
+                Self::record_change(
+                    txn, None, audit_log::Action::Update, audit_log::RecordType::User, user.id,
+                    before.as_ref().map(Self::user_snapshot), Some(Self::user_snapshot(&user)),
+                ).await?;
+                Ok(user)
+            })
+        })
+        .await
+    }
+    /// Point an external identity at a different user.
+    pub async fn link_identity(db: &DbConn, identity_id: i32, user_id: i32) -> Result<external_identity::Model, DbErr> {
+        external_identity::ActiveModel {
+            id: Unchanged(identity_id),
+            user_id: Set(user_id),
+            ..Default::default()
+        }
+            .update(db)
+            .await
+    }
+    /// Fold `from_id` into `into_id`.
+    ///
+    /// Every expenditure, split, subitem, transfer, identity, notification
+    /// and API token that belongs to `from_id` is reassigned, and then
+    /// `from_id` is deleted along with its two-factor authentication,
+    /// sessions and settings. The audit log is left alone; the merge gets
+    /// its own entry, which says what was moved and removed and which
+    /// `Query::find_audit_log` follows to name whoever made older changes.
+    /// If both users have a split on the same expenditure, the shares are
+    /// added together so the expenditure still sums to its amount.
+    pub async fn merge_users(db: &DbConn, actor_id: Option<i32>, from_id: i32, into_id: i32) -> Result<MergeSummary, TransactionError<DbErr>> {
+        db.transaction::<_, MergeSummary, DbErr>(|txn| {
+            Box::pin(async move {
+                if from_id == into_id {
+                    return Err(DbErr::Custom("cannot merge a user into itself".to_owned()));
+                }
+                let into_splits: HashMap<i32, split::Model> = Split::find()
+                    .filter(split::Column::UserId.eq(into_id))
+                    .all(txn)
+                    .await?
+                    .into_iter()
+                    .map(|s| (s.expenditure_id, s))
+                    .collect();
+                for from_split in Split::find().filter(split::Column::UserId.eq(from_id)).all(txn).await? {
+                    match into_splits.get(&from_split.expenditure_id) {
+                        Some(into_split) => {
+                            split::ActiveModel {
+                                id: Unchanged(into_split.id),
+                                share: Set(into_split.share.clone() + from_split.share.clone()),
+                                ..Default::default()
+                            }
+                                .update(txn)
+                                .await?;
+                            Split::delete_by_id(from_split.id).exec(txn).await?;
+                        }
+                        None => {
+                            split::ActiveModel {
+                                id: Unchanged(from_split.id),
+                                user_id: Set(into_id),
+                                ..Default::default()
+                            }
+                                .update(txn)
+                                .await?;
+                        }
+                    }
+                }
+                Expenditure::update_many()
+                    .col_expr(expenditure::Column::SpenderId, Expr::value(into_id))
+                    .filter(expenditure::Column::SpenderId.eq(from_id))
+                    .exec(txn)
+                    .await?;
+                Subitem::update_many()
+                    .col_expr(subitem::Column::UserId, Expr::value(into_id))
+                    .filter(subitem::Column::UserId.eq(from_id))
+                    .exec(txn)
+                    .await?;
+                Transfer::update_many()
+                    .col_expr(transfer::Column::DebtorId, Expr::value(into_id))
+                    .filter(transfer::Column::DebtorId.eq(from_id))
+                    .exec(txn)
+                    .await?;
+                Transfer::update_many()
+                    .col_expr(transfer::Column::CreditorId, Expr::value(into_id))
+                    .filter(transfer::Column::CreditorId.eq(from_id))
+                    .exec(txn)
+                    .await?;
+                ExternalIdentity::update_many()
+                    .col_expr(external_identity::Column::UserId, Expr::value(into_id))
+                    .filter(external_identity::Column::UserId.eq(from_id))
+                    .exec(txn)
+                    .await?;
+                Notification::update_many()
+                    .col_expr(notification::Column::UserId, Expr::value(into_id))
+                    .filter(notification::Column::UserId.eq(from_id))
+                    .exec(txn)
+                    .await?;
+                // Scripts using these tokens were acting for the same person.
+                let api_tokens = ApiToken::update_many()
+                    .col_expr(api_token::Column::UserId, Expr::value(into_id))
+                    .filter(api_token::Column::UserId.eq(from_id))
+                    .exec(txn)
+                    .await?
+                    .rows_affected;
+                // The surviving user keeps their own second factor, if any.
+                let two_factor_removed = UserTotp::delete_by_id(from_id).exec(txn).await?.rows_affected > 0;
+                RecoveryCode::delete_many()
+                    .filter(recovery_code::Column::UserId.eq(from_id))
+                    .exec(txn)
+                    .await?;
+                let from = User::find_by_id(from_id).one(txn).await?;
+                let into = User::find_by_id(into_id).one(txn).await?;
+                Self::record_change(
+                    txn, actor_id, audit_log::Action::Merge, audit_log::RecordType::User, from_id,
+                    from.as_ref().map(Self::user_snapshot),
+                    into.as_ref().map(|into| serde_json::json!({
+                        "merged_into": into.id,
+                        "username": into.username,
+                        "api_tokens_moved": api_tokens,
+                        "two_factor_removed": two_factor_removed,
+                    })),
+                ).await?;
+                AuthSession::delete_by_id(from_id).exec(txn).await?;
+                User::delete_by_id(from_id).exec(txn).await?;
+                Ok(MergeSummary { api_tokens, two_factor_removed })
+            })
+        })
+        .await
+    }
+    pub async fn create_api_token(db: &DbConn, user_id: i32, token_hash: String, form_data: ApiTokenForm) -> Result<api_token::Model, DbErr> {
+        let now = chrono::Utc::now();
+        api_token::ActiveModel {
+            user_id: Set(user_id),
+            name: Set(form_data.name),
+            token_hash: Set(token_hash),
+            read_only: Set(form_data.read_only),
+            created: Set(now),
+            expires: Set(form_data.expires_in_days.map(|days| now + chrono::Duration::days(days.into()))),
+            last_used: Set(None),
+            ..Default::default()
+        }
+            .insert(db)
+            .await
+    }
+    pub async fn touch_api_token(db: &DbConn, id: i32) -> Result<api_token::Model, DbErr> {
+        api_token::ActiveModel {
+            id: Unchanged(id),
+            last_used: Set(Some(chrono::Utc::now())),
+            ..Default::default()
+        }
+            .update(db)
+            .await
+    }
+    /// Revoke one of `user_id`'s tokens.
+    pub async fn delete_api_token(db: &DbConn, user_id: i32, id: i32) -> Result<DeleteResult, DbErr> {
+        ApiToken::delete_many()
+            .filter(api_token::Column::Id.eq(id))
+            .filter(api_token::Column::UserId.eq(user_id))
+            .exec(db)
+            .await
+    }
+    pub async fn set_password(db: &DbConn, user_id: i32, password_hash: String) -> Result<user::Model, DbErr> {
+        user::ActiveModel {
+            id: Unchanged(user_id),
+            password: Set(Some(password_hash)),
+            ..Default::default()
+        }
+            .update(db)
+            .await
+    }
+    pub async fn create_password_reset(db: &DbConn, user_id: i32, token_hash: String, valid_for: chrono::Duration) -> Result<password_reset::Model, DbErr> {
+        let now = chrono::Utc::now();
+        password_reset::ActiveModel {
+            user_id: Set(user_id),
+            token_hash: Set(token_hash),
+            created: Set(now),
+            expires: Set(now + valid_for),
+            used: Set(None),
+            ..Default::default()
+        }
+            .insert(db)
+            .await
+    }
+    /// Use up a reset token, set the new password, and sign the user out everywhere.
+    ///
+    /// The token is only marked used if it wasn't already, so of two
+    /// requests racing with the same link, one fails.
+    pub async fn reset_password(db: &DbConn, reset: password_reset::Model, password_hash: String) -> Result<user::Model, TransactionError<DbErr>> {
+        db.transaction::<_, user::Model, DbErr>(|txn| {
+            Box::pin(async move {
+                let used = PasswordReset::update_many()
+                    .col_expr(password_reset::Column::Used, Expr::value(chrono::Utc::now()))
+                    .filter(password_reset::Column::Id.eq(reset.id))
+                    .filter(password_reset::Column::Used.is_null())
+                    .exec(txn)
+                    .await?;
+                if used.rows_affected == 0 {
+                    return Err(DbErr::Custom("That link has already been used.".to_owned()));
+                }
+                AuthSession::delete_by_id(reset.user_id).exec(txn).await?;
+                user::ActiveModel {
+                    id: Unchanged(reset.user_id),
+                    password: Set(Some(password_hash)),
+                    ..Default::default()
+                }
+                    .update(txn)
+                    .await
+            })
+        })
+        .await
+    }
+    /// Count a failed login against `target`, locking it once there have
+    /// been `lock_after` failures in a row.
+    pub async fn record_login_failure(db: &DbConn, target: &str, lock_after: i32, lock_for: chrono::Duration) -> Result<login_throttle::Model, DbErr> {
+        let now = chrono::Utc::now();
+        let existing = LoginThrottle::find_by_id(target).one(db).await?;
+        match existing {
+            Some(existing) => {
+                // An expired lockout starts the count over.
+                let failures = match existing.locked_until {
+                    Some(locked_until) if locked_until <= now => 1,
+                    _ => existing.failures + 1,
+                };
+                login_throttle::ActiveModel {
+                    target: Unchanged(existing.target),
+                    failures: Set(failures),
+                    last_failure: Set(now),
+                    locked_until: Set((failures >= lock_after).then(|| now + lock_for)),
+                }
+                    .update(db)
+                    .await
+            }
+            None => {
+                login_throttle::ActiveModel {
+                    target: Set(target.to_owned()),
+                    failures: Set(1),
+                    last_failure: Set(now),
+                    locked_until: Set(None),
+                }
+                    .insert(db)
+                    .await
+            }
+        }
+    }
+    pub async fn clear_login_throttle(db: &DbConn, target: &str) -> Result<DeleteResult, DbErr> {
+        LoginThrottle::delete_by_id(target)
+            .exec(db)
+            .await
+    }
+    /// Turn on TOTP for a user, replacing any recovery codes they had.
+    pub async fn enable_totp(db: &DbConn, user_id: i32, secret: String, step: i64, recovery_code_hashes: Vec<String>) -> Result<user_totp::Model, TransactionError<DbErr>> {
+        db.transaction::<_, user_totp::Model, DbErr>(|txn| {
+            Box::pin(async move {
+                RecoveryCode::delete_many()
+                    .filter(recovery_code::Column::UserId.eq(user_id))
+                    .exec(txn)
+                    .await?;
+                RecoveryCode::insert_many(
+                    recovery_code_hashes.into_iter().map(|code_hash| recovery_code::ActiveModel {
+                        user_id: Set(user_id),
+                        code_hash: Set(code_hash),
+                        used: Set(None),
+                        ..Default::default()
+                    }))
+                    .exec(txn)
+                    .await?;
+                UserTotp::delete_by_id(user_id).exec(txn).await?;
+                user_totp::ActiveModel {
+                    user_id: Set(user_id),
+                    secret: Set(secret),
+                    last_step: Set(step),
+                    created: Set(chrono::Utc::now()),
+                }
+                    .insert(txn)
+                    .await
+            })
+        })
+        .await
+    }
+    /// Turn off TOTP for a user. Admins use this for people who are locked out.
+    pub async fn disable_totp(db: &DbConn, user_id: i32) -> Result<(), TransactionError<DbErr>> {
+        db.transaction::<_, (), DbErr>(|txn| {
+            Box::pin(async move {
+                RecoveryCode::delete_many()
+                    .filter(recovery_code::Column::UserId.eq(user_id))
+                    .exec(txn)
+                    .await?;
+                UserTotp::delete_by_id(user_id).exec(txn).await?;
+                Ok(())
+            })
+        })
+        .await
+    }
+    pub async fn set_totp_last_step(db: &DbConn, user_id: i32, step: i64) -> Result<user_totp::Model, DbErr> {
+        user_totp::ActiveModel {
+            user_id: Unchanged(user_id),
+            last_step: Set(step),
+            ..Default::default()
+        }
+            .update(db)
+            .await
+    }
+    /// Mark a recovery code as used, returning whether it was valid.
+    pub async fn use_recovery_code(db: &DbConn, user_id: i32, code_hash: String) -> Result<bool, DbErr> {
+        let result = RecoveryCode::update_many()
+            .col_expr(recovery_code::Column::Used, Expr::value(chrono::Utc::now()))
+            .filter(recovery_code::Column::UserId.eq(user_id))
+            .filter(recovery_code::Column::CodeHash.eq(code_hash))
+            .filter(recovery_code::Column::Used.is_null())
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+    pub async fn create_webhook(db: &DbConn, form_data: WebhookForm, secret: String) -> Result<crate::entities::webhook::Model, DbErr> {
+        crate::entities::webhook::ActiveModel {
+            url: Set(form_data.url),
+            secret: Set(secret),
+            events: Set(form_data.events.join(",")),
+            active: Set(true),
+            created: Set(chrono::Utc::now()),
+            ..Default::default()
+        }
+            .insert(db)
+            .await
+    }
+    pub async fn set_webhook_active(db: &DbConn, id: i32, active: bool) -> Result<crate::entities::webhook::Model, DbErr> {
+        crate::entities::webhook::ActiveModel {
+            id: Unchanged(id),
+            active: Set(active),
+            ..Default::default()
+        }
+            .update(db)
+            .await
+    }
+    /// Delete a webhook along with its delivery log.
+    pub async fn delete_webhook(db: &DbConn, id: i32) -> Result<DeleteResult, DbErr> {
+        Webhook::delete_by_id(id).exec(db).await
+    }
+    pub async fn record_webhook_attempt(
+        db: &DbConn,
+        id: i32,
+        attempts: i32,
+        status_code: Option<i32>,
+        error: Option<String>,
+        next_attempt: Option<DateTimeUtc>,
+    ) -> Result<webhook_delivery::Model, DbErr> {
+        let delivered = error.is_none().then(chrono::Utc::now);
+        webhook_delivery::ActiveModel {
+            id: Unchanged(id),
+            attempts: Set(attempts),
+            status_code: Set(status_code),
+            error: Set(error),
+            next_attempt: Set(next_attempt),
+            delivered: Set(delivered),
+            ..Default::default()
+        }
+            .update(db)
+            .await
+    }
+    /// Queue a delivery to be sent again as soon as possible.
+    pub async fn redeliver_webhook(db: &DbConn, id: i32) -> Result<webhook_delivery::Model, DbErr> {
+        webhook_delivery::ActiveModel {
+            id: Unchanged(id),
+            next_attempt: Set(Some(chrono::Utc::now())),
+            ..Default::default()
+        }
+            .update(db)
+            .await
+    }
+    /// Save a user's choices, keeping track of what was last sent.
+    pub async fn set_notification_preferences(db: &DbConn, user_id: i32, form_data: NotificationForm) -> Result<notification_preference::Model, DbErr> {
+        let existing = NotificationPreference::find_by_id(user_id).one(db).await?;
+        let mut model = notification_preference::ActiveModel {
+            user_id: Set(user_id),
+            email_changes: Set(form_data.email_changes),
+            digest: Set(form_data.digest),
+            remind: Set(form_data.remind),
+            ..Default::default()
+        };
+        match existing {
+            Some(_) => {
+                model.user_id = Unchanged(user_id);
+                model.update(db).await
+            }
+            None => {
+                model.last_digest = Set(None);
+                model.last_reminder = Set(None);
+                model.insert(db).await
+            }
+        }
+    }
+    pub async fn record_digest_sent(db: &DbConn, user_id: i32, time: DateTimeUtc) -> Result<notification_preference::Model, DbErr> {
+        notification_preference::ActiveModel {
+            user_id: Unchanged(user_id),
+            last_digest: Set(Some(time)),
+            ..Default::default()
+        }
+            .update(db)
+            .await
+    }
+    pub async fn record_reminder_sent(db: &DbConn, user_id: i32, time: DateTimeUtc) -> Result<notification_preference::Model, DbErr> {
+        notification_preference::ActiveModel {
+            user_id: Unchanged(user_id),
+            last_reminder: Set(Some(time)),
+            ..Default::default()
+        }
+            .update(db)
+            .await
+    }
+    /// Save how to read a bank's statements, replacing any mapping with the
+    /// same name.
+    pub async fn save_import_mapping(db: &DbConn, form_data: ImportMappingForm) -> Result<import_mapping::Model, DbErr> {
+        let existing = ImportMapping::find()
+            .filter(import_mapping::Column::Name.eq(&form_data.name))
+            .one(db)
+            .await?;
+        let model = import_mapping::ActiveModel {
+            id: match &existing {
+                Some(existing) => Unchanged(existing.id),
+                None => NotSet,
+            },
+            name: Set(form_data.name),
+            date_column: Set(form_data.date_column),
+            amount_column: Set(form_data.amount_column),
+            description_column: Set(form_data.description_column),
+            date_format: Set(form_data.date_format),
+            negate: Set(form_data.negate),
+            default_split: Set(form_data.default_split.to_string()),
+        };
+        match existing {
+            Some(_) => model.update(db).await,
+            None => model.insert(db).await,
+        }
+    }
+    /// Create an expenditure for each statement row, all or none, skipping
+    /// rows whose fingerprint was already imported.
+    pub async fn import_expenditures(db: &DbConn, actor_id: Option<i32>, rows: Vec<(String, ExpenditureForm)>) -> Result<Vec<expenditure::Model>, TransactionError<DbErr>> {
+        db.transaction::<_, Vec<expenditure::Model>, DbErr>(|txn| {
+            Box::pin(async move {
+                let mut created = Vec::new();
+                for (fingerprint, form_data) in rows {
+                    if Self::was_imported(txn, &fingerprint).await? {
+                        continue;
+                    }
+                    let expenditure = Self::save_expenditure(txn, actor_id, None, form_data)
+                        .await
+                        .map_err(|e| match e {
+                            TransactionError::Connection(e) | TransactionError::Transaction(e) => e,
+                        })?;
+                    Self::mark_imported(txn, fingerprint, Some(expenditure.id)).await?;
+                    created.push(expenditure);
+                }
+                Ok(created)
+            })
+        })
+        .await
+    }
+    async fn was_imported<C: ConnectionTrait>(db: &C, fingerprint: &str) -> Result<bool, DbErr> {
+        Ok(ImportedRow::find()
+            .filter(imported_row::Column::Fingerprint.eq(fingerprint))
+            .one(db)
+            .await?
+            .is_some())
+    }
+    async fn mark_imported<C: ConnectionTrait>(db: &C, fingerprint: String, expenditure_id: Option<i32>) -> Result<(), DbErr> {
+        imported_row::ActiveModel {
+            fingerprint: Set(fingerprint),
+            expenditure_id: Set(expenditure_id),
+            time: Set(chrono::Utc::now()),
+            ..Default::default()
+        }
+            .insert(db)
+            .await?;
+        Ok(())
+    }
+    /// Bring in a Splitwise group's history in one go, so a failure leaves
+    /// nothing half-imported. Returns what was created and everyone's debts
+    /// before and after, for checking against Splitwise's balances.
+    ///
+    /// Rows are fingerprinted like statement rows, so uploading a later
+    /// export only brings in what's new. Nobody is notified and no webhooks
+    /// are sent for each row; the audit log gets one entry for the import.
+    pub async fn import_splitwise(
+        db: &DbConn,
+        actor_id: Option<i32>,
+        expenditures: Vec<(String, ExpenditureForm)>,
+        transfers: Vec<(String, TransferForm)>,
+    ) -> Result<SplitwiseImport, TransactionError<DbErr>> {
+        db.transaction::<_, SplitwiseImport, DbErr>(|txn| {
+            Box::pin(async move {
+                let debts_before = Query::get_debts(txn).await?;
+                let mut created = SplitwiseImport { debts_before, ..Default::default() };
+                for (fingerprint, form_data) in expenditures {
+                    if Self::was_imported(txn, &fingerprint).await? {
+                        created.already_imported += 1;
+                        continue;
+                    }
+                    let expenditure = Self::write_expenditure(txn, None, form_data).await?;
+                    Self::mark_imported(txn, fingerprint, Some(expenditure.id)).await?;
+                    created.expenditures.push(expenditure);
+                }
+                for (fingerprint, form_data) in transfers {
+                    if Self::was_imported(txn, &fingerprint).await? {
+                        created.already_imported += 1;
+                        continue;
+                    }
+                    created.transfers.push(Self::write_transfer(txn, None, form_data).await?);
+                    Self::mark_imported(txn, fingerprint, None).await?;
+                }
+                if !created.expenditures.is_empty() || !created.transfers.is_empty() {
+                    Self::append_audit(txn, actor_id, audit_log::Action::Create, audit_log::RecordType::Import, 0, None, Some(&serde_json::json!({
+                        "source": "splitwise",
+                        "expenditures": created.expenditures.iter().map(|e| e.id).collect::<Vec<_>>(),
+                        "transfers": created.transfers.iter().map(|t| t.id).collect::<Vec<_>>(),
+                    }))).await?;
+                }
+                created.debts_after = Query::get_debts(txn).await?;
+                Ok(created)
+            })
+        })
+        .await
+    }
+    /// Create or update a category. Putting a category under itself, or
+    /// under one of its own subcategories, is an error.
+    pub async fn save_category(db: &DbConn, id: Option<i32>, form_data: CategoryForm) -> Result<category::Model, DbErr> {
+        if let (Some(id), Some(parent_id)) = (id, form_data.parent_id) {
+            // Walk up from the new parent, giving up on existing loops.
+            let parents: HashMap<i32, Option<i32>> = Category::find()
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|c| (c.id, c.parent_id))
+                .collect();
+            let mut next = Some(parent_id);
+            let mut steps = 0;
+            while let Some(ancestor) = next {
+                if ancestor == id || steps > parents.len() {
+                    return Err(DbErr::Custom("A category can't go under itself.".to_owned()));
+                }
+                next = parents.get(&ancestor).copied().flatten();
+                steps += 1;
+            }
+        }
+        let category = category::ActiveModel {
+            id: match id {
+                Some(id) => Unchanged(id),
+                None => NotSet,
+            },
+            name: Set(form_data.name.trim().to_string()),
+            parent_id: Set(form_data.parent_id),
+        };
+        match id {
+            Some(_) => category.update(db).await,
+            None => category.insert(db).await,
+        }
+    }
+    /// Delete a category. What was under it moves up a level, and its
+    /// expenditures are left without a category, which goes in their
+    /// history. Nobody is notified, since no amounts change.
+    pub async fn delete_category(db: &DbConn, actor_id: Option<i32>, id: i32) -> Result<DeleteResult, TransactionError<DbErr>> {
+        db.transaction::<_, DeleteResult, DbErr>(|txn| {
+            Box::pin(async move {
+                let Some(category) = Category::find_by_id(id).one(txn).await? else {
+                    return Ok(DeleteResult { rows_affected: 0 });
+                };
+                Category::update_many()
+                    .col_expr(category::Column::ParentId, Expr::value(category.parent_id))
+                    .filter(category::Column::ParentId.eq(id))
+                    .exec(txn)
+                    .await?;
+                let affected = Expenditure::find()
+                    .filter(expenditure::Column::CategoryId.eq(id))
+                    .all(txn)
+                    .await?;
+                let mut before = Vec::new();
+                for expenditure in &affected {
+                    before.push(Self::expenditure_snapshot(txn, expenditure.id).await?);
+                }
+                Expenditure::update_many()
+                    .col_expr(expenditure::Column::CategoryId, Expr::value(None::<i32>))
+                    .filter(expenditure::Column::CategoryId.eq(id))
+                    .exec(txn)
+                    .await?;
+                for (expenditure, before) in affected.iter().zip(before) {
+                    let after = Self::expenditure_snapshot(txn, expenditure.id).await?;
+                    Self::append_audit(txn, actor_id, audit_log::Action::Update, audit_log::RecordType::Expenditure, expenditure.id, before.as_ref(), after.as_ref()).await?;
+                }
+                Category::delete_by_id(id).exec(txn).await
+            })
+        })
+        .await
+    }
+    /// Mark the user's notifications read, up to and including `up_to_id`
+    /// so that ones that arrived after the page was shown stay unread.
+    pub async fn mark_notifications_read(db: &DbConn, user_id: i32, up_to_id: i32) -> Result<UpdateResult, DbErr> {
+        Notification::update_many()
+            .col_expr(notification::Column::Read, Expr::value(chrono::Utc::now()))
+            .filter(notification::Column::UserId.eq(user_id))
+            .filter(notification::Column::Id.lte(up_to_id))
+            .filter(notification::Column::Read.is_null())
+            .exec(db)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use audit_log::{Action, RecordType};
+    use serde_json::json;
+
+    const ISSUER: &str = "https://mock-issuer.test";
+
+    fn claims(username: &str) -> user::ActiveModel {
+        user::ActiveModel {
+            username: Set(username.to_string()),
+            name: Set(None),
+            email: Set(None),
+            resident: Set(true),
+            ..Default::default()
+        }
+    }
+
+    async fn add_user(db: &DbConn, username: &str) -> user::Model {
+        claims(username).insert(db).await.unwrap()
+    }
+
+    fn expenditure(spender_id: i32, splits: &[(i32, i32)]) -> ExpenditureForm {
+        ExpenditureForm {
+            spender_id,
+            amount: splits.iter().map(|(_, share)| share).sum::<i32>().into(),
+            description: String::new(),
+            date: DateField(chrono::NaiveDate::from_ymd_opt(2026, 10, 19).unwrap()),
+            splits: splits.iter().map(|(id, share)| (*id, (*share).into())).collect(),
+            category_id: None,
+            tags: String::new(),
+        }
+    }
+
+    fn names() -> HashMap<i32, String> {
+        HashMap::from([(1, "Alice".to_string()), (2, "Bob".to_string()), (3, "Carol".to_string())])
+    }
+
+    #[test]
+    fn tags_are_trimmed_and_deduplicated() {
+        let form = ExpenditureForm {
+            spender_id: 1,
+            amount: 100.into(),
+            description: String::new(),
+            date: DateField(chrono::NaiveDate::from_ymd_opt(2026, 10, 19).unwrap()),
+            splits: HashMap::from([(1, 100.into())]),
+            category_id: None,
+            tags: " vacation,car, ,vacation ".to_string(),
+        };
+        assert_eq!(form.tag_list().into_iter().collect::<Vec<_>>(), vec!["car", "vacation"]);
+    }
+
+    #[test]
+    fn expenditure_notifications_skip_the_actor() {
+        let after = json!({"spender_id": 1, "amount": 4000, "description": "Groceries", "splits": {"1": 2000, "2": 2000, "3": 0}});
+        let messages = notification_messages(Some(1), Action::Create, RecordType::Expenditure, None, Some(&after), &names());
+        assert_eq!(messages, vec![(2, "Alice added a $40.00 expenditure that includes you: Groceries".to_string())]);
+    }
+
+    #[test]
+    fn expenditure_update_notes_who_was_added_and_removed() {
+        let before = json!({"spender_id": 1, "amount": 4000, "description": "", "splits": {"1": 2000, "2": 2000}});
+        let after = json!({"spender_id": 1, "amount": 4000, "description": "", "splits": {"1": 2000, "3": 2000}});
+        let messages = notification_messages(None, Action::Update, RecordType::Expenditure, Some(&before), Some(&after), &names());
+        assert_eq!(messages, vec![
+            (1, "Someone edited a $40.00 expenditure that includes you".to_string()),
+            (2, "Someone removed you from a $40.00 expenditure".to_string()),
+            (3, "Someone added you to a $40.00 expenditure".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn transfer_notifications_name_the_other_party() {
+        let before = json!({"debtor_id": 2, "creditor_id": 3, "amount": 1500, "description": "Rent"});
+        let messages = notification_messages(Some(1), Action::Delete, RecordType::Transfer, Some(&before), None, &names());
+        assert_eq!(messages, vec![
+            (2, "Alice deleted a $15.00 transfer from you to Carol: Rent".to_string()),
+            (3, "Alice deleted a $15.00 transfer from Bob to you: Rent".to_string()),
+        ]);
+    }
+
+    #[rocket::async_test]
+    async fn identities_follow_renames() {
+        let db = crate::service::test_db().await;
+        let user = Mutation::ensure_user(&db, ISSUER, "sub-1", claims("alice")).await.unwrap();
+        assert!(!user.is_admin);
+        let renamed = Mutation::ensure_user(&db, ISSUER, "sub-1", claims("alice.smith")).await.unwrap();
+        assert_eq!(renamed.id, user.id);
+        assert_eq!(renamed.username, "alice.smith");
+        assert_eq!(User::find().count(&db).await.unwrap(), 1);
+    }
+
+    #[rocket::async_test]
+    async fn new_identities_never_take_over_an_existing_username() {
+        let db = crate::service::test_db().await;
+        let alice = add_user(&db, "alice").await;
+        let user = Mutation::ensure_user(&db, ISSUER, "sub-1", claims("alice")).await.unwrap();
+        assert_ne!(user.id, alice.id);
+        assert_eq!(user.username, "alice-2");
+        assert_eq!(User::find_by_id(alice.id).one(&db).await.unwrap(), Some(alice.clone()));
+        // Logging in again finds the same new user, not alice.
+        let again = Mutation::ensure_user(&db, ISSUER, "sub-1", claims("alice")).await.unwrap();
+        assert_eq!((again.id, again.username.as_str()), (user.id, "alice-2"));
+        // Alice can connect a different identity once she's logged in herself.
+        Mutation::connect_identity(&db, ISSUER, "sub-2", alice.id).await.unwrap();
+        assert_eq!(Mutation::ensure_user(&db, ISSUER, "sub-2", claims("alice")).await.unwrap().id, alice.id);
+        assert!(Mutation::connect_identity(&db, ISSUER, "sub-1", alice.id).await.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn proxy_users_from_before_identities_keep_their_id() {
+        let db = crate::service::test_db().await;
+        let alice = add_user(&db, "alice").await;
+        let user = Mutation::ensure_user(&db, PROXY_PROVIDER, "uid-1", claims("alice")).await.unwrap();
+        assert_eq!(user.id, alice.id);
+        assert_eq!(Mutation::ensure_user(&db, PROXY_PROVIDER, "uid-1", claims("alice")).await.unwrap().id, alice.id);
+        // Only once: another proxy identity with the same username doesn't.
+        let other = Mutation::ensure_user(&db, PROXY_PROVIDER, "uid-2", claims("alice")).await.unwrap();
+        assert_eq!((other.username.as_str(), other.id != alice.id), ("alice-2", true));
+        // Nor does an OIDC identity.
+        let bob = add_user(&db, "bob").await;
+        assert_ne!(Mutation::ensure_user(&db, ISSUER, "sub-1", claims("bob")).await.unwrap().id, bob.id);
+    }
+
+    #[rocket::async_test]
+    async fn linked_identities_log_in_as_the_new_user() {
+        let db = crate::service::test_db().await;
+        let alice = add_user(&db, "alice").await;
+        Mutation::ensure_user(&db, ISSUER, "sub-1", claims("asmith")).await.unwrap();
+        let identity = ExternalIdentity::find().one(&db).await.unwrap().unwrap();
+        Mutation::link_identity(&db, identity.id, alice.id).await.unwrap();
+        let user = Mutation::ensure_user(&db, ISSUER, "sub-1", claims("asmith")).await.unwrap();
+        assert_eq!(user.id, alice.id);
+    }
+
+    #[rocket::async_test]
+    async fn merging_combines_shares_and_moves_everything_else() {
+        let db = crate::service::test_db().await;
+        let alice = add_user(&db, "alice").await;
+        let bob = add_user(&db, "bob").await;
+        let carol = add_user(&db, "carol").await;
+        let groceries = Mutation::save_expenditure(&db, Some(bob.id), None, expenditure(bob.id, &[(alice.id, 500), (bob.id, 500), (carol.id, 500)])).await.unwrap();
+        let transfer = Mutation::save_transfer(&db, None, None, TransferForm {
+            debtor_id: bob.id,
+            creditor_id: carol.id,
+            amount: 200.into(),
+            description: String::new(),
+            date: DateField(chrono::NaiveDate::from_ymd_opt(2026, 10, 19).unwrap()),
+        }).await.unwrap();
+        let token = Mutation::create_api_token(&db, bob.id, "hash".to_string(), ApiTokenForm {
+            name: "script".to_string(),
+            read_only: true,
+            expires_in_days: None,
+        }).await.unwrap();
+        user_totp::ActiveModel {
+            user_id: Set(bob.id),
+            secret: Set("JBSWY3DPEHPK3PXP".to_string()),
+            last_step: Set(0),
+            created: Set(chrono::Utc::now()),
+        }
+            .insert(&db)
+            .await
+            .unwrap();
+
+        let summary = Mutation::merge_users(&db, Some(carol.id), bob.id, alice.id).await.unwrap();
+        assert_eq!((summary.api_tokens, summary.two_factor_removed), (1, true));
+        assert_eq!(ApiToken::find_by_id(token.id).one(&db).await.unwrap().unwrap().user_id, alice.id);
+        assert_eq!(UserTotp::find().count(&db).await.unwrap(), 0);
+
+        assert!(User::find_by_id(bob.id).one(&db).await.unwrap().is_none());
+        let groceries = Expenditure::find_by_id(groceries.id).one(&db).await.unwrap().unwrap();
+        assert_eq!(groceries.spender_id, alice.id);
+        let shares: HashMap<i32, Currency> = Split::find()
+            .filter(split::Column::ExpenditureId.eq(groceries.id))
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| (s.user_id, s.share))
+            .collect();
+        assert_eq!(shares, HashMap::from([(alice.id, 1000.into()), (carol.id, 500.into())]));
+        let transfer = Transfer::find_by_id(transfer.id).one(&db).await.unwrap().unwrap();
+        assert_eq!(transfer.debtor_id, alice.id);
+        let merge = AuditLog::find()
+            .filter(audit_log::Column::Action.eq(Action::Merge))
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((merge.actor_id, merge.record_id), (Some(carol.id), bob.id));
+        // Bob's entries are left as they were, but show who he is now.
+        assert_eq!(AuditLog::find().filter(audit_log::Column::ActorId.eq(bob.id)).count(&db).await.unwrap(), 1);
+        let log = Query::find_audit_log(&db, Some((RecordType::Expenditure, groceries.id)), None).await.unwrap();
+        assert_eq!(log[0].actor_name.as_deref(), Some("alice"));
+    }
+
+    #[rocket::async_test]
+    async fn splitwise_imports_are_quiet_and_skip_rows_seen_before() {
+        let db = crate::service::test_db().await;
+        let alice = add_user(&db, "alice").await;
+        let bob = add_user(&db, "bob").await;
+        let rows = || vec![("groceries".to_string(), expenditure(alice.id, &[(alice.id, 500), (bob.id, 500)]))];
+        let first = Mutation::import_splitwise(&db, Some(alice.id), rows(), vec![]).await.unwrap();
+        assert_eq!((first.expenditures.len(), first.already_imported), (1, 0));
+        let again = Mutation::import_splitwise(&db, Some(alice.id), rows(), vec![]).await.unwrap();
+        assert_eq!((again.expenditures.len(), again.already_imported), (0, 1));
+        assert_eq!(Expenditure::find().count(&db).await.unwrap(), 1);
+        assert_eq!(Notification::find().count(&db).await.unwrap(), 0);
+        let log = AuditLog::find().all(&db).await.unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].record_type, RecordType::Import);
+    }
+
+    #[rocket::async_test]
+    async fn categories_stay_a_tree_and_deleting_one_is_logged() {
+        let db = crate::service::test_db().await;
+        let alice = add_user(&db, "alice").await;
+        let category = |name: &str, parent_id| CategoryForm { name: name.to_string(), parent_id };
+        let food = Mutation::save_category(&db, None, category("Food", None)).await.unwrap();
+        let groceries = Mutation::save_category(&db, None, category("Groceries", Some(food.id))).await.unwrap();
+        assert!(Mutation::save_category(&db, Some(food.id), category("Food", Some(groceries.id))).await.is_err());
+        assert!(Mutation::save_category(&db, Some(food.id), category("Food", Some(food.id))).await.is_err());
+
+        let mut form = expenditure(alice.id, &[(alice.id, 500)]);
+        form.category_id = Some(groceries.id);
+        let milk = Mutation::save_expenditure(&db, Some(alice.id), None, form).await.unwrap();
+        Mutation::delete_category(&db, Some(alice.id), groceries.id).await.unwrap();
+        assert_eq!(Expenditure::find_by_id(milk.id).one(&db).await.unwrap().unwrap().category_id, None);
+        let log = Query::find_audit_log(&db, Some((RecordType::Expenditure, milk.id)), None).await.unwrap();
+        assert_eq!(log.len(), 2);
+        assert!(log.iter().any(|e| e.entry.action == Action::Update
+            && e.changes.iter().any(|c| c.field == "category" && c.before.as_deref() == Some("Groceries") && c.after.is_none())));
+    }
+
+    #[rocket::async_test]
+    async fn reset_links_work_once() {
+        let db = crate::service::test_db().await;
+        let alice = add_user(&db, "alice").await;
+        let reset = Mutation::create_password_reset(&db, alice.id, "hash".to_string(), chrono::Duration::hours(1)).await.unwrap();
+        Mutation::reset_password(&db, reset.clone(), "first".to_string()).await.unwrap();
+        // A second request that looked the token up before the first used it.
+        let Err(TransactionError::Transaction(DbErr::Custom(_))) = Mutation::reset_password(&db, reset, "second".to_string()).await else {
+            panic!("the link worked twice");
+        };
+        let alice = User::find_by_id(alice.id).one(&db).await.unwrap().unwrap();
+        assert_eq!(alice.password.as_deref(), Some("first"));
+    }
 }
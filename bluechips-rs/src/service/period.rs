@@ -0,0 +1,170 @@
+//! The periods totals are kept over. They're set in the Rocket config,
+//! like:
+//!
+//! ```toml
+//! cycle_start_day = 15
+//! fiscal_year_start_month = 9
+//! custom_periods = [{ name = "Lease", from = "2026-09-01", to = "2027-08-31" }]
+//! ```
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+
+use chrono::{Datelike, Months, NaiveDate};
+use rocket::serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// How the household divides up time for its totals.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Periods {
+    /// The day of the month billing cycles start on. In months too short
+    /// for it, the cycle starts on the last day instead.
+    pub cycle_start_day: u32,
+    /// The month, 1 to 12, the fiscal year starts in.
+    pub fiscal_year_start_month: u32,
+    /// Named ranges to total as well, like a lease or a trip.
+    pub custom_periods: Vec<CustomPeriod>,
+}
+
+impl Default for Periods {
+    fn default() -> Self {
+        Self {
+            cycle_start_day: 1,
+            fiscal_year_start_month: 1,
+            custom_periods: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CustomPeriod {
+    pub name: String,
+    pub from: NaiveDate,
+    /// The last day of the period, or none if it's still going.
+    #[serde(default)]
+    pub to: Option<NaiveDate>,
+}
+
+impl Periods {
+    /// Refuse settings that are out of range, so a typo stops the server
+    /// from starting rather than quietly meaning something else.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=31).contains(&self.cycle_start_day) {
+            return Err(format!("cycle_start_day must be from 1 to 31, not {}", self.cycle_start_day));
+        }
+        if !(1..=12).contains(&self.fiscal_year_start_month) {
+            return Err(format!("fiscal_year_start_month must be from 1 to 12, not {}", self.fiscal_year_start_month));
+        }
+        Ok(())
+    }
+
+    fn cycle_start_in(&self, year: i32, month: u32) -> NaiveDate {
+        let first = NaiveDate::from_ymd_opt(year, month, 1).expect("valid month");
+        let last = (first + Months::new(1)).pred_opt().expect("valid date");
+        first.with_day(self.cycle_start_day.min(last.day())).expect("valid day")
+    }
+
+    /// The billing cycle `day` falls in.
+    pub fn cycle(&self, day: NaiveDate) -> Span {
+        let mut start = self.cycle_start_in(day.year(), day.month());
+        if day < start {
+            let month = start.with_day(1).expect("valid day") - Months::new(1);
+            start = self.cycle_start_in(month.year(), month.month());
+        }
+        let month = start.with_day(1).expect("valid day") + Months::new(1);
+        let next = self.cycle_start_in(month.year(), month.month());
+        Span::new(Some(start), next.pred_opt())
+    }
+
+    /// The fiscal year `day` falls in.
+    pub fn fiscal_year(&self, day: NaiveDate) -> Span {
+        let month = self.fiscal_year_start_month;
+        let year = if day.month() >= month { day.year() } else { day.year() - 1 };
+        let start = NaiveDate::from_ymd_opt(year, month, 1).expect("valid month");
+        Span::new(Some(start), (start + Months::new(12)).pred_opt())
+    }
+}
+
+/// The days a total covers, both ends included. A missing end is open.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Span {
+    #[schema(value_type = Option<String>, format = Date)]
+    pub from: Option<NaiveDate>,
+    #[schema(value_type = Option<String>, format = Date)]
+    pub to: Option<NaiveDate>,
+}
+
+impl Span {
+    pub fn new(from: Option<NaiveDate>, to: Option<NaiveDate>) -> Self {
+        Self { from, to }
+    }
+
+    /// From the start of this span on, with no end.
+    pub fn onwards(&self) -> Self {
+        Self { from: self.from, to: None }
+    }
+}
+
+impl RangeBounds<NaiveDate> for Span {
+    fn start_bound(&self) -> Bound<&NaiveDate> {
+        self.from.as_ref().map_or(Bound::Unbounded, Bound::Included)
+    }
+
+    fn end_bound(&self) -> Bound<&NaiveDate> {
+        self.to.as_ref().map_or(Bound::Unbounded, Bound::Included)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const FORMAT: &str = "%b %-d, %Y";
+        match (self.from, self.to) {
+            (Some(from), Some(to)) => write!(f, "{} to {}", from.format(FORMAT), to.format(FORMAT)),
+            (Some(from), None) => write!(f, "since {}", from.format(FORMAT)),
+            (None, Some(to)) => write!(f, "until {}", to.format(FORMAT)),
+            (None, None) => write!(f, "all time"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn cycles_start_on_the_configured_day() {
+        let periods = Periods { cycle_start_day: 15, ..Default::default() };
+        assert_eq!(periods.cycle(date(2026, 10, 19)), Span::new(Some(date(2026, 10, 15)), Some(date(2026, 11, 14))));
+        assert_eq!(periods.cycle(date(2026, 10, 14)), Span::new(Some(date(2026, 9, 15)), Some(date(2026, 10, 14))));
+        assert_eq!(periods.cycle(date(2026, 1, 2)), Span::new(Some(date(2025, 12, 15)), Some(date(2026, 1, 14))));
+        assert_eq!(Periods::default().cycle(date(2026, 2, 10)), Span::new(Some(date(2026, 2, 1)), Some(date(2026, 2, 28))));
+    }
+
+    #[test]
+    fn short_months_start_cycles_on_their_last_day() {
+        let periods = Periods { cycle_start_day: 31, ..Default::default() };
+        assert_eq!(periods.cycle(date(2026, 2, 28)), Span::new(Some(date(2026, 2, 28)), Some(date(2026, 3, 30))));
+        assert_eq!(periods.cycle(date(2026, 2, 27)), Span::new(Some(date(2026, 1, 31)), Some(date(2026, 2, 27))));
+    }
+
+    #[test]
+    fn fiscal_years_start_on_the_configured_month() {
+        let periods = Periods { fiscal_year_start_month: 9, ..Default::default() };
+        assert_eq!(periods.fiscal_year(date(2026, 10, 19)), Span::new(Some(date(2026, 9, 1)), Some(date(2027, 8, 31))));
+        assert_eq!(periods.fiscal_year(date(2026, 8, 31)), Span::new(Some(date(2025, 9, 1)), Some(date(2026, 8, 31))));
+        assert_eq!(Periods::default().fiscal_year(date(2026, 10, 19)), Span::new(Some(date(2026, 1, 1)), Some(date(2026, 12, 31))));
+    }
+
+    #[test]
+    fn out_of_range_settings_are_refused() {
+        assert!(Periods::default().validate().is_ok());
+        assert!(Periods { cycle_start_day: 31, fiscal_year_start_month: 12, ..Default::default() }.validate().is_ok());
+        assert!(Periods { cycle_start_day: 0, ..Default::default() }.validate().is_err());
+        assert!(Periods { cycle_start_day: 40, ..Default::default() }.validate().is_err());
+        assert!(Periods { fiscal_year_start_month: 13, ..Default::default() }.validate().is_err());
+    }
+}
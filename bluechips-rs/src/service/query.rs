@@ -1,34 +1,401 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::{RangeBounds, Bound};
 
 use crate::entities::{prelude::*, *};
 use sea_orm::{prelude::*, *};
-use sea_orm::sea_query::{Cond, SimpleExpr, IntoCondition, ConditionType, TableRef, IntoIden, SelectStatement, Alias};
-use chrono::{Local, NaiveDate, Datelike, Duration, Months};
+use sea_orm::sea_query::{Cond, SimpleExpr, IntoCondition, ConditionType, TableRef, IntoIden, SelectStatement, Alias, Func, LikeExpr, BinOper};
+use chrono::{Local, NaiveDate, Duration};
+use rocket::futures::Stream;
+use rocket::http::uri::fmt::{Formatter as UriFormatter, Query as UriQuery, UriDisplay};
+use rocket::serde::Serialize;
+use utoipa::ToSchema;
 
-#[derive(FromQueryResult)]
+use super::period::{Periods, Span};
+
+#[derive(FromQueryResult, Serialize, ToSchema)]
 pub struct ExpenditureDisplay {
     pub id: i32,
     pub amount: Currency,
     pub mine: bool,
     pub involved: bool,
     pub description: Option<String>,
+    #[schema(value_type = Option<String>, format = Date)]
     pub date: Option<Date>,
     pub spender_name: Option<String>,
     pub share_amount: Currency,
 }
 
-#[derive(FromQueryResult)]
+#[derive(FromQueryResult, Serialize, ToSchema)]
 pub struct TransferDisplay {
     pub id: i32,
     pub amount: Currency,
     pub involved: bool,
     pub description: Option<String>,
+    #[schema(value_type = Option<String>, format = Date)]
     pub date: Option<Date>,
     pub debtor_name: Option<String>,
     pub creditor_name: Option<String>,
 }
 
+/// One split of an expenditure, for exports and reports.
+#[derive(FromQueryResult)]
+pub struct SplitRow {
+    pub expenditure_id: i32,
+    pub date: Option<Date>,
+    pub description: Option<String>,
+    pub spender_id: i32,
+    pub amount: Currency,
+    pub user_id: i32,
+    pub share: Currency,
+    pub category_id: Option<i32>,
+}
+
+/// Narrows down history and exports. Dates and amounts are inclusive.
+#[derive(Clone, Debug, Default)]
+pub struct HistoryFilter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// Only records this user is part of.
+    pub user_id: Option<i32>,
+    /// Only expenditures this user paid for.
+    pub spender_id: Option<i32>,
+    /// Only transfers from this user.
+    pub debtor_id: Option<i32>,
+    /// Only transfers to this user.
+    pub creditor_id: Option<i32>,
+    pub min_amount: Option<Currency>,
+    pub max_amount: Option<Currency>,
+    /// Only records whose description contains this, ignoring case.
+    pub search: Option<String>,
+    /// Only expenditures in this category or one under it.
+    pub category_id: Option<i32>,
+    /// Only expenditures with this tag.
+    pub tag: Option<String>,
+}
+
+/// The IDs of a category and every one under it, for `IN`.
+const CATEGORY_AND_BELOW: &str = "(WITH RECURSIVE below(id) AS (\
+    SELECT CAST(? AS INTEGER) \
+    UNION SELECT categories.id FROM categories JOIN below ON categories.parent_id = below.id\
+) SELECT id FROM below)";
+
+impl HistoryFilter {
+    fn dates(&self, column: impl ColumnTrait) -> Cond {
+        let mut cond = Cond::all();
+        if let Some(from) = self.from {
+            cond = cond.add(column.gte(from));
+        }
+        if let Some(to) = self.to {
+            cond = cond.add(column.lte(to));
+        }
+        cond
+    }
+
+    /// What expenditures and transfers have in common: dates, amounts and
+    /// descriptions.
+    fn common(&self, date: impl ColumnTrait, amount: impl ColumnTrait, description: impl ColumnTrait) -> Cond {
+        let mut cond = self.dates(date);
+        if let Some(min) = &self.min_amount {
+            cond = cond.add(amount.gte(min.clone()));
+        }
+        if let Some(max) = &self.max_amount {
+            cond = cond.add(amount.lte(max.clone()));
+        }
+        if let Some(search) = &self.search {
+            let escaped: String = search
+                .to_lowercase()
+                .chars()
+                .flat_map(|c| match c {
+                    '%' | '_' | '\\' => vec!['\\', c],
+                    c => vec![c],
+                })
+                .collect();
+            cond = cond.add(
+                Expr::expr(Func::lower(Expr::col((description.entity_name(), description))))
+                    .like(LikeExpr::new(format!("%{}%", escaped)).escape('\\'))
+            );
+        }
+        cond
+    }
+
+    fn expenditures(&self) -> Cond {
+        let mut cond = self.common(expenditure::Column::Date, expenditure::Column::Amount, expenditure::Column::Description);
+        if let Some(spender_id) = self.spender_id {
+            cond = cond.add(expenditure::Column::SpenderId.eq(spender_id));
+        }
+        if let Some(category_id) = self.category_id {
+            cond = cond.add(
+                Expr::col((expenditure::Entity, expenditure::Column::CategoryId))
+                    .binary(BinOper::In, Expr::cust_with_values(CATEGORY_AND_BELOW, [category_id]))
+            );
+        }
+        if let Some(tag) = &self.tag {
+            let tagged = ExpenditureTag::find()
+                .select_only()
+                .column(expenditure_tag::Column::ExpenditureId)
+                .filter(expenditure_tag::Column::Tag.eq(tag.as_str()))
+                .into_query();
+            cond = cond.add(expenditure::Column::Id.in_subquery(tagged));
+        }
+        if let Some(user_id) = self.user_id {
+            let sharing = Split::find()
+                .select_only()
+                .column(split::Column::ExpenditureId)
+                .filter(split::Column::UserId.eq(user_id))
+                .filter(split::Column::Share.ne(0))
+                .into_query();
+            cond = cond.add(
+                Cond::any()
+                    .add(expenditure::Column::SpenderId.eq(user_id))
+                    .add(expenditure::Column::Id.in_subquery(sharing))
+            );
+        }
+        cond
+    }
+
+    fn transfers(&self) -> Cond {
+        let mut cond = self.common(transfer::Column::Date, transfer::Column::Amount, transfer::Column::Description);
+        if let Some(debtor_id) = self.debtor_id {
+            cond = cond.add(transfer::Column::DebtorId.eq(debtor_id));
+        }
+        if let Some(creditor_id) = self.creditor_id {
+            cond = cond.add(transfer::Column::CreditorId.eq(creditor_id));
+        }
+        if let Some(user_id) = self.user_id {
+            cond = cond.add(
+                Cond::any()
+                    .add(transfer::Column::DebtorId.eq(user_id))
+                    .add(transfer::Column::CreditorId.eq(user_id))
+            );
+        }
+        cond
+    }
+}
+
+/// Where a page of history picks up: just past the record with this date
+/// and ID, going back in time. Records without a date come last.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HistoryCursor {
+    pub date: NaiveDate,
+    pub id: i32,
+}
+
+impl HistoryCursor {
+    fn undated() -> NaiveDate {
+        NaiveDate::from_ymd_opt(1, 1, 1).expect("valid date")
+    }
+
+    pub fn new(date: Option<NaiveDate>, id: i32) -> Self {
+        Self { date: date.unwrap_or_else(Self::undated), id }
+    }
+
+    /// The sort key for a table's rows: their date, with undated rows
+    /// sorting before everything else.
+    fn key(date: impl ColumnTrait) -> SimpleExpr {
+        Func::coalesce([Expr::col((date.entity_name(), date)).into(), Expr::val(Self::undated()).into()]).into()
+    }
+
+    /// Rows after this one, newest first.
+    fn after(&self, date: impl ColumnTrait, id: impl ColumnTrait) -> Cond {
+        Cond::any()
+            .add(Expr::expr(Self::key(date)).lt(self.date))
+            .add(Expr::expr(Self::key(date)).eq(self.date).and(id.lt(self.id)))
+    }
+}
+
+/// Written like `2026-10-19_123` in query strings.
+impl std::fmt::Display for HistoryCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.date, self.id)
+    }
+}
+
+impl std::str::FromStr for HistoryCursor {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid cursor {:?}", s);
+        let (date, id) = s.split_once('_').ok_or_else(invalid)?;
+        Ok(Self {
+            date: date.parse().map_err(|_| invalid())?,
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'v> rocket::form::FromFormField<'v> for HistoryCursor {
+    fn from_value(field: rocket::form::ValueField<'v>) -> rocket::form::Result<'v, Self> {
+        field.value.parse().map_err(|e: String| rocket::form::Error::validation(e).into())
+    }
+}
+
+impl UriDisplay<UriQuery> for HistoryCursor {
+    fn fmt(&self, f: &mut UriFormatter<'_, UriQuery>) -> std::fmt::Result {
+        f.write_value(self.to_string())
+    }
+}
+
+rocket::http::impl_from_uri_param_identity!([UriQuery] HistoryCursor);
+
+/// A category as pickers and breakdowns show it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct CategoryPath {
+    pub id: i32,
+    /// Like `Food > Groceries`.
+    pub path: String,
+    /// How many categories it's under.
+    pub depth: usize,
+}
+
+/// Every category with its path, each followed by the ones under it, in
+/// order of name. A category whose parent is missing is treated as top
+/// level; any caught in a loop are left out.
+pub fn category_paths(categories: &[category::Model]) -> Vec<CategoryPath> {
+    let ids: HashSet<i32> = categories.iter().map(|c| c.id).collect();
+    let mut children: HashMap<Option<i32>, Vec<&category::Model>> = HashMap::new();
+    for c in categories {
+        children.entry(c.parent_id.filter(|p| ids.contains(p))).or_default().push(c);
+    }
+    for siblings in children.values_mut() {
+        siblings.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()).then(a.id.cmp(&b.id)));
+    }
+    fn visit(parent: Option<i32>, prefix: &str, depth: usize, children: &HashMap<Option<i32>, Vec<&category::Model>>, paths: &mut Vec<CategoryPath>) {
+        for c in children.get(&parent).into_iter().flatten() {
+            let path = match depth {
+                0 => c.name.clone(),
+                _ => format!("{} > {}", prefix, c.name),
+            };
+            paths.push(CategoryPath { id: c.id, path: path.clone(), depth });
+            visit(Some(c.id), &path, depth + 1, children, paths);
+        }
+    }
+    let mut paths = Vec::with_capacity(categories.len());
+    visit(None, "", 0, &children, &mut paths);
+    paths
+}
+
+/// Spending in a category and everything under it.
+#[derive(Serialize, ToSchema)]
+pub struct CategoryTotal {
+    /// Not set for spending without a category.
+    pub category_id: Option<i32>,
+    pub path: String,
+    pub depth: usize,
+    /// The household's spending and the user's share of it.
+    pub total: (Currency, Currency),
+}
+
+/// Add each category's spending to the ones it's under, and list them in
+/// the order of `category_paths`, leaving out those with nothing spent.
+/// Spending without a category comes last.
+fn roll_up_categories(categories: &[category::Model], totals: Vec<(Option<i32>, Currency, Currency)>) -> Vec<CategoryTotal> {
+    let parents: HashMap<i32, Option<i32>> = categories.iter().map(|c| (c.id, c.parent_id)).collect();
+    let mut rolled: HashMap<Option<i32>, (Currency, Currency)> = HashMap::new();
+    for (category_id, total, mine) in totals {
+        let mut seen = HashSet::new();
+        let mut next = category_id;
+        loop {
+            let entry = rolled.entry(next).or_insert((0.into(), 0.into()));
+            entry.0 = entry.0.clone() + total.clone();
+            entry.1 = entry.1.clone() + mine.clone();
+            match next.and_then(|id| parents.get(&id).copied().flatten()) {
+                Some(parent) if seen.insert(parent) => next = Some(parent),
+                _ => break,
+            }
+        }
+    }
+    let spent = |total: &(Currency, Currency)| !total.0.is_zero() || !total.1.is_zero();
+    let mut breakdown: Vec<CategoryTotal> = category_paths(categories)
+        .into_iter()
+        .filter_map(|c| {
+            let total = rolled.remove(&Some(c.id)).filter(spent)?;
+            Some(CategoryTotal { category_id: Some(c.id), path: c.path, depth: c.depth, total })
+        })
+        .collect();
+    if let Some(total) = rolled.remove(&None).filter(spent) {
+        breakdown.push(CategoryTotal { category_id: None, path: "Uncategorized".to_string(), depth: 0, total });
+    }
+    breakdown
+}
+
+/// Where `user_id` ended up after any number of merges.
+fn follow_merges(merged: &HashMap<i32, i32>, mut user_id: i32) -> i32 {
+    // Bounded in case the entries somehow form a loop.
+    for _ in 0..merged.len() {
+        match merged.get(&user_id) {
+            Some(into) => user_id = *into,
+            None => break,
+        }
+    }
+    user_id
+}
+
+/// An audit log entry with its before and after JSON compared field by field.
+pub struct AuditDisplay {
+    pub entry: audit_log::Model,
+    pub actor_name: Option<String>,
+    pub changes: Vec<AuditChange>,
+}
+
+pub struct AuditChange {
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Flatten the snapshots written by `Mutation` into the fields that differ,
+/// formatted for display.
+fn audit_changes(before: Option<&str>, after: Option<&str>, users: &HashMap<i32, String>) -> Vec<AuditChange> {
+    fn flatten(json: Option<&str>, users: &HashMap<i32, String>) -> Vec<(String, Option<String>)> {
+        let user_name = |id: i64| users.get(&(id as i32)).cloned().unwrap_or_else(|| format!("user {}", id));
+        let object = match json.and_then(|j| serde_json::from_str(j).ok()) {
+            Some(serde_json::Value::Object(object)) => object,
+            _ => return vec![],
+        };
+        let mut fields = vec![];
+        for (key, value) in object {
+            match (key.as_str(), value) {
+                ("splits", serde_json::Value::Object(splits)) => {
+                    for (user_id, share) in splits {
+                        let name = user_id.parse().map(user_name).unwrap_or(user_id);
+                        fields.push((format!("split: {}", name), share.as_i64().map(|s| Currency::from(s as i32).to_string())));
+                    }
+                }
+                (_, serde_json::Value::Null) => fields.push((key, None)),
+                ("amount", value) => fields.push((key, value.as_i64().map(|a| Currency::from(a as i32).to_string()))),
+                (_, serde_json::Value::Number(id)) if key.ends_with("_id") || key == "merged_into" => {
+                    let field = key.trim_end_matches("_id").replace('_', " ");
+                    fields.push((field, id.as_i64().map(user_name)));
+                }
+                (_, serde_json::Value::String(value)) => fields.push((key, Some(value))),
+                (_, serde_json::Value::Bool(value)) => fields.push((key, Some(if value { "yes" } else { "no" }.to_string()))),
+                (_, value) => fields.push((key, Some(value.to_string()))),
+            }
+        }
+        fields
+    }
+    let before: HashMap<_, _> = flatten(before, users).into_iter().collect();
+    let after = flatten(after, users);
+    let mut changes: Vec<AuditChange> = after
+        .iter()
+        .filter(|(field, value)| before.get(field) != Some(value))
+        .map(|(field, value)| AuditChange {
+            field: field.clone(),
+            before: before.get(field).cloned().flatten(),
+            after: value.clone(),
+        })
+        .collect();
+    let after: HashMap<_, _> = after.into_iter().collect();
+    let mut removed: Vec<AuditChange> = before
+        .into_iter()
+        .filter(|(field, _)| !after.contains_key(field))
+        .map(|(field, value)| AuditChange { field, before: value, after: None })
+        .collect();
+    removed.sort_by(|a, b| a.field.cmp(&b.field));
+    changes.extend(removed);
+    changes
+}
+
 #[derive(Debug)]
 pub enum SettleError {
     CollectiveDebt(Vec<(i32, Currency)>),
@@ -78,11 +445,34 @@ impl Query {
             .await
     }
 
-    pub async fn find_all_expenditures(db: &DbConn, user_id: i32) -> Result<Vec<ExpenditureDisplay>, DbErr> {
-        Self::annotate_expenditures(
+    /// Expenditures the user is part of that were entered after `since`.
+    pub async fn find_my_expenditures_entered_since(db: &DbConn, user_id: i32, since: DateTimeUtc) -> Result<Vec<ExpenditureDisplay>, DbErr> {
+        Ok(Self::annotate_expenditures(
             user_id,
             Expenditure::find()
-                .order_by_desc(expenditure::Column::Date)
+                .filter(expenditure::Column::EnteredTime.gt(since.naive_utc()))
+                .order_by_asc(expenditure::Column::Date)
+        )
+            .all(db)
+            .await?
+            .into_iter()
+            .filter(|e| e.involved)
+            .collect())
+    }
+
+    /// Matching expenditures, newest first, picking up after `after`. Up
+    /// to `limit` of them if it's given.
+    pub async fn find_expenditures(db: &DbConn, user_id: i32, filter: &HistoryFilter, after: Option<HistoryCursor>, limit: Option<u64>) -> Result<Vec<ExpenditureDisplay>, DbErr> {
+        let mut select = Expenditure::find().filter(filter.expenditures());
+        if let Some(after) = after {
+            select = select.filter(after.after(expenditure::Column::Date, expenditure::Column::Id));
+        }
+        Self::annotate_expenditures(
+            user_id,
+            select
+                .order_by_desc(HistoryCursor::key(expenditure::Column::Date))
+                .order_by_desc(expenditure::Column::Id)
+                .limit(limit)
         )
             .all(db)
             .await
@@ -144,11 +534,19 @@ impl Query {
             .await
     }
 
-    pub async fn find_all_transfers(db: &DbConn, user_id: i32) -> Result<Vec<TransferDisplay>, DbErr> {
+    /// Matching transfers, newest first, picking up after `after`. Up to
+    /// `limit` of them if it's given.
+    pub async fn find_transfers(db: &DbConn, user_id: i32, filter: &HistoryFilter, after: Option<HistoryCursor>, limit: Option<u64>) -> Result<Vec<TransferDisplay>, DbErr> {
+        let mut select = Transfer::find().filter(filter.transfers());
+        if let Some(after) = after {
+            select = select.filter(after.after(transfer::Column::Date, transfer::Column::Id));
+        }
         Self::annotate_transfers(
             user_id,
-            Transfer::find()
-                .order_by_desc(transfer::Column::Date)
+            select
+                .order_by_desc(HistoryCursor::key(transfer::Column::Date))
+                .order_by_desc(transfer::Column::Id)
+                .limit(limit)
         )
             .all(db)
             .await
@@ -163,6 +561,75 @@ impl Query {
             .await
     }
 
+    /// Every split of every matching expenditure, oldest first. With a
+    /// user, that's expenditures they paid for or have a share in.
+    pub async fn stream_expenditure_splits<'a>(db: &'a DbConn, filter: &HistoryFilter) -> Result<impl Stream<Item = Result<SplitRow, DbErr>> + Send + 'a, DbErr> {
+        Split::find()
+            .select_only()
+            .columns([split::Column::ExpenditureId, split::Column::UserId, split::Column::Share])
+            .join(JoinType::InnerJoin, split::Relation::Expenditure.def())
+            .columns([
+                expenditure::Column::Date,
+                expenditure::Column::Description,
+                expenditure::Column::SpenderId,
+                expenditure::Column::Amount,
+                expenditure::Column::CategoryId,
+            ])
+            .filter(filter.expenditures())
+            .order_by_asc(Expr::col((expenditure::Entity, expenditure::Column::Date)).is_null())
+            .order_by_asc(expenditure::Column::Date)
+            .order_by_asc(split::Column::ExpenditureId)
+            .order_by_asc(split::Column::UserId)
+            .into_model::<SplitRow>()
+            .stream(db)
+            .await
+    }
+
+    /// Matching transfers, oldest first. With a user, only the ones they
+    /// sent or received.
+    pub async fn stream_transfers<'a>(db: &'a DbConn, filter: &HistoryFilter) -> Result<impl Stream<Item = Result<transfer::Model, DbErr>> + Send + 'a, DbErr> {
+        Transfer::find()
+            .filter(filter.transfers())
+            .order_by_asc(Expr::col((transfer::Entity, transfer::Column::Date)).is_null())
+            .order_by_asc(transfer::Column::Date)
+            .order_by_asc(transfer::Column::Id)
+            .stream(db)
+            .await
+    }
+
+    /// Expenditures the user paid for or has a share in, oldest first, up to
+    /// `to`. `share_amount` is their share and `mine` whether they paid.
+    pub async fn stream_my_expenditures(db: &DbConn, user_id: i32, to: Option<NaiveDate>) -> Result<impl Stream<Item = Result<ExpenditureDisplay, DbErr>> + Send + '_, DbErr> {
+        let filter = HistoryFilter { to, ..Default::default() };
+        Self::annotate_expenditures(
+            user_id,
+            Expenditure::find()
+                .filter(
+                    Cond::any()
+                        .add(expenditure::Column::SpenderId.eq(user_id))
+                        .add(split::Column::Id.is_not_null())
+                )
+                .filter(filter.dates(expenditure::Column::Date))
+                .order_by_asc(Expr::col((expenditure::Entity, expenditure::Column::Date)).is_null())
+                .order_by_asc(expenditure::Column::Date)
+                .order_by_asc(expenditure::Column::Id)
+        )
+            .stream(db)
+            .await
+    }
+
+    pub async fn get_expenditure_with_splits(db: &DbConn, id: i32) -> Result<Option<(expenditure::Model, Vec<split::Model>)>, DbErr> {
+        Ok(Expenditure::find_by_id(id)
+            .find_with_related(Split)
+            .all(db)
+            .await?
+            .pop())
+    }
+
+    pub async fn get_transfer(db: &DbConn, id: i32) -> Result<Option<transfer::Model>, DbErr> {
+        Transfer::find_by_id(id).one(db).await
+    }
+
     pub async fn find_users(db: &DbConn) -> Result<Vec<user::Model>, DbErr> {
         User::find()
             .order_by_desc(user::Column::Resident)
@@ -184,7 +651,263 @@ impl Query {
             .await
     }
 
-    pub async fn get_debts(db: &DbConn) -> Result<HashMap<i32, Currency>, DbErr> {
+    pub async fn find_identities(db: &DbConn) -> Result<Vec<(external_identity::Model, Option<user::Model>)>, DbErr> {
+        ExternalIdentity::find()
+            .find_also_related(User)
+            .order_by_asc(external_identity::Column::Provider)
+            .order_by_asc(external_identity::Column::UserId)
+            .all(db)
+            .await
+    }
+
+    pub async fn find_api_tokens(db: &DbConn, user_id: i32) -> Result<Vec<api_token::Model>, DbErr> {
+        ApiToken::find()
+            .filter(api_token::Column::UserId.eq(user_id))
+            .order_by_asc(api_token::Column::Created)
+            .all(db)
+            .await
+    }
+
+    pub async fn find_api_token_by_hash(db: &DbConn, token_hash: &str) -> Result<Option<api_token::Model>, DbErr> {
+        ApiToken::find()
+            .filter(api_token::Column::TokenHash.eq(token_hash))
+            .one(db)
+            .await
+    }
+
+    /// Find a reset token that hasn't been used or expired yet.
+    pub async fn find_password_reset_by_hash(db: &DbConn, token_hash: &str) -> Result<Option<password_reset::Model>, DbErr> {
+        PasswordReset::find()
+            .filter(password_reset::Column::TokenHash.eq(token_hash))
+            .filter(password_reset::Column::Used.is_null())
+            .filter(password_reset::Column::Expires.gt(chrono::Utc::now()))
+            .one(db)
+            .await
+    }
+
+    pub async fn get_login_throttle(db: &DbConn, target: &str) -> Result<Option<login_throttle::Model>, DbErr> {
+        LoginThrottle::find_by_id(target)
+            .one(db)
+            .await
+    }
+
+    pub async fn find_login_throttles(db: &DbConn) -> Result<Vec<login_throttle::Model>, DbErr> {
+        LoginThrottle::find()
+            .order_by_desc(login_throttle::Column::LastFailure)
+            .all(db)
+            .await
+    }
+
+    pub async fn get_user_totp(db: &DbConn, user_id: i32) -> Result<Option<user_totp::Model>, DbErr> {
+        UserTotp::find_by_id(user_id)
+            .one(db)
+            .await
+    }
+
+    pub async fn find_totp_user_ids(db: &DbConn) -> Result<Vec<i32>, DbErr> {
+        UserTotp::find()
+            .select_only()
+            .column(user_totp::Column::UserId)
+            .into_tuple()
+            .all(db)
+            .await
+    }
+
+    pub async fn get_notification_preference(db: &DbConn, user_id: i32) -> Result<notification_preference::Model, DbErr> {
+        Ok(NotificationPreference::find_by_id(user_id)
+            .one(db)
+            .await?
+            .unwrap_or(notification_preference::Model {
+                user_id,
+                email_changes: false,
+                digest: notification_preference::DigestFrequency::Never,
+                last_digest: None,
+                remind: false,
+                last_reminder: None,
+            }))
+    }
+
+    /// Those of `user_ids` who have an email address and want to hear about
+    /// changes to records they're part of.
+    pub async fn find_change_recipients(db: &DbConn, user_ids: impl IntoIterator<Item = i32>) -> Result<Vec<user::Model>, DbErr> {
+        User::find()
+            .join(JoinType::InnerJoin, notification_preference::Relation::User.def().rev())
+            .filter(user::Column::Id.is_in(user_ids))
+            .filter(user::Column::Email.is_not_null())
+            .filter(notification_preference::Column::EmailChanges.eq(true))
+            .all(db)
+            .await
+    }
+
+    /// Everyone with an email address who wants a digest or reminders.
+    pub async fn find_digest_recipients(db: &DbConn) -> Result<Vec<(notification_preference::Model, user::Model)>, DbErr> {
+        Ok(NotificationPreference::find()
+            .find_also_related(User)
+            .filter(
+                Cond::any()
+                    .add(notification_preference::Column::Digest.ne(notification_preference::DigestFrequency::Never))
+                    .add(notification_preference::Column::Remind.eq(true))
+            )
+            .filter(user::Column::Email.is_not_null())
+            .all(db)
+            .await?
+            .into_iter()
+            .filter_map(|(preference, user)| Some((preference, user?)))
+            .collect())
+    }
+
+    /// The user's most recent notifications, newest first.
+    pub async fn find_notifications(db: &DbConn, user_id: i32, limit: u64) -> Result<Vec<notification::Model>, DbErr> {
+        Notification::find()
+            .filter(notification::Column::UserId.eq(user_id))
+            .order_by_desc(notification::Column::Id)
+            .limit(limit)
+            .all(db)
+            .await
+    }
+
+    pub async fn count_unread_notifications(db: &DbConn, user_id: i32) -> Result<u64, DbErr> {
+        Notification::find()
+            .filter(notification::Column::UserId.eq(user_id))
+            .filter(notification::Column::Read.is_null())
+            .count(db)
+            .await
+    }
+
+    pub async fn count_unused_recovery_codes(db: &DbConn, user_id: i32) -> Result<u64, DbErr> {
+        RecoveryCode::find()
+            .filter(recovery_code::Column::UserId.eq(user_id))
+            .filter(recovery_code::Column::Used.is_null())
+            .count(db)
+            .await
+    }
+
+    /// Newest first. Pass `record` to see a single record's history, and
+    /// `page` as `(limit, offset)` to page through the whole log.
+    pub async fn find_audit_log(db: &DbConn, record: Option<(audit_log::RecordType, i32)>, page: Option<(u64, u64)>) -> Result<Vec<AuditDisplay>, DbErr> {
+        let mut select = AuditLog::find().order_by_desc(audit_log::Column::Id);
+        if let Some((record_type, record_id)) = record {
+            select = select
+                .filter(audit_log::Column::RecordType.eq(record_type))
+                .filter(audit_log::Column::RecordId.eq(record_id));
+        }
+        if let Some((limit, offset)) = page {
+            select = select.limit(limit).offset(offset);
+        }
+        let entries = select.all(db).await?;
+        let users: HashMap<i32, String> = Self::find_users(db)
+            .await?
+            .into_iter()
+            .map(|u| (u.id, u.name.unwrap_or(u.username)))
+            .collect();
+        let merged = Self::find_merged_users(db).await?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| AuditDisplay {
+                changes: audit_changes(entry.before.as_deref(), entry.after.as_deref(), &users),
+                actor_name: entry.actor_id.and_then(|id| users.get(&follow_merges(&merged, id)).cloned()),
+                entry,
+            })
+            .collect())
+    }
+
+    /// Who each merged user was merged into, from the merges' audit entries.
+    async fn find_merged_users(db: &DbConn) -> Result<HashMap<i32, i32>, DbErr> {
+        Ok(AuditLog::find()
+            .filter(audit_log::Column::Action.eq(audit_log::Action::Merge))
+            .filter(audit_log::Column::RecordType.eq(audit_log::RecordType::User))
+            .all(db)
+            .await?
+            .into_iter()
+            .filter_map(|entry| {
+                let after: serde_json::Value = serde_json::from_str(entry.after.as_deref()?).ok()?;
+                Some((entry.record_id, i32::try_from(after["merged_into"].as_i64()?).ok()?))
+            })
+            .collect())
+    }
+
+    pub async fn find_import_mappings(db: &DbConn) -> Result<Vec<import_mapping::Model>, DbErr> {
+        ImportMapping::find()
+            .order_by_asc(import_mapping::Column::Name)
+            .all(db)
+            .await
+    }
+    pub async fn find_categories(db: &DbConn) -> Result<Vec<category::Model>, DbErr> {
+        Category::find()
+            .order_by_asc(category::Column::Name)
+            .all(db)
+            .await
+    }
+    pub async fn get_category(db: &DbConn, id: i32) -> Result<Option<category::Model>, DbErr> {
+        Category::find_by_id(id).one(db).await
+    }
+    /// Every tag in use, for suggestions.
+    pub async fn find_tags(db: &DbConn) -> Result<Vec<String>, DbErr> {
+        ExpenditureTag::find()
+            .select_only()
+            .column(expenditure_tag::Column::Tag)
+            .distinct()
+            .order_by_asc(expenditure_tag::Column::Tag)
+            .into_tuple()
+            .all(db)
+            .await
+    }
+    pub async fn get_expenditure_tags<C: ConnectionTrait>(db: &C, expenditure_id: i32) -> Result<Vec<String>, DbErr> {
+        ExpenditureTag::find()
+            .select_only()
+            .column(expenditure_tag::Column::Tag)
+            .filter(expenditure_tag::Column::ExpenditureId.eq(expenditure_id))
+            .order_by_asc(expenditure_tag::Column::Tag)
+            .into_tuple()
+            .all(db)
+            .await
+    }
+    pub async fn get_import_mapping(db: &DbConn, id: i32) -> Result<Option<import_mapping::Model>, DbErr> {
+        ImportMapping::find_by_id(id).one(db).await
+    }
+    /// Which of `fingerprints` have already been imported.
+    pub async fn find_imported_fingerprints(db: &DbConn, fingerprints: Vec<String>) -> Result<HashSet<String>, DbErr> {
+        Ok(ImportedRow::find()
+            .filter(imported_row::Column::Fingerprint.is_in(fingerprints))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|r| r.fingerprint)
+            .collect())
+    }
+    pub async fn find_webhooks(db: &DbConn) -> Result<Vec<webhook::Model>, DbErr> {
+        Webhook::find()
+            .order_by_asc(webhook::Column::Id)
+            .all(db)
+            .await
+    }
+
+    pub async fn get_webhook(db: &DbConn, id: i32) -> Result<Option<webhook::Model>, DbErr> {
+        Webhook::find_by_id(id).one(db).await
+    }
+
+    pub async fn find_webhook_deliveries(db: &DbConn, webhook_id: i32) -> Result<Vec<webhook_delivery::Model>, DbErr> {
+        WebhookDelivery::find()
+            .filter(webhook_delivery::Column::WebhookId.eq(webhook_id))
+            .order_by_desc(webhook_delivery::Column::Id)
+            .limit(100)
+            .all(db)
+            .await
+    }
+
+    /// Oldest first, so events arrive roughly in order.
+    pub async fn find_due_webhook_deliveries(db: &DbConn, now: DateTimeUtc) -> Result<Vec<(webhook_delivery::Model, Option<webhook::Model>)>, DbErr> {
+        WebhookDelivery::find()
+            .filter(webhook_delivery::Column::NextAttempt.lte(now))
+            .find_also_related(Webhook)
+            .filter(webhook::Column::Active.eq(true))
+            .order_by_asc(webhook_delivery::Column::Id)
+            .limit(100)
+            .all(db)
+            .await
+    }
+
+    pub async fn get_debts<C: ConnectionTrait>(db: &C) -> Result<HashMap<i32, Currency>, DbErr> {
         #[derive(DeriveIden)]
         struct TotalSpend;
         #[derive(DeriveIden)]
@@ -299,7 +1022,8 @@ impl Query {
         }
     }
 
-    async fn get_totals_for_date_range(db: &DbConn, user_id: i32, range: impl RangeBounds<NaiveDate>) -> Result<(Currency, Currency), DbErr> {
+    /// Expenditures in `range`, each joined to the user's split of it.
+    fn totals_query(user_id: i32, range: impl RangeBounds<NaiveDate>) -> Select<expenditure::Entity> {
         let query = Expenditure::find()
             .select_only();
         let query = match range.start_bound() {
@@ -312,36 +1036,91 @@ impl Query {
             Bound::Excluded(d) => query.filter(expenditure::Column::Date.lt(*d)),
             Bound::Unbounded => query,
         };
-        let integer = Alias::new("integer");
         query
             .join(JoinType::LeftJoin, expenditure::Relation::Split.def().on_condition(move |_, right| {Expr::col((right, split::Column::UserId)).eq(user_id).into_condition()}))
-            .column_as(Expr::expr(expenditure::Column::Amount.sum()).if_null(0).cast_as(integer.clone()), "total")
-            .column_as(Expr::expr(split::Column::Share.sum()).if_null(0).cast_as(integer.clone()), "mine")
+            .column_as(Expr::expr(expenditure::Column::Amount.sum()).if_null(0).cast_as(Alias::new("integer")), "total")
+            .column_as(Expr::expr(split::Column::Share.sum()).if_null(0).cast_as(Alias::new("integer")), "mine")
+    }
+
+    async fn get_totals_for_date_range(db: &DbConn, user_id: i32, range: impl RangeBounds<NaiveDate>) -> Result<(Currency, Currency), DbErr> {
+        Self::totals_query(user_id, range)
             .into_tuple::<(Currency, Currency)>()
             .one(db)
             .await
             .map(|v| v.unwrap_or((0.into(), 0.into())))
     }
 
-    pub async fn get_totals(db: &DbConn, user_id: i32) -> Result<Totals, DbErr> {
+    /// Like `get_totals_for_date_range`, for each category.
+    pub async fn get_totals_by_category_for_date_range(db: &DbConn, user_id: i32, range: impl RangeBounds<NaiveDate>) -> Result<Vec<CategoryTotal>, DbErr> {
+        let totals = Self::totals_query(user_id, range)
+            .column(expenditure::Column::CategoryId)
+            .group_by(expenditure::Column::CategoryId)
+            .into_tuple::<(Currency, Currency, Option<i32>)>()
+            .all(db)
+            .await?;
+        let categories = Self::find_categories(db).await?;
+        Ok(roll_up_categories(&categories, totals.into_iter().map(|(total, mine, category_id)| (category_id, total, mine)).collect()))
+    }
+
+    /// Totals over `span`, under the given name.
+    pub async fn get_totals_for_span(db: &DbConn, user_id: i32, name: String, span: Span) -> Result<PeriodTotal, DbErr> {
+        Ok(PeriodTotal {
+            total: Self::get_totals_for_date_range(db, user_id, span).await?,
+            name,
+            span,
+        })
+    }
+
+    /// Totals for the household's fiscal year and billing cycles, as set
+    /// up in `periods`.
+    pub async fn get_totals(db: &DbConn, user_id: i32, periods: &Periods) -> Result<Totals, DbErr> {
         let today = Local::now().date_naive();
-        let first_of_month = NaiveDate::from_ymd_opt(today.year(), today.month(), 1).unwrap();
+        let year_to_date_span = periods.fiscal_year(today).onwards();
+        let month_to_date_span = periods.cycle(today).onwards();
+        let last_month_span = periods.cycle(month_to_date_span.from.and_then(|d| d.pred_opt()).expect("cycles have a start"));
+        let mut custom = Vec::new();
+        for period in &periods.custom_periods {
+            custom.push(Self::get_totals_for_span(db, user_id, period.name.clone(), Span::new(Some(period.from), period.to)).await?);
+        }
         Ok(Totals {
             total: Self::get_totals_for_date_range(db, user_id, ..).await?,
             past_year: Self::get_totals_for_date_range(db, user_id, today-Duration::days(365)..).await?,
-            year_to_date: Self::get_totals_for_date_range(db, user_id, NaiveDate::from_yo_opt(today.year(), 1).unwrap()..).await?,
-            month_to_date: Self::get_totals_for_date_range(db, user_id, first_of_month..).await?,
-            last_month: Self::get_totals_for_date_range(db, user_id, first_of_month-Months::new(1)..first_of_month).await?,
+            year_to_date: Self::get_totals_for_date_range(db, user_id, year_to_date_span).await?,
+            month_to_date: Self::get_totals_for_date_range(db, user_id, month_to_date_span).await?,
+            last_month: Self::get_totals_for_date_range(db, user_id, last_month_span).await?,
+            year_to_date_by_category: Self::get_totals_by_category_for_date_range(db, user_id, year_to_date_span).await?,
+            year_to_date_span,
+            month_to_date_span,
+            last_month_span,
+            custom,
         })
     }
 }
 
+/// Each pair is the household's spending and the user's share of it.
+///
+/// The year is the household's fiscal year, and months are its billing
+/// cycles; by default those are calendar years and months.
+#[derive(Serialize, ToSchema)]
 pub struct Totals {
     pub total: (Currency, Currency),
     pub past_year: (Currency, Currency),
     pub year_to_date: (Currency, Currency),
     pub month_to_date: (Currency, Currency),
     pub last_month: (Currency, Currency),
+    pub year_to_date_by_category: Vec<CategoryTotal>,
+    pub year_to_date_span: Span,
+    pub month_to_date_span: Span,
+    pub last_month_span: Span,
+    /// The household's own named periods.
+    pub custom: Vec<PeriodTotal>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PeriodTotal {
+    pub name: String,
+    pub span: Span,
+    pub total: (Currency, Currency),
 }
 
 trait RelationDefExt {
@@ -358,3 +1137,156 @@ impl RelationDefExt for RelationDef {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn audit_changes_show_only_differences() {
+        let users = HashMap::from([(1, "Alice".to_string()), (2, "Bob".to_string())]);
+        let before = r#"{"spender_id":1,"amount":1000,"description":"Rent","date":"2026-10-01","splits":{"1":500,"2":500}}"#;
+        let after = r#"{"spender_id":1,"amount":1000,"description":"Rent","date":"2026-10-01","splits":{"1":700,"2":300}}"#;
+        let changes = audit_changes(Some(before), Some(after), &users);
+        let changes: Vec<_> = changes.iter().map(|c| (c.field.as_str(), c.before.as_deref(), c.after.as_deref())).collect();
+        assert_eq!(changes, vec![
+            ("split: Alice", Some("$5.00"), Some("$7.00")),
+            ("split: Bob", Some("$5.00"), Some("$3.00")),
+        ]);
+    }
+
+    fn categories() -> Vec<category::Model> {
+        let category = |id, name: &str, parent_id| category::Model { id, name: name.to_string(), parent_id };
+        vec![
+            category(1, "Food", None),
+            category(2, "Groceries", Some(1)),
+            category(3, "Eating out", Some(1)),
+            category(4, "Bills", None),
+            category(5, "Orphan", Some(99)),
+        ]
+    }
+
+    #[test]
+    fn category_paths_nest_under_parents() {
+        let paths: Vec<_> = category_paths(&categories()).into_iter().map(|c| (c.path, c.depth)).collect();
+        assert_eq!(paths, vec![
+            ("Bills".to_string(), 0),
+            ("Food".to_string(), 0),
+            ("Food > Eating out".to_string(), 1),
+            ("Food > Groceries".to_string(), 1),
+            ("Orphan".to_string(), 0),
+        ]);
+    }
+
+    #[test]
+    fn category_totals_include_subcategories() {
+        let totals = roll_up_categories(&categories(), vec![
+            (Some(1), 100.into(), 50.into()),
+            (Some(2), 1000.into(), 500.into()),
+            (None, 300.into(), 0.into()),
+        ]);
+        let totals: Vec<_> = totals.iter().map(|t| (t.path.as_str(), i32::from(t.total.0.clone()), i32::from(t.total.1.clone()))).collect();
+        assert_eq!(totals, vec![
+            ("Food", 1100, 550),
+            ("Food > Groceries", 1000, 500),
+            ("Uncategorized", 300, 0),
+        ]);
+    }
+
+    #[test]
+    fn audit_changes_for_delete() {
+        let before = r#"{"debtor_id":2,"creditor_id":1,"amount":250,"description":null,"date":null}"#;
+        let changes = audit_changes(Some(before), None, &HashMap::new());
+        let fields: Vec<_> = changes.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(fields, vec!["amount", "creditor", "date", "debtor", "description"]);
+        assert!(changes.iter().all(|c| c.after.is_none()));
+        assert_eq!(changes[0].before.as_deref(), Some("$2.50"));
+        assert_eq!(changes[1].before.as_deref(), Some("user 1"));
+    }
+
+    #[test]
+    fn cursors_round_trip_through_query_strings() {
+        let cursor = HistoryCursor::new(NaiveDate::from_ymd_opt(2026, 10, 19), 123);
+        assert_eq!(cursor.to_string(), "2026-10-19_123");
+        assert_eq!("2026-10-19_123".parse::<HistoryCursor>(), Ok(cursor));
+        let undated = HistoryCursor::new(None, 7);
+        assert_eq!(undated.to_string().parse::<HistoryCursor>(), Ok(undated));
+        for invalid in ["", "2026-10-19", "2026-10-19_", "10/19/2026_1", "2026-10-19_x"] {
+            assert!(invalid.parse::<HistoryCursor>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    async fn add_user(db: &DbConn) -> i32 {
+        user::ActiveModel {
+            username: Set("alice".to_string()),
+            resident: Set(true),
+            ..Default::default()
+        }
+            .insert(db)
+            .await
+            .unwrap()
+            .id
+    }
+
+    async fn add_expenditure(db: &DbConn, spender_id: i32, date: Option<(i32, u32, u32)>, description: &str) -> i32 {
+        expenditure::ActiveModel {
+            spender_id: Set(spender_id),
+            amount: Set(100.into()),
+            description: Set(Some(description.to_string())),
+            date: Set(date.map(|(y, m, d)| NaiveDate::from_ymd_opt(y, m, d).unwrap())),
+            ..Default::default()
+        }
+            .insert(db)
+            .await
+            .unwrap()
+            .id
+    }
+
+    #[rocket::async_test]
+    async fn pages_pick_up_after_the_cursor_with_undated_records_last() {
+        let db = crate::service::test_db().await;
+        let user_id = add_user(&db).await;
+        let first = add_expenditure(&db, user_id, Some((2026, 10, 1)), "").await;
+        let undated = add_expenditure(&db, user_id, None, "").await;
+        let second = add_expenditure(&db, user_id, Some((2026, 10, 2)), "").await;
+        let third = add_expenditure(&db, user_id, Some((2026, 10, 2)), "").await;
+        let filter = HistoryFilter::default();
+        let mut pages = vec![];
+        let mut after = None;
+        loop {
+            let page = Query::find_expenditures(&db, user_id, &filter, after, Some(2)).await.unwrap();
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = Some(HistoryCursor::new(last.date, last.id));
+            pages.push(page.iter().map(|e| e.id).collect::<Vec<_>>());
+        }
+        assert_eq!(pages, vec![vec![third, second], vec![first, undated]]);
+    }
+
+    #[rocket::async_test]
+    async fn searches_match_wildcards_literally() {
+        let db = crate::service::test_db().await;
+        let user_id = add_user(&db).await;
+        let juice = add_expenditure(&db, user_id, None, "100% Juice").await;
+        add_expenditure(&db, user_id, None, "1000 juice").await;
+        let underscore = add_expenditure(&db, user_id, None, "a_b").await;
+        add_expenditure(&db, user_id, None, "axb").await;
+        let db = &db;
+        let search = |search: &str| {
+            let filter = HistoryFilter { search: Some(search.to_string()), ..Default::default() };
+            async move {
+                Query::find_expenditures(db, user_id, &filter, None, None)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|e| e.id)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(search("%").await, vec![juice]);
+        assert_eq!(search("0% j").await, vec![juice]);
+        assert_eq!(search("_").await, vec![underscore]);
+        assert_eq!(search("JUICE").await.len(), 2);
+    }
+}
@@ -0,0 +1,394 @@
+//! Outbound webhooks.
+//!
+//! `Mutation` queues a delivery for each subscribed webhook in the same
+//! transaction as the change itself, so nothing is sent for changes that are
+//! rolled back and nothing is lost if the app stops before sending. A
+//! background task started at liftoff then POSTs queued deliveries, retrying
+//! failures with exponential backoff.
+//!
+//! Each request carries the event name in `X-BlueChips-Event`, the delivery
+//! ID in `X-BlueChips-Delivery`, and `X-BlueChips-Signature: sha256=<hex>`,
+//! an HMAC-SHA256 of the body keyed with the webhook's secret.
+//!
+//! Webhooks can't point at loopback, link-local or private addresses, so
+//! they can't be used to reach services that are only meant to be seen from
+//! inside the server's network. That's checked when a webhook is created
+//! and again before every delivery, since DNS can change in between. Each
+//! delivery then connects only to the addresses that were checked, so a
+//! name can't switch to a private address between the check and the
+//! connection.
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use rocket::serde::Deserialize;
+use sea_orm::{DatabaseConnection, DbErr};
+use sha2::Sha256;
+
+use crate::entities::{audit_log, webhook, webhook_delivery};
+use crate::service::{Mutation, Query};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    ExpenditureCreated,
+    ExpenditureUpdated,
+    ExpenditureDeleted,
+    TransferCreated,
+    TransferUpdated,
+    TransferDeleted,
+    /// Sent after any of the above, with everyone's current balance.
+    BalanceChanged,
+}
+
+impl Event {
+    pub const ALL: [Event; 7] = [
+        Event::ExpenditureCreated,
+        Event::ExpenditureUpdated,
+        Event::ExpenditureDeleted,
+        Event::TransferCreated,
+        Event::TransferUpdated,
+        Event::TransferDeleted,
+        Event::BalanceChanged,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::ExpenditureCreated => "expenditure.created",
+            Event::ExpenditureUpdated => "expenditure.updated",
+            Event::ExpenditureDeleted => "expenditure.deleted",
+            Event::TransferCreated => "transfer.created",
+            Event::TransferUpdated => "transfer.updated",
+            Event::TransferDeleted => "transfer.deleted",
+            Event::BalanceChanged => "balance.changed",
+        }
+    }
+
+    /// The event for a change recorded in the audit log, if there is one.
+    pub fn for_change(record_type: audit_log::RecordType, action: audit_log::Action) -> Option<Self> {
+        use audit_log::{Action, RecordType};
+        match (record_type, action) {
+            (RecordType::Expenditure, Action::Create) => Some(Event::ExpenditureCreated),
+            (RecordType::Expenditure, Action::Update) => Some(Event::ExpenditureUpdated),
+            (RecordType::Expenditure, Action::Delete) => Some(Event::ExpenditureDeleted),
+            (RecordType::Transfer, Action::Create) => Some(Event::TransferCreated),
+            (RecordType::Transfer, Action::Update) => Some(Event::TransferUpdated),
+            (RecordType::Transfer, Action::Delete) => Some(Event::TransferDeleted),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a webhook's comma-separated `events` list includes `event`.
+pub fn subscribed(events: &str, event: Event) -> bool {
+    let name = event.name();
+    events.split(',').map(str::trim).any(|pattern| {
+        pattern == "*"
+            || pattern == name
+            || pattern.strip_suffix(".*").map_or(false, |prefix| {
+                name.strip_prefix(prefix).map_or(false, |rest| rest.starts_with('.'))
+            })
+    })
+}
+
+pub fn generate_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub webhook_poll_seconds: u64,
+    pub webhook_timeout_seconds: u64,
+    /// Deliveries are abandoned after this many failed attempts.
+    pub webhook_max_attempts: i32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            webhook_poll_seconds: 5,
+            webhook_timeout_seconds: 10,
+            webhook_max_attempts: 8,
+        }
+    }
+}
+
+/// 30 seconds after the first failure, doubling up to six hours.
+fn backoff(attempts: i32) -> chrono::Duration {
+    let seconds = 30i64.saturating_mul(1 << attempts.clamp(1, 20).saturating_sub(1));
+    chrono::Duration::seconds(seconds.min(6 * 60 * 60))
+}
+
+/// Whether `ip` is somewhere on the internet, rather than this machine or
+/// a private network.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            // 0.0.0.0/8 means this network, 100.64.0.0/10 is carrier-grade
+            // NAT, and 240.0.0.0/4 is reserved.
+            let shared = a == 100 && (b & 0xc0) == 64;
+            !(a == 0 || a >= 240 || ip.is_private() || ip.is_loopback() || ip.is_link_local()
+                || ip.is_multicast() || ip.is_documentation() || shared)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            let embedded = |high: u16, low: u16| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+            // IPv4-mapped ::ffff:a.b.c.d and IPv4-compatible ::a.b.c.d, which
+            // also covers :: and ::1.
+            if let Some(v4) = ip.to_ipv4() {
+                return is_public(v4.into());
+            }
+            // 6to4 carries an IPv4 address in 2002::/16.
+            if segments[0] == 0x2002 {
+                return is_public(embedded(segments[1], segments[2]).into());
+            }
+            // NAT64 (64:ff9b::/96 and the local-use 64:ff9b:1::/48) goes
+            // through a translator that could be anywhere on the network.
+            let nat64 = segments[0] == 0x64 && segments[1] == 0xff9b;
+            // fc00::/7 is unique local, fe80::/10 link-local, ff00::/8
+            // multicast.
+            !(nat64 || (segments[0] & 0xfe00) == 0xfc00 || (segments[0] & 0xffc0) == 0xfe80 || ip.is_multicast())
+        }
+    }
+}
+
+/// Refuse a webhook URL unless every address its host resolves to is
+/// public, returning the addresses that were checked.
+pub async fn check_target(url: &str) -> Result<Vec<SocketAddr>, String> {
+    let url = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("only http:// and https:// URLs are allowed".to_string());
+    }
+    let host = url.host_str().ok_or("the URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addresses: Vec<IpAddr> = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => vec![ip],
+        Err(_) => rocket::tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|e| format!("{} doesn't resolve: {}", host, e))?
+            .map(|address| address.ip())
+            .collect(),
+    };
+    if addresses.is_empty() {
+        return Err(format!("{} doesn't resolve", host));
+    }
+    match addresses.iter().find(|ip| !is_public(**ip)) {
+        Some(ip) => Err(format!("{} is a private address", ip)),
+        None => Ok(addresses.into_iter().map(|ip| SocketAddr::new(ip, port)).collect()),
+    }
+}
+
+/// A client for one delivery to `url`, which only connects to `addresses`.
+fn client(config: &Config, url: &str, addresses: &[SocketAddr]) -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.webhook_timeout_seconds))
+        // A redirect could lead anywhere, including places `check_target`
+        // would have refused.
+        .redirect(reqwest::redirect::Policy::none());
+    if let Some(host) = reqwest::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string)) {
+        builder = builder.resolve_to_addrs(&host, addresses);
+    }
+    builder.build()
+}
+
+/// Make one attempt, returning the status code if the receiver accepted it.
+pub async fn send(client: &reqwest::Client, webhook: &webhook::Model, delivery: &webhook_delivery::Model) -> Result<u16, (Option<u16>, String)> {
+    let response = client
+        .post(&webhook.url)
+        .header("Content-Type", "application/json")
+        .header("User-Agent", "BlueChips-Webhook")
+        .header("X-BlueChips-Event", &delivery.event)
+        .header("X-BlueChips-Delivery", delivery.id.to_string())
+        .header("X-BlueChips-Signature", sign(&webhook.secret, &delivery.payload))
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|e| (None, e.to_string()))?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(status.as_u16())
+    } else {
+        Err((Some(status.as_u16()), format!("receiver answered {}", status)))
+    }
+}
+
+/// Attempt every delivery that is due. Returns how many were attempted.
+pub async fn run_due(db: &DatabaseConnection, config: &Config) -> Result<usize, DbErr> {
+    let due = Query::find_due_webhook_deliveries(db, chrono::Utc::now()).await?;
+    let count = due.len();
+    for (delivery, webhook) in due {
+        let Some(webhook) = webhook else { continue };
+        let attempts = delivery.attempts + 1;
+        let result = match check_target(&webhook.url).await {
+            Ok(addresses) => match client(config, &webhook.url, &addresses) {
+                Ok(client) => send(&client, &webhook, &delivery).await,
+                Err(e) => Err((None, e.to_string())),
+            },
+            Err(e) => Err((None, format!("refused to send: {}", e))),
+        };
+        let (status_code, error, next_attempt) = match result {
+            Ok(status) => (Some(status), None, None),
+            Err((status, error)) => {
+                warn!("Webhook delivery {} to {} failed: {}", delivery.id, webhook.url, error);
+                let next_attempt = (attempts < config.webhook_max_attempts)
+                    .then(|| chrono::Utc::now() + backoff(attempts));
+                (status, Some(error), next_attempt)
+            }
+        };
+        Mutation::record_webhook_attempt(db, delivery.id, attempts, status_code.map(i32::from), error, next_attempt).await?;
+    }
+    Ok(count)
+}
+
+/// Start the delivery loop in the background.
+pub fn spawn(db: DatabaseConnection, config: Config) {
+    rocket::tokio::spawn(async move {
+        loop {
+            if let Err(e) = run_due(&db, &config).await {
+                error!("Webhook delivery failed: {:?}", e);
+            }
+            rocket::tokio::time::sleep(Duration::from_secs(config.webhook_poll_seconds)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn subscriptions() {
+        assert!(subscribed("*", Event::BalanceChanged));
+        assert!(subscribed("expenditure.created, transfer.*", Event::TransferDeleted));
+        assert!(subscribed("expenditure.created, transfer.*", Event::ExpenditureCreated));
+        assert!(!subscribed("expenditure.created, transfer.*", Event::ExpenditureDeleted));
+        assert!(!subscribed("trans.*", Event::TransferCreated));
+    }
+
+    #[rocket::async_test]
+    async fn private_targets_are_refused() {
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://10.1.2.3/hook",
+            "http://192.168.0.10/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+            "http://[::127.0.0.1]/hook",
+            "http://[::a00:1]/hook",
+            "http://[64:ff9b::5db8:d822]/hook",
+            "http://[2002:c0a8:1::1]/hook",
+            "http://[ff02::1]/hook",
+            "http://0.1.2.3/hook",
+            "http://224.0.0.1/hook",
+            "ftp://93.184.216.34/hook",
+        ] {
+            assert!(check_target(url).await.is_err(), "{} was allowed", url);
+        }
+        assert_eq!(check_target("https://93.184.216.34/hook").await, Ok(vec!["93.184.216.34:443".parse().unwrap()]));
+        assert!(check_target("https://[2606:2800:220:1::]/hook").await.is_ok());
+        assert!(check_target("https://[2002:5db8:d822::1]/hook").await.is_ok());
+    }
+
+    #[test]
+    fn backoff_grows_and_caps() {
+        assert_eq!(backoff(1), chrono::Duration::seconds(30));
+        assert_eq!(backoff(3), chrono::Duration::seconds(120));
+        assert_eq!(backoff(30), chrono::Duration::hours(6));
+    }
+
+    /// Accept one request on a local socket and return it as text.
+    fn receiver(status: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (url, handle)
+    }
+
+    fn fixtures(url: String) -> (webhook::Model, webhook_delivery::Model) {
+        let now = chrono::Utc::now();
+        (
+            webhook::Model {
+                id: 1,
+                url,
+                secret: "s3cret".to_string(),
+                events: "*".to_string(),
+                active: true,
+                created: now,
+            },
+            webhook_delivery::Model {
+                id: 7,
+                webhook_id: 1,
+                event: "expenditure.created".to_string(),
+                payload: r#"{"event":"expenditure.created","data":{"id":3}}"#.to_string(),
+                created: now,
+                attempts: 0,
+                status_code: None,
+                error: None,
+                next_attempt: Some(now),
+                delivered: None,
+            },
+        )
+    }
+
+    #[rocket::async_test]
+    async fn delivers_signed_payload() {
+        let (url, handle) = receiver("204 No Content");
+        let (webhook, delivery) = fixtures(url);
+        let status = send(&reqwest::Client::new(), &webhook, &delivery).await.unwrap();
+        assert_eq!(status, 204);
+        let request = handle.join().unwrap();
+        let lower = request.to_lowercase();
+        assert!(request.starts_with("POST /hook "));
+        assert!(lower.contains("x-bluechips-event: expenditure.created"));
+        assert!(lower.contains("x-bluechips-delivery: 7"));
+        assert!(lower.contains(&format!("x-bluechips-signature: {}", sign("s3cret", &delivery.payload))));
+        assert!(request.ends_with(&delivery.payload));
+    }
+
+    #[rocket::async_test]
+    async fn reports_receiver_errors() {
+        let (url, handle) = receiver("500 Internal Server Error");
+        let (webhook, delivery) = fixtures(url);
+        let (status, _) = send(&reqwest::Client::new(), &webhook, &delivery).await.unwrap_err();
+        assert_eq!(status, Some(500));
+        handle.join().unwrap();
+    }
+}